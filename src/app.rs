@@ -1,76 +1,1397 @@
 #![deny(unused_must_use)]
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::error::Error;
+use std::ffi::OsString;
 use std::fmt::{Debug, Formatter};
-use clap::{arg, Parser};
-use dicexp::{DiceBag, new_simple_rng, simple_rng};
-use rand;
-
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use dicexp::{BreakdownPart, DetailedRoll, DiceBag, DiceRoll, Distribution, RolledDice, new_simple_rng, simple_rng};
+use serde::{Deserialize, Serialize};
 
 /// CLI arguments struct (used with CLI parser module clap)
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_override_self = true)]
 pub struct Args {
 	/// Show the average result for each dice expression
-	#[arg(short='a', long="average")]
+	#[arg(short='a', long="average", global=true)]
 	show_average: bool,
 	/// Show the minimum and maximum possible result for each dice expression
-	#[arg(short='r', long="range")]
+	#[arg(short='r', long="range", global=true)]
 	show_range: bool,
+	/// Also show the average rounded down, the way D&D stat blocks show it (eg "2d8+2" => 11)
+	#[arg(long="floor-average", global=true)]
+	floor_average: bool,
 	/// Show only the roll results and nothing more (incompatible with -a/--average and -r/--range)
-	#[arg(short='q', long="quiet")]
+	#[arg(short='q', long="quiet", global=true)]
 	quiet: bool,
+	/// Show the individual dice rolled for each dice expression (eg "3d6+2 => 13 [4, 6, 1] + 2")
+	#[arg(short='v', long="verbose", global=true)]
+	verbose: bool,
+	/// Show the fully worked out arithmetic for each dice expression, with each die replaced by
+	/// the number rolled (eg "4(2d6+3) => 4*((5+2)+3) = 40")
+	#[arg(long="breakdown", global=true)]
+	breakdown: bool,
+	/// Print each result as a single-line JSON object (expression, total, min, max, average, and
+	/// the individual dice rolled)
+	#[arg(long="json", global=true)]
+	json: bool,
+	/// Print the results as comma-separated values (CSV), starting with a header row
+	#[arg(long="csv", global=true)]
+	csv: bool,
+	/// Print the results as a markdown table (expression, total, range, and average)
+	#[arg(long="markdown", global=true)]
+	markdown: bool,
+	/// Output format (--json, --csv, and --markdown are shortcuts for this option, and take
+	/// precedence over it)
+	#[arg(long="format", value_enum, default_value_t=Format::Text, env="DICEXP_FORMAT", global=true)]
+	format: Format,
+	/// When to color the output (auto colors the output when printing to a terminal, unless the
+	/// NO_COLOR environment variable is set)
+	#[arg(long="color", value_enum, default_value_t=ColorChoice::Auto, env="DICEXP_COLOR", global=true)]
+	color: ColorChoice,
+	/// Number of times to evaluate each dice expression
+	#[arg(short='n', long="count", default_value_t=1, value_parser=clap::value_parser!(u32).range(1..), global=true)]
+	count: u32,
+	/// Show statistics for each dice expression (mean, median, mode, standard deviation, and the
+	/// chance of rolling at least the rolled total), worked out exactly when possible and
+	/// estimated from simulated rolls otherwise
+	#[arg(long="stats", global=true)]
+	stats: bool,
+	/// Difficulty check: show SUCCESS or FAILURE for each roll depending on whether it meets or
+	/// beats this target number, and exit with an error code if any roll fails
+	#[arg(long="dc", value_name="TARGET", allow_negative_numbers=true, global=true)]
+	dc: Option<i64>,
+	/// Don't roll the dice, only show the minimum possible result for each dice expression
+	#[arg(long="min-only", global=true)]
+	min_only: bool,
+	/// Don't roll the dice, only show the maximum possible result for each dice expression
+	#[arg(long="max-only", global=true)]
+	max_only: bool,
+	/// Don't roll the dice, only show the average result for each dice expression
+	#[arg(long="average-only", global=true)]
+	average_only: bool,
+	/// Also show the sum of all of the rolls
+	#[arg(long="sum", global=true)]
+	sum: bool,
+	/// Instead of a single roll, roll each dice expression this many times and show the observed
+	/// distribution of results, mean, and standard deviation
+	#[arg(long="simulate", value_name="SAMPLES", value_parser=clap::value_parser!(u64).range(1..), global=true)]
+	simulate: Option<u64>,
 	/// Optional seed for random number generator
-	#[arg(short='s', long="seed")]
+	#[arg(short='s', long="seed", env="DICEXP_SEED", global=true)]
 	seed: Option<u64>,
-	/// One or more RPG dice notation expressions to evaluate (eg "1d20+3")
-	dice_expressions: Vec<String>
+	/// Show the seed for the random number generator (a random seed is generated if -s/--seed
+	/// is not given), so that the same rolls can be reproduced later with -s/--seed
+	#[arg(long="show-seed", global=true)]
+	show_seed: bool,
+	/// Append every roll (timestamp, expression, seed, dice rolled, and total) to this file as
+	/// JSON lines, as a record of the session
+	#[arg(long="log", value_name="FILE", global=true)]
+	log: Option<PathBuf>,
+	/// Load dice expressions from a file (one per line, # starts a comment), evaluated after any
+	/// expressions given as arguments
+	#[arg(short='f', long="file", global=true)]
+	file: Option<PathBuf>,
+	/// One or more RPG dice notation expressions to evaluate (eg "1d20+3") or aliases from the
+	/// config file, or - to read expressions from standard input (one per line)
+	dice_expressions: Vec<String>,
+	/// Aliases from the config file
+	#[arg(skip)]
+	aliases: HashMap<String, String>,
+	#[command(subcommand)]
+	command: Option<Command>
+}
+
+/// CLI subcommands
+#[derive(Subcommand, Debug)]
+pub enum Command {
+	/// Roll actions from a character sheet file (TOML or JSON) that maps named actions to dice
+	/// expressions, which may use the sheet's stats as {variables}
+	Sheet {
+		/// Character sheet file (.toml or .json)
+		#[arg(value_name="FILE")]
+		sheet_file: PathBuf,
+		/// Names of the actions to roll (lists the sheet's actions if none are given)
+		actions: Vec<String>
+	},
+	/// Roll initiative for each combatant and list them from highest to lowest (ties go to the
+	/// combatant with the better average roll, then to the winner of a d20 roll-off)
+	Init {
+		/// Combatants as NAME:EXPRESSION (eg "Goblin:1d20+2")
+		#[arg(required=true)]
+		combatants: Vec<String>
+	},
+	/// Compare the statistics of two dice expressions, including how likely each is to roll higher
+	Compare {
+		/// The first dice expression
+		expression_a: String,
+		/// The second dice expression
+		expression_b: String
+	},
+	/// Check a posted roll by rolling the expression again with the seed it was rolled with (the
+	/// roll must be the first one made with the seed, eg with "dicexp -s SEED EXPRESSION"), and
+	/// exit with an error code if it does not match. Only the total is checked unless the result
+	/// of every die is given too.
+	Verify {
+		/// The seed that the roll was made with
+		seed: u64,
+		/// The dice expression that was rolled
+		expression: String,
+		/// The total that was rolled
+		#[arg(allow_negative_numbers=true)]
+		total: i64,
+		/// The result of every die that was rolled, in the order they were rolled (eg as shown by
+		/// --json), to check them as well as the total
+		dice: Vec<u32>
+	},
+	/// Roll on a random table file, where each line maps a range of rolls to a result (eg
+	/// "01-50: gold")
+	Table {
+		/// The random table file
+		#[arg(value_name="FILE")]
+		table_file: PathBuf
+	},
+	/// Print a shell completion script
+	#[command(hide=true)]
+	Completions {
+		/// The shell to generate completions for
+		shell: Shell
+	}
+}
+
+/// Options for --color
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+	Auto, Always, Never
+}
+
+/// Options for --format
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+	Text, Json, Csv, Markdown
+}
+
+impl Args {
+	/// Turns --format into the matching --json, --csv, or --markdown flag, unless one of those
+	/// flags was given
+	fn apply_format(&mut self) {
+		if self.json || self.csv || self.markdown {
+			return;
+		}
+		match self.format {
+			Format::Text => {}
+			Format::Json => self.json = true,
+			Format::Csv => self.csv = true,
+			Format::Markdown => self.markdown = true
+		}
+	}
 }
 
-/// Entry point for the CLI app
-pub fn main() -> Result<(), Box<dyn Error>> {
-	let args = Args::parse();
+/// Entry point for the CLI app, which exits with an error code if a --dc check failed
+pub fn main() -> Result<ExitCode, Box<dyn Error>> {
+	let config = Config::load()?;
+	let mut args = Args::parse_from(config.with_default_args(env::args_os()));
+	args.aliases = config.aliases;
+	args.apply_format();
 	sanity_check(&args)?;
-	for output in run(args)? {
-		println!("{}", output);
+	if args.log.is_some() {
+		// the log needs the seed so that the session can be replayed
+		args.seed.get_or_insert_with(rand::random);
 	}
-	Ok(())
+	// before any subcommand, so that the seed is shown for the rolls of init and table too
+	if args.show_seed {
+		let seed = *args.seed.get_or_insert_with(rand::random);
+		if args.json {
+			println!("{{\"seed\":{}}}", seed);
+		} else if args.csv || args.markdown {
+			// keep the table output clean
+			eprintln!("Seed => {}", seed);
+		} else {
+			println!("Seed => {}", seed);
+		}
+	}
+	match args.command.take() {
+		None => {}
+		Some(Command::Sheet{sheet_file, actions}) => {
+			let sheet = Sheet::load(&sheet_file)?;
+			if actions.is_empty() {
+				for (name, exp) in &sheet.actions {
+					println!("{}: {}", name, exp);
+				}
+				return Ok(ExitCode::SUCCESS);
+			}
+			for action in &actions {
+				args.dice_expressions.push(format!("{}={}", action, sheet.expression(action)?));
+			}
+		}
+		Some(Command::Init{combatants}) => {
+			for line in roll_initiative(&combatants, &args)? {
+				println!("{}", line);
+			}
+			return Ok(ExitCode::SUCCESS);
+		}
+		Some(Command::Compare{expression_a, expression_b}) => {
+			for line in compare(&expression_a, &expression_b, &args)? {
+				println!("{}", line);
+			}
+			return Ok(ExitCode::SUCCESS);
+		}
+		Some(Command::Verify{seed, expression, total, dice}) => {
+			let (line, valid) = verify(seed, &expression, total, &dice, &args)?;
+			println!("{}", line);
+			return Ok(if valid {ExitCode::SUCCESS} else {ExitCode::FAILURE});
+		}
+		Some(Command::Table{table_file}) => {
+			let table = RandomTable::load(&table_file)?;
+			let dir = table_file.parent().unwrap_or(Path::new("."));
+			let mut dice = new_dice_bag(&args);
+			for _ in 0..args.count {
+				let (roll, result) = table.roll(&mut dice, dir, 0)?;
+				if args.json {
+					println!("{}", serde_json::to_string(&JsonTableRoll{table: &table_file, roll, result: &result})?);
+				} else if args.quiet {
+					println!("{}", result);
+				} else {
+					println!("{} => {}: {}", table.dice, paint(format!("{}", roll).as_str(), BOLD, use_color(&args)), result);
+				}
+			}
+			return Ok(ExitCode::SUCCESS);
+		}
+		Some(Command::Completions{shell}) => {
+			clap_complete::generate(shell, &mut Args::command(), "dicexp", &mut io::stdout());
+			return Ok(ExitCode::SUCCESS);
+		}
+	}
+	let missed_dc = if reads_stdin(&args) {
+		run_stdin(args)?
+	} else {
+		if let Some(path) = &args.file {
+			let expressions = load_expressions(path)?;
+			args.dice_expressions.extend(expressions);
+		}
+		let expressions = args.dice_expressions.iter().map(|exp| Ok(exp.clone()));
+		run_with(&args, expressions, &mut print_flushed)?
+	};
+	Ok(if missed_dc {ExitCode::FAILURE} else {ExitCode::SUCCESS})
+}
+
+/// Runs the program, return a list of results for each expression and whether any of them
+/// failed the --dc check
+pub fn run(args: Args) -> Result<(Vec<String>, bool), Box<dyn Error>>  {
+	let mut results: Vec<String> = Vec::with_capacity(args.dice_expressions.len() * args.count as usize + 1);
+	let expressions = args.dice_expressions.iter().map(|exp| Ok(exp.clone()));
+	let missed_dc = run_with(&args, expressions, &mut |line| {
+		results.push(line);
+		Ok(())
+	})?;
+	Ok((results, missed_dc))
+}
+
+/// Runs the program on expressions read from standard input (one per line), printing each
+/// result as soon as its line has been evaluated (so that dicexp can sit in the middle of a
+/// pipeline), and returns whether any of them failed the --dc check
+pub fn run_stdin(args: Args) -> Result<bool, Box<dyn Error>> {
+	let expressions = io::stdin().lock().lines().filter_map(|line| match line {
+		Ok(line) => strip_comment(line.as_str()).map(|exp| Ok(exp.to_string())),
+		Err(e) => Some(Err(e))
+	});
+	run_with(&args, expressions, &mut print_flushed)
+}
+
+/// Evaluates each expression in turn, passing every line of output to `emit` as soon as it is
+/// ready, and returns whether any of them failed the --dc check
+fn run_with<I>(args: &Args, expressions: I, emit: &mut dyn FnMut(String) -> io::Result<()>) -> Result<bool, Box<dyn Error>>
+		where I: IntoIterator<Item=io::Result<String>> {
+	let mut dice = new_dice_bag(args);
+	let mut log = SessionLog::open(args)?;
+	let mut missed_dc = false;
+	let mut sum = 0i64;
+	if let Some(header) = header(args) {
+		emit(header)?;
+	}
+	for exp in expressions {
+		let exp = exp?;
+		let (label, exp) = split_label(exp.as_str());
+		for exp in expand_alias(&args.aliases, exp) {
+			for _ in 0..args.count {
+				let evaluation = evaluate(&mut dice, label, exp, args)?;
+				log.record(label, exp, &evaluation)?;
+				missed_dc |= evaluation.missed_dc;
+				sum += evaluation.total;
+				emit(evaluation.text)?;
+			}
+		}
+	}
+	if let Some(footer) = footer(args, sum) {
+		emit(footer)?;
+	}
+	Ok(missed_dc)
+}
+
+/// Prints a line of output and flushes it right away, rather than waiting for more output
+fn print_flushed(line: String) -> io::Result<()> {
+	let mut stdout = io::stdout().lock();
+	writeln!(stdout, "{}", line)?;
+	stdout.flush()
+}
+
+/// Splits a labeled expression (eg "attack=1d20+7") into its label and expression. Only a bare
+/// name (like a variable name) is a label, so anything else is left whole for the parser to
+/// report (eg the '=' of "1d20=5"), and comparisons (eg "1d20>=15") are not mistaken for labels.
+pub fn split_label(arg: &str) -> (Option<&str>, &str) {
+	if let Some((label, exp)) = arg.split_once('=') {
+		let label = label.trim();
+		if !exp.starts_with('=') && dicexp::is_variable_name(label) {
+			return (Some(label), exp.trim());
+		}
+	}
+	(None, arg)
+}
+
+/// User configuration, loaded from `~/.config/dicexp/config.toml` (or from the file named by the
+/// `DICEXP_CONFIG` environment variable)
+///
+/// # Example
+/// ```toml
+/// # added in front of the arguments of every dicexp command
+/// default_args = ["--average", "--range"]
+///
+/// [aliases]
+/// sneak = "1d20+8; 3d6+4d6"
+/// fireball = "8d6"
+/// ```
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	/// Arguments to put in front of the command line arguments
+	pub default_args: Vec<String>,
+	/// Named dice expressions, where one alias can hold several expressions separated by ;
+	pub aliases: HashMap<String, String>
+}
+
+impl Config {
+	/// Loads the user configuration file, or returns an empty configuration if there is none
+	pub fn load() -> Result<Self, Box<dyn Error>> {
+		let path = match config_path() {
+			None => return Ok(Config::default()),
+			Some(path) => path
+		};
+		match fs::read_to_string(&path) {
+			Ok(text) => Config::parse(text.as_str()).map_err(|e| InvalidArgumentError{
+				msg: format!("Invalid config file {}: {}", path.display(), e)
+			}.into()),
+			// a missing default config file is fine, but a missing DICEXP_CONFIG file is not
+			Err(e) if e.kind() == io::ErrorKind::NotFound && env::var_os("DICEXP_CONFIG").is_none() => Ok(Config::default()),
+			Err(e) => Err(InvalidArgumentError{
+				msg: format!("Failed to read config file {}: {}", path.display(), e)
+			}.into())
+		}
+	}
+
+	/// Parses the contents of a configuration file
+	pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+		toml::from_str(text)
+	}
+
+	/// Inserts the default arguments after the program name (the first argument)
+	pub fn with_default_args<I>(&self, args: I) -> Vec<OsString> where I: IntoIterator<Item=OsString> {
+		let mut args = args.into_iter();
+		let mut all_args: Vec<OsString> = Vec::new();
+		all_args.extend(args.next());
+		all_args.extend(self.default_args.iter().map(OsString::from));
+		all_args.extend(args);
+		all_args
+	}
+}
+
+/// Expands an alias into the dice expressions it stands for (an expression that is not an alias
+/// just expands to itself)
+pub fn expand_alias<'a>(aliases: &'a HashMap<String, String>, exp: &'a str) -> Vec<&'a str> {
+	match aliases.get(exp) {
+		None => vec![exp],
+		Some(expressions) => expressions.split(';').map(str::trim).filter(|e| !e.is_empty()).collect()
+	}
+}
+
+fn config_path() -> Option<PathBuf> {
+	if let Some(path) = env::var_os("DICEXP_CONFIG") {
+		return Some(PathBuf::from(path));
+	}
+	let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+		Some(dir) => PathBuf::from(dir),
+		None => PathBuf::from(env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?).join(".config")
+	};
+	Some(config_dir.join("dicexp").join("config.toml"))
+}
+
+/// A combatant's initiative roll
+#[derive(Serialize)]
+struct Initiative<'a> {
+	name: &'a str,
+	expression: &'a str,
+	total: i64,
+	#[serde(skip)]
+	average: f64,
+	#[serde(skip)]
+	tie_breaker: i64
+}
+
+/// Checks a posted total by rolling the expression again with the given seed, returning the
+/// formatted output and whether the total is correct
+fn verify(seed: u64, exp: &str, total: i64, dice: &[u32], args: &Args) -> Result<(String, bool), Box<dyn Error>> {
+	let (label, exp) = split_label(exp);
+	let detailed = DiceBag::new(simple_rng(seed)).eval_detailed(exp)?;
+	let roll = detailed.roll;
+	let rolled: Vec<u32> = detailed.dice.iter().flat_map(|dice| dice.rolls.iter().copied()).collect();
+	// the dice are only checked if they were given
+	let valid = roll.total == total && (dice.is_empty() || dice == rolled.as_slice());
+	if args.json {
+		let (claimed_dice, dice) = match dice.is_empty() {
+			true => (None, None),
+			false => (Some(dice), Some(rolled))
+		};
+		return Ok((serde_json::to_string(&JsonVerification{label, expression: exp, seed, claimed: total, claimed_dice, roll, dice, valid})?, valid));
+	}
+	let color = use_color(args);
+	let name = label.unwrap_or(exp);
+	// the dice are shown the way they were given, eg "14 [9]"
+	let with_dice = |total: i64, dice: &[u32]| match dice.is_empty() {
+		true => total.to_string(),
+		false => format!("{} {:?}", total, dice)
+	};
+	let line = match valid {
+		true => format!("{} {} => {} (seed {})", paint("VALID", GREEN, color), name, with_dice(total, dice), seed),
+		false => format!("{} {} => {} (seed {} rolls {})", paint("INVALID", RED, color), name, with_dice(total, dice), seed,
+			with_dice(roll.total, if dice.is_empty() {&[]} else {&rolled}))
+	};
+	Ok((line, valid))
+}
+
+/// The result of checking a posted roll, for JSON output
+#[derive(Serialize)]
+struct JsonVerification<'a> {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	label: Option<&'a str>,
+	expression: &'a str,
+	seed: u64,
+	claimed: i64,
+	/// the claimed result of every die, if given
+	#[serde(skip_serializing_if = "Option::is_none")]
+	claimed_dice: Option<&'a [u32]>,
+	#[serde(flatten)]
+	roll: DiceRoll,
+	/// the result of every die rolled with the seed, if the claimed dice were given
+	#[serde(skip_serializing_if = "Option::is_none")]
+	dice: Option<Vec<u32>>,
+	valid: bool
 }
 
-/// Runs the program, return a list of results for each expression
-pub fn run(args: Args) -> Result<Vec<String>, Box<dyn Error>>  {
-	let mut dice: DiceBag<rand::rngs::StdRng>;
-	let mut results: Vec<String> = Vec::with_capacity(args.dice_expressions.len());
+/// Rolls initiative for each "NAME:EXPRESSION" combatant, returning the formatted output in
+/// initiative order
+fn roll_initiative<'a>(combatants: &'a [String], args: &Args) -> Result<Vec<String>, Box<dyn Error>> {
+	let mut dice = new_dice_bag(args);
+	let mut order: Vec<Initiative<'a>> = Vec::with_capacity(combatants.len());
+	for combatant in combatants {
+		let (name, exp) = combatant.split_once(':').ok_or_else(|| InvalidArgumentError{
+			msg: format!("Invalid combatant '{}', expected NAME:EXPRESSION (eg \"Goblin:1d20+2\")", combatant)
+		})?;
+		let roll = dice.eval(exp)?;
+		order.push(Initiative{
+			name: name.trim(), expression: exp.trim(), total: roll.total, average: roll.average,
+			tie_breaker: dice.roll(1, 20, 0)
+		});
+	}
+	order.sort_by(|a, b| b.total.cmp(&a.total)
+		.then(b.average.total_cmp(&a.average))
+		.then(b.tie_breaker.cmp(&a.tie_breaker)));
+	if args.json {
+		return order.iter().map(|init| serde_json::to_string(init).map_err(|e| e.into())).collect();
+	}
+	let color = use_color(args);
+	Ok(order.iter().enumerate().map(|(i, init)| format!("{}. {}: {} ({})",
+		i + 1, init.name, paint(format!("{}", init.total).as_str(), BOLD, color), init.expression
+	)).collect())
+}
+
+/// The statistics of one of the expressions being compared
+#[derive(Serialize)]
+struct ComparedExpression<'a> {
+	expression: &'a str,
+	average: f64,
+	min: i64,
+	max: i64,
+	std_dev: f64,
+	/// whether the distribution was worked out exactly, rather than estimated from sampled rolls
+	exact: bool
+}
+
+/// The result of comparing two dice expressions, for JSON output
+#[derive(Serialize)]
+struct Comparison<'a> {
+	a: ComparedExpression<'a>,
+	b: ComparedExpression<'a>,
+	a_greater: f64,
+	b_greater: f64,
+	tie: f64
+}
+
+/// Compares the statistics of two dice expressions, returning the formatted output
+fn compare(exp_a: &str, exp_b: &str, args: &Args) -> Result<Vec<String>, Box<dyn Error>> {
+	let mut dice = new_dice_bag(args);
+	let (dist_a, exact_a) = stats_distribution(&dice, exp_a, args)?;
+	let (dist_b, exact_b) = stats_distribution(&dice, exp_b, args)?;
+	let (a_greater, b_greater) = (dist_a.prob_greater_than(&dist_b), dist_b.prob_greater_than(&dist_a));
+	let comparison = Comparison{
+		a: ComparedExpression{expression: exp_a, average: dice.eval_ave(exp_a)?, min: dice.eval_min(exp_a)?,
+			max: dice.eval_max(exp_a)?, std_dev: dist_a.std_dev(), exact: exact_a},
+		b: ComparedExpression{expression: exp_b, average: dice.eval_ave(exp_b)?, min: dice.eval_min(exp_b)?,
+			max: dice.eval_max(exp_b)?, std_dev: dist_b.std_dev(), exact: exact_b},
+		a_greater, b_greater, tie: (1. - a_greater - b_greater).max(0.)
+	};
+	if args.json {
+		return Ok(vec![serde_json::to_string(&comparison)?]);
+	}
+	let color = use_color(args);
+	let mut lines: Vec<String> = [&comparison.a, &comparison.b].iter().map(|stats| format!(
+		"{}: average {}, range {} to {}, std. dev. {:.2}{}",
+		stats.expression, paint(format!("{}", stats.average).as_str(), BOLD, color), stats.min, stats.max, stats.std_dev,
+		if stats.exact {String::new()} else {format!(" (estimated from {} rolls)", STATS_SAMPLES)}
+	)).collect();
+	lines.push(format!("{} > {}: {:.1}%", exp_a, exp_b, 100. * comparison.a_greater));
+	lines.push(format!("{} > {}: {:.1}%", exp_b, exp_a, 100. * comparison.b_greater));
+	lines.push(format!("tie: {:.1}%", 100. * comparison.tie));
+	Ok(lines)
+}
+
+/// A character sheet, which maps named actions to dice expressions, where the expressions can
+/// refer to the character's stats as {variables}
+///
+/// # Example
+/// ```toml
+/// [stats]
+/// str = 4
+/// prof = 3
+///
+/// [actions]
+/// attack = "1d20+{str}+{prof}"
+/// damage = "1d8+{str}"
+/// ```
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sheet {
+	/// Named character stats
+	pub stats: HashMap<String, i64>,
+	/// Named dice expressions (sorted by name)
+	pub actions: BTreeMap<String, String>
+}
+
+impl Sheet {
+	/// Loads a character sheet from a JSON file (.json) or TOML file (anything else)
+	pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+		let text = fs::read_to_string(path).map_err(|e| InvalidArgumentError{
+			msg: format!("Failed to read character sheet {}: {}", path.display(), e)
+		})?;
+		let sheet: Result<Sheet, Box<dyn Error>> = match path.extension().and_then(|ext| ext.to_str()) {
+			Some(ext) if ext.eq_ignore_ascii_case("json") => serde_json::from_str(text.as_str()).map_err(|e| e.into()),
+			_ => toml::from_str(text.as_str()).map_err(|e| e.into())
+		};
+		sheet.map_err(|e| InvalidArgumentError{
+			msg: format!("Invalid character sheet {}: {}", path.display(), e)
+		}.into())
+	}
+
+	/// Returns the dice expression for the named action, with the stats filled in
+	pub fn expression(&self, action: &str) -> Result<String, InvalidArgumentError> {
+		let exp = self.actions.get(action).ok_or_else(|| InvalidArgumentError{
+			msg: format!("No action named '{}' in character sheet", action)
+		})?;
+		// the action name is shown as the label of its roll
+		if !dicexp::is_variable_name(action) {
+			return Err(InvalidArgumentError{
+				msg: format!("Action name '{}' must be made of letters, digits, and underscores", action)
+			});
+		}
+		dicexp::fill_template(exp, &self.stats).map_err(|e| InvalidArgumentError{
+			msg: format!("{} in action '{}'", e.msg.unwrap_or_default(), action)
+		})
+	}
+}
+
+/// A random table, which maps ranges of a dice roll to results. Results can contain dice
+/// expressions to roll in {braces} and references to other tables in [brackets].
+///
+/// # Example
+/// ```text
+/// # treasure.txt
+/// dice: 1d100
+/// 01-50: {2d6*10} gold pieces
+/// 51-90: a gem worth {1d4*50} gold pieces
+/// 91-00: [magic_items.txt]
+/// ```
+#[derive(Default, Debug)]
+pub struct RandomTable {
+	/// The dice expression to roll on the table (by default, 1dN where N is the highest roll on
+	/// the table)
+	pub dice: String,
+	/// The lowest roll, highest roll, and result of each row of the table
+	pub rows: Vec<(i64, i64, String)>
+}
+
+/// Limit on how deeply tables can refer to other tables, in case they refer to each other
+const MAX_TABLE_DEPTH: usize = 16;
+
+impl RandomTable {
+	/// Loads a random table from a file
+	pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+		let text = fs::read_to_string(path).map_err(|e| InvalidArgumentError{
+			msg: format!("Failed to read table {}: {}", path.display(), e)
+		})?;
+		RandomTable::parse(text.as_str()).map_err(|e| InvalidArgumentError{
+			msg: format!("Invalid table {}: {}", path.display(), e.msg)
+		}.into())
+	}
+
+	/// Parses a random table, one "RANGE: RESULT" (or "RANGE,RESULT") row per line, where RANGE is
+	/// a number or a range of numbers (eg "01-50", with "00" meaning 100), plus an optional
+	/// "dice: EXPRESSION" line
+	pub fn parse(text: &str) -> Result<Self, InvalidArgumentError> {
+		let mut table = RandomTable::default();
+		for line in text.lines().filter_map(strip_comment) {
+			let (key, result) = line.split_once([':', ',']).ok_or_else(|| InvalidArgumentError{
+				msg: format!("Invalid row '{}', expected RANGE: RESULT", line)
+			})?;
+			let (key, result) = (key.trim(), result.trim());
+			if key.eq_ignore_ascii_case("dice") {
+				table.dice = result.to_string();
+				continue;
+			}
+			let parse_roll = |n: &str| match n.trim() {
+				"00" => Ok(100),
+				n => n.parse::<i64>().map_err(|_| InvalidArgumentError{
+					msg: format!("Invalid range '{}' in row '{}'", key, line)
+				})
+			};
+			let (low, high) = match key.split_once('-') {
+				None => (parse_roll(key)?, parse_roll(key)?),
+				Some((low, high)) => (parse_roll(low)?, parse_roll(high)?)
+			};
+			table.rows.push((low.min(high), low.max(high), result.to_string()));
+		}
+		if table.rows.is_empty() {
+			return Err(InvalidArgumentError{msg: "The table is empty".into()});
+		}
+		if table.dice.is_empty() {
+			table.dice = format!("1d{}", table.rows.iter().map(|(_, high, _)| *high).max().unwrap_or(1));
+		}
+		Ok(table)
+	}
+
+	/// Rolls on the table, returning the roll and the result (with any dice in the result rolled
+	/// and any referenced tables, relative to `dir`, rolled on in turn)
+	pub fn roll(&self, dice: &mut DiceBag<rand::rngs::StdRng>, dir: &Path, depth: usize) -> Result<(i64, String), Box<dyn Error>> {
+		let roll = dice.eval_total(self.dice.as_str())?;
+		let result = self.rows.iter().find(|(low, high, _)| (*low..=*high).contains(&roll))
+			.map(|(_, _, result)| result)
+			.ok_or_else(|| InvalidArgumentError{msg: format!("No result in the table for a roll of {}", roll)})?;
+		let mut output = String::with_capacity(result.len());
+		let mut rest = result.as_str();
+		while let Some(open) = rest.find(['{', '[']) {
+			let close_char = if rest[open..].starts_with('{') {'}'} else {']'};
+			let close = matching_bracket(rest, open).ok_or_else(|| InvalidArgumentError{
+				msg: format!("Found '{}' without matching '{}' in result '{}'", &rest[open..open+1], close_char, result)
+			})?;
+			output.push_str(&rest[..open]);
+			let inner = rest[open+1..close].trim();
+			if close_char == '}' {
+				output.push_str(format!("{}", dice.eval_total(inner)?).as_str());
+			} else {
+				if depth >= MAX_TABLE_DEPTH {
+					return Err(InvalidArgumentError{
+						msg: format!("Tables nested more than {} deep (do they refer to each other?)", MAX_TABLE_DEPTH)
+					}.into());
+				}
+				let path = dir.join(inner);
+				let table = RandomTable::load(&path)?;
+				let (_, nested) = table.roll(dice, path.parent().unwrap_or(dir), depth + 1)?;
+				output.push_str(nested.as_str());
+			}
+			rest = &rest[close+1..];
+		}
+		output.push_str(rest);
+		Ok((roll, output))
+	}
+}
+
+/// Returns the position of the bracket that closes the one at `open`, skipping over any nested
+/// pairs of brackets (eg the braces of a dice group in "{ {1d6, 1d8}kh1 } gold")
+fn matching_bracket(text: &str, open: usize) -> Option<usize> {
+	let (open_char, close_char) = match text[open..].starts_with('{') {
+		true => ('{', '}'),
+		false => ('[', ']')
+	};
+	let mut depth = 0;
+	for (i, c) in text[open..].char_indices() {
+		if c == open_char {depth += 1;}
+		if c == close_char {depth -= 1;}
+		if depth == 0 {return Some(open + i);}
+	}
+	None
+}
+
+/// A roll on a random table, for JSON output
+#[derive(Serialize)]
+struct JsonTableRoll<'a> {
+	table: &'a Path,
+	roll: i64,
+	result: &'a str
+}
+
+/// Reads the dice expressions from the given file, skipping blank lines and # comments
+fn load_expressions(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+	let text = fs::read_to_string(path).map_err(|e| InvalidArgumentError{
+		msg: format!("Failed to read file {}: {}", path.display(), e)
+	})?;
+	Ok(text.lines().filter_map(strip_comment).map(String::from).collect())
+}
+
+/// Removes any # comment and surrounding whitespace from a line of input, returning `None` if
+/// there is no expression left
+fn strip_comment(line: &str) -> Option<&str> {
+	let exp = match line.find('#') {
+		None => line,
+		Some(i) => &line[..i]
+	}.trim();
+	if exp.is_empty() {None} else {Some(exp)}
+}
+
+/// Expressions are read from stdin when the only expression is "-", or when no expressions were
+/// given and stdin is not a terminal (ie something is being piped in)
+fn reads_stdin(args: &Args) -> bool {
+	match args.dice_expressions.as_slice() {
+		[] => args.file.is_none() && !io::stdin().is_terminal(),
+		[exp] => exp == "-",
+		_ => false
+	}
+}
+
+fn new_dice_bag(args: &Args) -> DiceBag<rand::rngs::StdRng> {
 	match args.seed {
-		None => dice = DiceBag::new(new_simple_rng()),
-		Some(seed) => dice = DiceBag::new(simple_rng(seed)),
-	}
-	for exp in &args.dice_expressions {
-		let mut output = String::new();
-		let roll = dice.eval(exp.as_str())?;
-		if ! args.quiet {
-			output.push_str(exp.as_str());
-			output.push_str(" => ");
-		}
-		output.push_str(format!("{}", roll.total).as_str());
-		if ! args.quiet && (args.show_average || args.show_range) {
-			output.push_str(" (");
-			if args.show_range {
-				output.push_str(format!("{}-{}", roll.min, roll.max).as_str());
+		None => DiceBag::new(new_simple_rng()),
+		Some(seed) => DiceBag::new(simple_rng(seed)),
+	}
+}
+
+/// Returns the line to print before any results, if the output format has one
+fn header(args: &Args) -> Option<String> {
+	if args.csv && args.simulate.is_some() {
+		return Some("expression,result,probability".into());
+	}
+	if let (true, Some(bound)) = (args.csv, bound_only(args)) {
+		return Some(format!("expression,{}", bound));
+	}
+	if args.csv {
+		let mut header = String::from("expression,total,min,max,average");
+		if args.floor_average {header.push_str(",floor_average");}
+		if args.stats {header.push_str(",mean,median,mode,std_dev,chance_at_least");}
+		if args.dc.is_some() {header.push_str(",success");}
+		return Some(header);
+	}
+	if let (true, Some(bound)) = (args.markdown, bound_only(args)) {
+		let bound = match bound {
+			"min" => "Min",
+			"max" => "Max",
+			_ => "Average"
+		};
+		return Some(format!("| Expression | {} |\n| --- | ---: |", bound));
+	}
+	if args.markdown {
+		let mut header = String::from("| Expression | Total | Range | Average |");
+		if args.dc.is_some() {header.push_str(" Result |");}
+		header.push_str("\n| --- | ---: | :---: | ---: |");
+		if args.dc.is_some() {header.push_str(" --- |");}
+		return Some(header);
+	}
+	None
+}
+
+/// Returns the line to print after all of the results, if any
+fn footer(args: &Args, sum: i64) -> Option<String> {
+	if !args.sum {
+		return None;
+	}
+	if args.json {
+		return Some(format!("{{\"sum\":{}}}", sum));
+	}
+	if args.csv {
+		// keep the same number of columns as the header
+		let columns = header(args).map_or(0, |header| header.split(',').count());
+		return Some(format!("sum,{}{}", sum, ",".repeat(columns.saturating_sub(2))));
+	}
+	if args.markdown {
+		let columns = if args.dc.is_some() {3} else {2};
+		return Some(format!("| **Sum** | **{}** |{}", sum, " |".repeat(columns)));
+	}
+	Some(format!("Sum => {}", paint(format!("{}", sum).as_str(), BOLD, use_color(args))))
+}
+
+/// The formatted result of evaluating a single expression
+struct Evaluation {
+	/// Formatted output
+	text: String,
+	/// The rolled total
+	total: i64,
+	/// Whether the roll failed the --dc check
+	missed_dc: bool,
+	/// The dice that were rolled (none for --simulate)
+	detailed: Option<DetailedRoll>
+}
+
+/// A line of the --log file
+#[derive(Serialize)]
+struct LogEntry<'a> {
+	timestamp: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	label: Option<&'a str>,
+	expression: &'a str,
+	seed: Option<u64>,
+	breakdown: String,
+	dice: &'a [RolledDice],
+	total: i64
+}
+
+/// The --log file, which records every roll as a JSON line
+struct SessionLog {
+	file: Option<fs::File>,
+	seed: Option<u64>
+}
+
+impl SessionLog {
+	/// Opens the --log file for appending (creating it if needed), or does nothing if there is no
+	/// --log option
+	fn open(args: &Args) -> Result<Self, Box<dyn Error>> {
+		let file = match &args.log {
+			None => None,
+			Some(path) => Some(fs::OpenOptions::new().create(true).append(true).open(path).map_err(|e| InvalidArgumentError{
+				msg: format!("Failed to open log file {}: {}", path.display(), e)
+			})?)
+		};
+		Ok(SessionLog{file, seed: args.seed})
+	}
+
+	/// Appends a roll to the log file (if there is one)
+	fn record(&mut self, label: Option<&str>, exp: &str, evaluation: &Evaluation) -> Result<(), Box<dyn Error>> {
+		if let (Some(file), Some(detailed)) = (self.file.as_mut(), evaluation.detailed.as_ref()) {
+			let entry = LogEntry{
+				timestamp: humantime::format_rfc3339_millis(std::time::SystemTime::now()).to_string(),
+				label,
+				expression: exp,
+				seed: self.seed,
+				breakdown: verbose_breakdown(&detailed.breakdown, false),
+				dice: &detailed.dice,
+				total: detailed.roll.total
+			};
+			writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+		}
+		Ok(())
+	}
+}
+
+/// Evaluates a single expression and formats the result according to the CLI arguments, showing
+/// the label (if any) in place of the expression
+fn evaluate(dice: &mut DiceBag<rand::rngs::StdRng>, label: Option<&str>, exp: &str, args: &Args) -> Result<Evaluation, Box<dyn Error>> {
+	let name = label.unwrap_or(exp);
+	if let Some(samples) = args.simulate {
+		let text = simulate_to_string(dice, label, exp, samples as usize, args)?;
+		return Ok(Evaluation{text, total: 0, missed_dc: false, detailed: None});
+	}
+	if let Some(bound) = bound_only(args) {
+		let value: serde_json::Value = match bound {
+			"min" => dice.eval_min(exp)?.into(),
+			"max" => dice.eval_max(exp)?.into(),
+			_ if args.floor_average => dice.eval(exp)?.floor_average().into(),
+			_ => dice.eval_ave(exp)?.into()
+		};
+		let text = if args.json {
+			serde_json::to_string(&JsonBound{label, expression: exp, bound: [(bound.to_string(), value)].into_iter().collect()})?
+		} else if args.csv {
+			format!("{},{}", csv_field(name), value)
+		} else if args.markdown {
+			format!("| {} | {} |", markdown_cell(name), value)
+		} else if args.quiet {
+			paint(value.to_string().as_str(), BOLD, use_color(args))
+		} else {
+			format!("{} => {}", name, paint(value.to_string().as_str(), BOLD, use_color(args)))
+		};
+		return Ok(Evaluation{text, total: 0, missed_dc: false, detailed: None});
+	}
+	let detailed = dice.eval_detailed(exp)?;
+	let roll = detailed.roll;
+	let success = args.dc.map(|dc| roll.total >= dc);
+	let missed_dc = success == Some(false);
+	let stats = match args.stats {
+		true => Some(roll_stats(dice, exp, roll.total, args)?),
+		false => None
+	};
+	if args.json {
+		let floor_average = args.floor_average.then(|| roll.floor_average());
+		let text = serde_json::to_string(&JsonRoll{label, expression: exp, roll, floor_average, dice: &detailed.dice,
+			tags: &detailed.tags, stats, success})?;
+		return Ok(Evaluation{text, total: roll.total, missed_dc, detailed: Some(detailed)});
+	}
+	if args.csv {
+		let mut output = format!("{},{},{},{},{}", csv_field(name), roll.total, roll.min, roll.max, roll.average);
+		if args.floor_average {
+			output.push_str(format!(",{}", roll.floor_average()).as_str());
+		}
+		if let Some(stats) = stats {
+			let mode = stats.mode.map_or(String::new(), |mode| mode.to_string());
+			output.push_str(format!(",{},{},{},{},{}", stats.mean, stats.median, mode, stats.std_dev, stats.chance_at_least).as_str());
+		}
+		if let Some(success) = success {
+			output.push_str(format!(",{}", success).as_str());
+		}
+		return Ok(Evaluation{text: output, total: roll.total, missed_dc, detailed: Some(detailed)});
+	}
+	if args.markdown {
+		let mut output = match args.floor_average {
+			true => format!("| {} | {} | {}-{} | {} ({}) |", markdown_cell(name), roll.total, roll.min, roll.max,
+				roll.average, roll.floor_average()),
+			false => format!("| {} | {} | {}-{} | {} |", markdown_cell(name), roll.total, roll.min, roll.max, roll.average)
+		};
+		match success {
+			Some(true) => output.push_str(" SUCCESS |"),
+			Some(false) => output.push_str(" FAILURE |"),
+			None => {}
+		}
+		return Ok(Evaluation{text: output, total: roll.total, missed_dc, detailed: Some(detailed)});
+	}
+	let color = use_color(args);
+	let mut output = String::new();
+	if ! args.quiet {
+		output.push_str(name);
+		output.push_str(" => ");
+	}
+	if args.breakdown {
+		output.push_str(worked_breakdown(&detailed.breakdown).as_str());
+		output.push_str(" = ");
+	}
+	output.push_str(paint(format!("{}", roll.total).as_str(), BOLD, color).as_str());
+	if ! args.quiet && ! detailed.tags.is_empty() {
+		// subtotals of tagged damage, eg "(7 fire, 3 cold)"
+		let subtotals: Vec<String> = detailed.tags.iter().map(|(tag, subtotal)| format!("{} {}", subtotal, tag)).collect();
+		output.push_str(format!(" ({})", subtotals.join(", ")).as_str());
+	}
+	if args.verbose {
+		output.push(' ');
+		output.push_str(verbose_breakdown(&detailed.breakdown, color).as_str());
+	}
+	if ! args.quiet && (args.show_average || args.show_range || args.floor_average) {
+		let mut extra = String::from(" (");
+		if args.show_range {
+			extra.push_str(format!("{}-{}", roll.min, roll.max).as_str());
+		}
+		if (args.show_average || args.floor_average) && args.show_range {extra.push_str(", ");}
+		if args.show_average || args.floor_average {
+			extra.push_str(format!("{:.1} ave.", roll.average).as_str());
+		}
+		if args.floor_average {
+			extra.push_str(format!(", {} floored", roll.floor_average()).as_str());
+		}
+		extra.push(')');
+		output.push_str(paint(extra.as_str(), DIM, color).as_str());
+	}
+	if let Some(stats) = stats {
+		let modes: Vec<String> = match stats.modes.is_empty() {
+			true => stats.mode.iter().map(|mode| mode.to_string()).collect(),
+			false => stats.modes.iter().map(|mode| mode.to_string()).collect()
+		};
+		let mode = match modes.is_empty() {
+			true => String::new(),
+			false => format!(", mode {}", modes.join(" or "))
+		};
+		let estimated = match stats.exact {
+			true => String::new(),
+			false => format!(", estimated from {} rolls", STATS_SAMPLES)
+		};
+		let extra = format!(" [mean {:.2}, median {}{}, std. dev. {:.2}, {:.1}% chance of {} or higher{}]",
+			stats.mean, stats.median, mode, stats.std_dev, 100. * stats.chance_at_least, roll.total, estimated);
+		output.push_str(paint(extra.as_str(), DIM, color).as_str());
+	}
+	match success {
+		Some(true) => output.push_str(format!(" {}", paint("SUCCESS", GREEN, color)).as_str()),
+		Some(false) => output.push_str(format!(" {}", paint("FAILURE", RED, color)).as_str()),
+		None => {}
+	}
+	Ok(Evaluation{text: output, total: roll.total, missed_dc, detailed: Some(detailed)})
+}
+
+/// Returns which result --min-only, --max-only, or --average-only asked for instead of rolling
+/// ("min", "max", or "average"), if any
+fn bound_only(args: &Args) -> Option<&'static str> {
+	if args.min_only {
+		Some("min")
+	} else if args.max_only {
+		Some("max")
+	} else if args.average_only {
+		Some("average")
+	} else {
+		None
+	}
+}
+
+/// ANSI terminal escape codes used to color the output
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// Decides whether to color the output, following the --color option and the NO_COLOR convention
+/// (see https://no-color.org)
+fn use_color(args: &Args) -> bool {
+	match args.color {
+		ColorChoice::Always => true,
+		ColorChoice::Never => false,
+		ColorChoice::Auto => io::stdout().is_terminal()
+			&& env::var_os("NO_COLOR").is_none_or(|no_color| no_color.is_empty())
+	}
+}
+
+/// Wraps the text in the given ANSI style if color is turned on
+fn paint(text: &str, style: &str, color: bool) -> String {
+	if color {format!("{}{}{}", style, text, RESET)} else {text.into()}
+}
+
+/// Number of simulated rolls used to estimate statistics when there are too many combinations of
+/// dice to work them out exactly
+const STATS_SAMPLES: usize = 10000;
+
+/// Statistics for a rolled expression, shown with --stats
+#[derive(Serialize)]
+struct RollStats {
+	mean: f64,
+	median: i64,
+	/// the smallest of the most likely results (left out when estimated, since a sampled mode is
+	/// mostly noise)
+	#[serde(skip_serializing_if = "Option::is_none")]
+	mode: Option<i64>,
+	/// every result tied for most likely, if there is more than one
+	#[serde(skip_serializing_if = "no_tie")]
+	modes: Vec<i64>,
+	std_dev: f64,
+	/// chance of rolling at least the rolled total
+	chance_at_least: f64,
+	/// whether the statistics were worked out exactly, rather than estimated from sampled rolls
+	exact: bool
+}
+
+fn roll_stats(dice: &DiceBag<rand::rngs::StdRng>, exp: &str, total: i64, args: &Args) -> Result<RollStats, Box<dyn Error>> {
+	let (dist, exact) = stats_distribution(dice, exp, args)?;
+	Ok(RollStats{
+		mean: dist.mean(),
+		median: dist.median().unwrap_or(total),
+		mode: if exact {dist.mode()} else {None},
+		modes: if exact {dist.modes()} else {Vec::new()},
+		std_dev: dist.std_dev(),
+		chance_at_least: dist.prob_at_least(total),
+		exact
+	})
+}
+
+/// Works out the distribution of an expression exactly if there are few enough combinations of
+/// dice, and otherwise estimates it from rolls of a separate dice bag, so that asking for
+/// statistics does not change the rolls that come after (eg with --seed). Returns the distribution
+/// and whether it is exact.
+fn stats_distribution(dice: &DiceBag<rand::rngs::StdRng>, exp: &str, args: &Args) -> Result<(Distribution, bool), Box<dyn Error>> {
+	match dice.exact_distribution(exp)? {
+		Some(dist) => Ok((dist, true)),
+		None => Ok((new_dice_bag(args).sample_distribution(exp, STATS_SAMPLES)?, false))
+	}
+}
+
+/// Returns true if there is at most one most likely result (so the modes are left out of JSON output)
+fn no_tie(modes: &[i64]) -> bool {
+	modes.len() < 2
+}
+
+/// Samples a single expression many times and formats the observed distribution according to the
+/// CLI arguments
+fn simulate_to_string(dice: &mut DiceBag<rand::rngs::StdRng>, label: Option<&str>, exp: &str, samples: usize, args: &Args) -> Result<String, Box<dyn Error>> {
+	let dist = dice.sample_distribution(exp, samples)?;
+	let name = label.unwrap_or(exp);
+	if args.json {
+		return Ok(serde_json::to_string(&JsonDistribution{
+			label, expression: exp, samples, mean: dist.mean(), std_dev: dist.std_dev(),
+			median: dist.median(), mode: dist.mode(),
+			distribution: dist.iter().collect()
+		})?);
+	}
+	if args.csv {
+		let rows: Vec<String> = dist.iter()
+			.map(|(result, p)| format!("{},{},{}", csv_field(name), result, p))
+			.collect();
+		return Ok(rows.join("\n"));
+	}
+	let mut output = format!("{} => {} samples: mean {:.2}, std. dev. {:.2}", name, samples, dist.mean(), dist.std_dev());
+	if let (true, Some(median), Some(mode)) = (args.stats, dist.median(), dist.mode()) {
+		output.push_str(format!(", median {}, mode {}", median, mode).as_str());
+	}
+	for line in histogram(&dist) {
+		output.push('\n');
+		output.push_str(line.as_str());
+	}
+	Ok(output)
+}
+
+/// Maximum number of rows in a histogram, wider ranges of results are grouped into buckets
+const HISTOGRAM_ROWS: i64 = 30;
+/// Length of the longest bar in a histogram
+const HISTOGRAM_WIDTH: f64 = 50.;
+
+/// Draws a distribution as a horizontal bar chart, one line per result (or per bucket of results
+/// if there are too many results to show them all)
+fn histogram(dist: &Distribution) -> Vec<String> {
+	let (min, max) = match (dist.min(), dist.max()) {
+		(Some(min), Some(max)) => (min, max),
+		_ => return Vec::new()
+	};
+	// results are often spaced out (eg "1d10x5"), so make each bucket hold the same number of them
+	let step = dist.iter().zip(dist.iter().skip(1)).fold(0, |g, ((a, _), (b, _))| gcd(g, b - a)).max(1);
+	let rows = (max - min) / step + 1;
+	let bucket_size = step * ((rows + HISTOGRAM_ROWS - 1) / HISTOGRAM_ROWS);
+	let mut buckets: Vec<(String, f64)> = Vec::new();
+	let mut start = min;
+	while start <= max {
+		let end = start.saturating_add(bucket_size - step).min(max);
+		let label = if start == end {format!("{}", start)} else {format!("{}-{}", start, end)};
+		let p: f64 = dist.iter().filter(|(k, _)| *k >= start && *k <= end).map(|(_, p)| p).sum();
+		buckets.push((label, p));
+		start = end.saturating_add(step);
+		if end == max {break;}
+	}
+	let max_p = buckets.iter().map(|(_, p)| *p).fold(0., f64::max);
+	let label_width = buckets.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+	buckets.iter().map(|(label, p)| {
+		let bar = "#".repeat((p / max_p * HISTOGRAM_WIDTH).round() as usize);
+		format!("{:>w$}: {:>6.2}% {}", label, 100. * p, bar, w = label_width)
+	}).collect()
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+	if b == 0 {a} else {gcd(b, a % b)}
+}
+
+/// Writes out the breakdown of a rolled expression with each dice term replaced by the list of
+/// dice it rolled and spaces around the arithmetic operators (eg "[4, 6, 1] + 2"). When color is
+/// turned on, dice that rolled their highest value are green and dice that rolled a 1 are red.
+/// Dropped dice (eg by "4d6kh3") are crossed out with ~~ instead.
+fn verbose_breakdown(breakdown: &[BreakdownPart], color: bool) -> String {
+	let mut output = String::new();
+	// operators that follow a value are binary (spaced), otherwise they are unary (eg "-2")
+	let mut after_value = false;
+	for part in breakdown {
+		match part {
+			BreakdownPart::Dice(dice) => {
+				let rolls: Vec<String> = dice.rolls.iter().enumerate().map(|(i, roll)| {
+					let text = format!("{}", roll);
+					match *roll {
+						// dropped dice are crossed out, eg "[6, 5, 4, ~~1~~]"
+						_ if dice.is_dropped(i) => format!("~~{}~~", text),
+						r if r == dice.sides => paint(text.as_str(), GREEN, color),
+						1 => paint(text.as_str(), RED, color),
+						_ => text
+					}
+				}).collect();
+				output.push('[');
+				output.push_str(rolls.join(", ").as_str());
+				output.push(']');
+				after_value = true;
 			}
-			if args.show_average && args.show_range {output.push_str(", ");}
-			if args.show_average {
-				output.push_str(format!("{:.1} ave.", roll.average).as_str());
+			BreakdownPart::Text(text) => {
+				let mut tag_end = 0;
+				for (i, c) in text.char_indices() {
+					if i < tag_end || c.is_whitespace() {continue;}
+					if c == '[' {
+						// damage type tags are copied as they are (eg "[cold iron]")
+						tag_end = text[i..].find(']').map_or(text.len(), |end| i + end + 1);
+						output.push_str(&text[i..tag_end]);
+						after_value = true;
+					} else if matches!(c, '>' | '<' | '=' | '!') {
+						// comparison operators are spaced as a whole (eg " >= ")
+						let continued = matches!(output.chars().last(), Some('>' | '<' | '=' | '!'));
+						if !continued && !output.ends_with(' ') {output.push(' ');}
+						output.push(c);
+						if !text[i+1..].starts_with('=') {output.push(' ');}
+						after_value = false;
+					} else if (after_value && matches!(c, '+' | '-' | '*' | '/' | 'x' | 'X')) || matches!(c, '?' | ':') {
+						output.push(' ');
+						output.push(c);
+						output.push(' ');
+						after_value = false;
+					} else if c == ',' {
+						output.push_str(", ");
+						after_value = false;
+					} else {
+						output.push(c);
+						after_value = c.is_ascii_digit() || c == ')' || c == '}' || c == '%';
+					}
+				}
 			}
-			output.push_str(")");
 		}
-		results.push(output);
 	}
-	Ok(results)
+	output
+}
+
+/// Writes out the arithmetic of a rolled expression, with every kept die replaced by the number
+/// rolled and implied multiplication written out (eg "4(2d6+3)" becomes "4*((5+2)+3)")
+fn worked_breakdown(breakdown: &[BreakdownPart]) -> String {
+	fn push(output: &mut String, c: char) {
+		if c == '(' && output.ends_with(|prev: char| prev.is_ascii_digit() || prev == ')') {
+			output.push('*');
+		}
+		output.push(c);
+	}
+	let mut output = String::new();
+	for part in breakdown {
+		match part {
+			BreakdownPart::Dice(dice) => {
+				let rolls: Vec<String> = dice.kept().map(|roll| format!("{}", roll)).collect();
+				match rolls.len() {
+					0 => push(&mut output, '0'),
+					1 => rolls[0].chars().for_each(|c| push(&mut output, c)),
+					_ => format!("({})", rolls.join("+")).chars().for_each(|c| push(&mut output, c))
+				}
+			}
+			BreakdownPart::Text(text) => {
+				let mut tag_end = 0;
+				for (i, c) in text.char_indices() {
+					match c {
+						_ if i < tag_end => {}
+						'[' => {
+							tag_end = text[i..].find(']').map_or(text.len(), |end| i + end + 1);
+							output.push_str(&text[i..tag_end]);
+						}
+						c if c.is_whitespace() => {}
+						'x' | 'X' => push(&mut output, '*'),
+						'%' => output.push_str("100"),
+						'?' => output.push_str(" ? "),
+						':' => output.push_str(" : "),
+						c => push(&mut output, c)
+					}
+				}
+			}
+		}
+	}
+	output
+}
+
+/// Quotes a CSV field if it contains any characters that would otherwise break the row
+fn csv_field(text: &str) -> String {
+	if text.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", text.replace('"', "\"\""))
+	} else {
+		text.into()
+	}
+}
+
+/// JSON output record for a single evaluated expression
+#[derive(Serialize)]
+struct JsonRoll<'a> {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	label: Option<&'a str>,
+	expression: &'a str,
+	#[serde(flatten)]
+	roll: DiceRoll,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	floor_average: Option<i64>,
+	dice: &'a [RolledDice],
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	tags: &'a BTreeMap<String, i64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	stats: Option<RollStats>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	success: Option<bool>
+}
+
+/// JSON output record for --min-only, --max-only, and --average-only
+#[derive(Serialize)]
+struct JsonBound<'a> {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	label: Option<&'a str>,
+	expression: &'a str,
+	/// the requested bound ("min", "max", or "average") and its value
+	#[serde(flatten)]
+	bound: serde_json::Map<String, serde_json::Value>
+}
+
+/// JSON output record for a simulated expression
+#[derive(Serialize)]
+struct JsonDistribution<'a> {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	label: Option<&'a str>,
+	expression: &'a str,
+	samples: usize,
+	mean: f64,
+	std_dev: f64,
+	median: Option<i64>,
+	mode: Option<i64>,
+	distribution: Vec<(i64, f64)>
+}
+
+/// Escapes text for use in a markdown table cell
+fn markdown_cell(text: &str) -> String {
+	text.replace('|', "\\|")
 }
 
 fn sanity_check(args: &Args) -> Result<(), Box<dyn Error>> {
-	if args.quiet && (args.show_range || args.show_average) {
-		return Err(InvalidArgumentError{msg: "Invalid arguments: -q/--quiet is not compatible with -a/--average and -r/--range".into()}.into());
+	if args.quiet && (args.show_range || args.show_average || args.verbose) {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: -q/--quiet is not compatible with -a/--average, -r/--range, and -v/--verbose".into()}.into());
+	}
+	if args.floor_average && (args.quiet || args.simulate.is_some() || args.min_only || args.max_only) {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: --floor-average is not compatible with -q/--quiet, --simulate, --min-only, or --max-only".into()}.into());
+	}
+	if args.quiet && args.stats {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: -q/--quiet is not compatible with --stats".into()}.into());
+	}
+	if args.simulate.is_some() && (args.dc.is_some() || args.sum) {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: --simulate is not compatible with --dc or --sum".into()}.into());
+	}
+	if args.simulate.is_some() && (args.quiet || args.show_range || args.show_average || args.verbose) {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: --simulate is not compatible with -q/--quiet, -a/--average, -r/--range, or -v/--verbose".into()}.into());
+	}
+	if [args.json, args.csv, args.markdown].iter().filter(|flag| **flag).count() > 1 {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: only one of --json, --csv, and --markdown can be used".into()}.into());
+	}
+	if args.markdown && (args.quiet || args.show_range || args.show_average || args.verbose || args.stats || args.simulate.is_some()) {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: --markdown is not compatible with -q/--quiet, -a/--average, -r/--range, -v/--verbose, --stats, or --simulate".into()}.into());
+	}
+	if args.breakdown && (args.json || args.csv || args.markdown || args.simulate.is_some() || bound_only(args).is_some()) {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: --breakdown is not compatible with --json, --csv, --markdown, --simulate, --min-only, --max-only, or --average-only".into()}.into());
+	}
+	if (args.json || args.csv) && (args.quiet || args.show_range || args.show_average || args.verbose) {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: --json and --csv are not compatible with -q/--quiet, -a/--average, -r/--range, or -v/--verbose".into()}.into());
+	}
+	if [args.min_only, args.max_only, args.average_only].iter().filter(|flag| **flag).count() > 1 {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: only one of --min-only, --max-only, and --average-only can be used".into()}.into());
+	}
+	if bound_only(args).is_some() && (args.show_range || args.show_average || args.verbose || args.stats
+			|| args.dc.is_some() || args.sum || args.simulate.is_some()) {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: --min-only, --max-only, and --average-only are not compatible with -a/--average, -r/--range, -v/--verbose, --stats, --dc, --sum, or --simulate".into()}.into());
+	}
+	if (args.dice_expressions.len() > 1 || args.file.is_some()) && args.dice_expressions.iter().any(|exp| exp == "-") {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: - (read from stdin) cannot be combined with other dice expressions or -f/--file".into()}.into());
+	}
+	// only rolls of dice expressions (including the actions of a sheet) are logged
+	if args.log.is_some() && !matches!(args.command, None | Some(Command::Sheet{..})) {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: --log is not compatible with the init, compare, verify, table, and completions commands".into()}.into());
+	}
+	if args.show_seed && matches!(args.command, Some(Command::Verify{..} | Command::Completions{..})) {
+		return Err(InvalidArgumentError{msg: "Invalid arguments: --show-seed is not compatible with the verify and completions commands".into()}.into());
 	}
 	Ok(())
 }
@@ -104,3 +1425,343 @@ impl core::fmt::Display for InvalidArgumentError {
 
 impl Error for InvalidArgumentError {}
 
+
+#[cfg(test)]
+mod unit_tests {
+
+	#[test]
+	fn comment_checks() {
+		use crate::strip_comment;
+		assert_eq!(strip_comment("1d20+4"), Some("1d20+4"));
+		assert_eq!(strip_comment("  1d20+4   # goblin attack"), Some("1d20+4"));
+		assert_eq!(strip_comment("# goblin ambush"), None);
+		assert_eq!(strip_comment("   "), None);
+	}
+
+	#[test]
+	fn verbose_checks() {
+		use crate::verbose_breakdown;
+		use dicexp::{BreakdownPart, RolledDice};
+		let dice = BreakdownPart::Dice(RolledDice{count: 3, sides: 6, rolls: vec![4, 6, 1], dropped: vec![]});
+		assert_eq!(verbose_breakdown(&[dice.clone(), BreakdownPart::Text("+2".into())], false), "[4, 6, 1] + 2");
+		assert_eq!(verbose_breakdown(&[BreakdownPart::Text("-2 x(".into()), dice.clone(), BreakdownPart::Text("-1)".into())], false), "-2 x ([4, 6, 1] - 1)");
+		assert_eq!(verbose_breakdown(&[dice.clone(), BreakdownPart::Text("?2d6:-1".into())], false), "[4, 6, 1] ? 2d6 : -1");
+		assert_eq!(verbose_breakdown(&[dice.clone(), BreakdownPart::Text("+5>=15".into())], false), "[4, 6, 1] + 5 >= 15");
+		assert_eq!(verbose_breakdown(&[BreakdownPart::Text("{".into()), dice.clone(), BreakdownPart::Text("+1,5}kh1-1".into())], false), "{[4, 6, 1] + 1, 5}kh1 - 1");
+		assert_eq!(verbose_breakdown(&[dice.clone(), BreakdownPart::Text("[cold iron]+2[fire]".into())], false), "[4, 6, 1][cold iron] + 2[fire]");
+		assert_eq!(verbose_breakdown(&[dice], true), "[4, \x1b[32m6\x1b[0m, \x1b[31m1\x1b[0m]");
+		let kept = BreakdownPart::Dice(RolledDice{count: 3, sides: 6, rolls: vec![4, 6, 1], dropped: vec![2]});
+		assert_eq!(verbose_breakdown(&[kept], true), "[4, \x1b[32m6\x1b[0m, ~~1~~]");
+	}
+
+	#[test]
+	fn breakdown_checks() {
+		use crate::worked_breakdown;
+		use dicexp::{BreakdownPart, RolledDice};
+		let two_dice = BreakdownPart::Dice(RolledDice{count: 2, sides: 6, rolls: vec![5, 2], dropped: vec![]});
+		let one_die = BreakdownPart::Dice(RolledDice{count: 1, sides: 20, rolls: vec![17], dropped: vec![]});
+		assert_eq!(worked_breakdown(&[BreakdownPart::Text("4(".into()), two_dice.clone(), BreakdownPart::Text("+3)".into())]), "4*((5+2)+3)");
+		assert_eq!(worked_breakdown(&[one_die.clone(), BreakdownPart::Text(" - 2 x 3".into())]), "17-2*3");
+		assert_eq!(worked_breakdown(&[two_dice, BreakdownPart::Text("(2)".into())]), "(5+2)*(2)");
+		let kept = BreakdownPart::Dice(RolledDice{count: 2, sides: 20, rolls: vec![3, 17], dropped: vec![0]});
+		assert_eq!(worked_breakdown(&[kept, BreakdownPart::Text("+5".into())]), "17+5");
+		assert_eq!(worked_breakdown(&[one_die, BreakdownPart::Text("[cold iron] + 2".into())]), "17[cold iron]+2");
+	}
+
+	#[test]
+	fn histogram_checks() {
+		use crate::histogram;
+		use dicexp::Distribution;
+		let chart = histogram(&Distribution::from_samples([1, 2, 2, 3]));
+		assert_eq!(chart.len(), 3);
+		assert_eq!(chart[0], format!("1:  25.00% {}", "#".repeat(25)));
+		assert_eq!(chart[1], format!("2:  50.00% {}", "#".repeat(50)));
+		let chart = histogram(&Distribution::from_samples(1..=100));
+		assert_eq!(chart.len(), 25);
+		assert!(chart[0].starts_with("   1-4:   4.00% "));
+		let chart = histogram(&Distribution::from_samples((1..=100).map(|n| 3 * n)));
+		assert_eq!(chart.len(), 25);
+		assert!(chart[1].starts_with("  15-24:   4.00% "));
+		assert!(histogram(&Distribution::from_samples([])).is_empty());
+	}
+
+	#[test]
+	fn config_checks() {
+		use crate::{Config, expand_alias};
+		use std::ffi::OsString;
+		let config = Config::parse(r#"
+			default_args = ["-a"]
+			[aliases]
+			sneak = "1d20+8; 3d6+4d6"
+		"#).unwrap();
+		assert_eq!(expand_alias(&config.aliases, "sneak"), vec!["1d20+8", "3d6+4d6"]);
+		assert_eq!(expand_alias(&config.aliases, "1d6"), vec!["1d6"]);
+		let args = config.with_default_args(["dicexp", "1d6"].map(OsString::from));
+		assert_eq!(args, ["dicexp", "-a", "1d6"].map(OsString::from));
+		assert!(Config::parse("").unwrap().aliases.is_empty());
+		assert!(Config::parse("unknown = 1").is_err());
+	}
+
+	#[test]
+	fn sheet_checks() {
+		use crate::Sheet;
+		let sheet: Sheet = toml::from_str(r#"
+			[stats]
+			str = -1
+			prof = 3
+			[actions]
+			attack = "1d20+{str}+{ prof }"
+			damage = "1d8+{dex}"
+			advantage = "{1d20+{prof}, 1d20+{prof}}kh1"
+			"power attack" = "1d20"
+		"#).unwrap();
+		assert_eq!(sheet.expression("attack").unwrap(), "1d20+(-1)+3");
+		assert_eq!(sheet.expression("advantage").unwrap(), "{1d20+3, 1d20+3}kh1");
+		assert!(sheet.expression("damage").is_err());
+		assert!(sheet.expression("dance").is_err());
+		assert!(sheet.expression("power attack").is_err());
+	}
+
+	#[test]
+	fn init_checks() {
+		use crate::{Args, roll_initiative};
+		use clap::Parser;
+		let args = Args::parse_from(["dicexp", "-s", "7", "--color", "never"]);
+		let combatants = ["Slow:1".to_string(), "Fast:20".into(), "Tied:10".into(), "Bonus:9+1".into()];
+		let order = roll_initiative(&combatants, &args).unwrap();
+		assert_eq!(order, ["1. Fast: 20 (20)", "2. Tied: 10 (10)", "3. Bonus: 10 (9+1)", "4. Slow: 1 (1)"]);
+		let combatants = ["Goblin:1d4".to_string(), "Hero:1d4+4".into()];
+		assert!(roll_initiative(&combatants, &args).unwrap()[0].starts_with("1. Hero: "));
+		assert!(roll_initiative(&["Goblin".to_string()], &args).is_err());
+	}
+
+	#[test]
+	fn completions_checks() {
+		use crate::Args;
+		use clap::CommandFactory;
+		use clap_complete::Shell;
+		let mut script = Vec::new();
+		clap_complete::generate(Shell::Bash, &mut Args::command(), "dicexp", &mut script);
+		let script = String::from_utf8(script).unwrap();
+		assert!(script.contains("--show-seed"));
+		assert!(script.contains("dicexp,sheet)"));
+	}
+
+	#[test]
+	fn log_checks() {
+		use crate::{Args, run};
+		use clap::Parser;
+		let path = std::env::temp_dir().join(format!("dicexp-log-checks-{}.jsonl", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let args = Args::parse_from(["dicexp", "-s", "7", "--log", path.to_str().unwrap(), "3d6+2", "5"]);
+		let (results, _) = run(args).unwrap();
+		let log = std::fs::read_to_string(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		let entries: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+		assert_eq!(entries.len(), 2);
+		assert_eq!(entries[0]["expression"], "3d6+2");
+		assert_eq!(entries[0]["seed"], 7);
+		assert_eq!(format!("3d6+2 => {}", entries[0]["total"]), results[0]);
+		assert_eq!(entries[1]["breakdown"], "5");
+		// rolls made by other commands are not logged, so --log is rejected rather than ignored
+		assert!(crate::sanity_check(&Args::parse_from(["dicexp", "--log", "log.jsonl", "init", "A:1d20"])).is_err());
+		assert!(crate::sanity_check(&Args::parse_from(["dicexp", "--log", "log.jsonl", "table", "treasure.txt"])).is_err());
+		assert!(crate::sanity_check(&Args::parse_from(["dicexp", "--log", "log.jsonl", "sheet", "fighter.toml", "attack"])).is_ok());
+	}
+
+	#[test]
+	fn bound_only_checks() {
+		use crate::{Args, run};
+		use clap::Parser;
+		let (results, _) = run(Args::parse_from(["dicexp", "--min-only", "3d6+2", "1d4*2"])).unwrap();
+		assert_eq!(results, ["3d6+2 => 5", "1d4*2 => 2"]);
+		let (results, _) = run(Args::parse_from(["dicexp", "--average-only", "--json", "3d6+2"])).unwrap();
+		assert_eq!(results, ["{\"expression\":\"3d6+2\",\"average\":12.5}"]);
+		let (results, _) = run(Args::parse_from(["dicexp", "--max-only", "--csv", "3d6+2"])).unwrap();
+		assert_eq!(results, ["expression,max", "3d6+2,20"]);
+		let (results, _) = run(Args::parse_from(["dicexp", "--average-only", "--floor-average", "3d6+2", "2d8+2"])).unwrap();
+		assert_eq!(results, ["3d6+2 => 12", "2d8+2 => 11"]);
+	}
+
+	#[test]
+	fn verify_checks() {
+		use crate::{Args, verify};
+		use clap::Parser;
+		use dicexp::{DiceBag, simple_rng};
+		let args = Args::parse_from(["dicexp", "--color", "never"]);
+		let total = DiceBag::new(simple_rng(42)).eval_total("3d6+2").unwrap();
+		assert_eq!(verify(42, "3d6+2", total, &[], &args).unwrap(), (format!("VALID 3d6+2 => {} (seed 42)", total), true));
+		assert_eq!(verify(42, "fireball=3d6+2", total + 1, &[], &args).unwrap(),
+			(format!("INVALID fireball => {} (seed 42 rolls {})", total + 1, total), false));
+		// the dice are checked too when they are given, even if the total matches
+		let roll = DiceBag::new(simple_rng(42)).eval_detailed("2d20kh1").unwrap();
+		let (total, dice) = (roll.roll.total, roll.dice[0].rolls.clone());
+		assert!(verify(42, "2d20kh1", total, &dice, &args).unwrap().1);
+		let swapped = [dice[1], dice[0]];
+		assert_eq!(verify(42, "2d20kh1", total, &swapped, &args).unwrap(), (format!("INVALID 2d20kh1 => {} {:?} (seed 42 rolls {} {:?})",
+			total, swapped, total, dice), false));
+		let args = Args::parse_from(["dicexp", "--json"]);
+		let (line, valid) = verify(42, "5", 5, &[], &args).unwrap();
+		assert_eq!(line, "{\"expression\":\"5\",\"seed\":42,\"claimed\":5,\"total\":5,\"min\":5,\"max\":5,\"average\":5.0,\"valid\":true}");
+		assert!(valid);
+		let (line, valid) = verify(42, "1d6", 1, &[7], &args).unwrap();
+		assert!(line.contains("\"claimed_dice\":[7]") && line.contains("\"dice\":[") && !valid);
+		assert!(verify(42, "3d6+", 5, &[], &args).is_err());
+		// verify uses the seed it is given, so there is no seed to show
+		assert!(crate::sanity_check(&Args::parse_from(["dicexp", "--show-seed", "verify", "42", "5", "5"])).is_err());
+		assert!(crate::sanity_check(&Args::parse_from(["dicexp", "--show-seed", "init", "A:1d20"])).is_ok());
+	}
+
+	#[test]
+	fn compare_checks() {
+		use crate::{Args, compare};
+		use clap::Parser;
+		let args = Args::parse_from(["dicexp", "--color", "never"]);
+		let lines = compare("2d6+4", "1d12+5", &args).unwrap();
+		assert_eq!(lines, ["2d6+4: average 11, range 6 to 16, std. dev. 2.42", "1d12+5: average 11.5, range 6 to 17, std. dev. 3.45",
+			"2d6+4 > 1d12+5: 41.7%", "1d12+5 > 2d6+4: 50.0%", "tie: 8.3%"]);
+		let lines = compare("200d1000", "1d6", &args).unwrap();
+		assert!(lines[0].ends_with(" (estimated from 10000 rolls)") && !lines[1].contains("estimated"));
+		assert_eq!(lines[2], "200d1000 > 1d6: 100.0%");
+		let lines = compare("1d20", "1d20", &Args::parse_from(["dicexp", "--json"])).unwrap();
+		let json: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+		assert!(json["a"]["exact"] == true && (json["tie"].as_f64().unwrap() - 0.05).abs() < 1e-9);
+	}
+
+	#[test]
+	fn stats_checks() {
+		use crate::{Args, run};
+		use clap::Parser;
+		let (results, _) = run(Args::parse_from(["dicexp", "--color", "never", "--stats", "5", "3d6"])).unwrap();
+		assert_eq!(results[0], "5 => 5 [mean 5.00, median 5, mode 5, std. dev. 0.00, 100.0% chance of 5 or higher]");
+		assert!(results[1].contains("[mean 10.50, median 10, mode 10 or 11, std. dev. 2.96, "));
+		let (results, _) = run(Args::parse_from(["dicexp", "--json", "--stats", "4d6kh3"])).unwrap();
+		assert!(results[0].contains("\"median\":12,\"mode\":13,\"std_dev\""));
+		// too many combinations to work out exactly, so estimated without changing the rolls
+		let (results, _) = run(Args::parse_from(["dicexp", "--color", "never", "--stats", "--seed", "7", "100d100kh50", "1d20"])).unwrap();
+		let (rolls, _) = run(Args::parse_from(["dicexp", "--color", "never", "--seed", "7", "100d100kh50", "1d20"])).unwrap();
+		assert!(results[0].ends_with(" or higher, estimated from 10000 rolls]") && !results[0].contains("mode"));
+		assert!(results[1].contains(", mode ") && !results[1].contains("estimated"));
+		for (result, roll) in results.iter().zip(&rolls) {
+			assert!(result.starts_with(roll.as_str()));
+		}
+		let args = Args::parse_from(["dicexp", "--json", "--stats", "--seed", "7", "100d100kh50"]);
+		let dist = crate::new_dice_bag(&args).sample_distribution("100d100kh50", crate::STATS_SAMPLES).unwrap();
+		let (results, _) = run(args).unwrap();
+		assert!(results[0].contains("\"exact\":false") && !results[0].contains("\"mode\""));
+		// every figure comes from the same sampled rolls, including the mean
+		let json: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+		assert!((json["stats"]["mean"].as_f64().unwrap() - dist.mean()).abs() < 1e-6);
+	}
+
+	#[test]
+	fn floor_average_checks() {
+		use crate::{Args, run};
+		use clap::Parser;
+		let (results, _) = run(Args::parse_from(["dicexp", "--color", "never", "--floor-average", "1d8+2"])).unwrap();
+		assert!(results[0].ends_with(" (6.5 ave., 6 floored)"));
+		let (results, _) = run(Args::parse_from(["dicexp", "--color", "never", "-r", "--floor-average", "5"])).unwrap();
+		assert_eq!(results, ["5 => 5 (5-5, 5.0 ave., 5 floored)"]);
+		let (results, _) = run(Args::parse_from(["dicexp", "--csv", "--floor-average", "4"])).unwrap();
+		assert_eq!(results, ["expression,total,min,max,average,floor_average", "4,4,4,4,4,4"]);
+		let (results, _) = run(Args::parse_from(["dicexp", "--markdown", "--floor-average", "1d2"])).unwrap();
+		assert!(results[1].ends_with(" | 1-2 | 1.5 (1) |"));
+		assert!(crate::sanity_check(&Args::parse_from(["dicexp", "-q", "--floor-average", "1d6"])).is_err());
+	}
+
+	#[test]
+	fn label_checks() {
+		use crate::{Args, run, split_label};
+		use clap::Parser;
+		assert_eq!(split_label("attack=1d20+7"), (Some("attack"), "1d20+7"));
+		assert_eq!(split_label("long_sword = 1d8 + 3"), (Some("long_sword"), "1d8 + 3"));
+		assert_eq!(split_label("Long Sword = 1d8 + 3"), (None, "Long Sword = 1d8 + 3"));
+		assert_eq!(split_label("1d20=5"), (None, "1d20=5"));
+		assert_eq!(split_label("1d20+7"), (None, "1d20+7"));
+		assert_eq!(split_label("1d20>=15"), (None, "1d20>=15"));
+		assert_eq!(split_label("1d6==6"), (None, "1d6==6"));
+		let (results, _) = run(Args::parse_from(["dicexp", "-r", "attack=1d20+7", "5"])).unwrap();
+		assert!(results[0].starts_with("attack => ") && results[0].ends_with(" (8-27)"));
+		assert_eq!(results[1], "5 => 5 (5-5)");
+		let (results, _) = run(Args::parse_from(["dicexp", "--json", "--max-only", "big=2d6"])).unwrap();
+		assert_eq!(results, ["{\"label\":\"big\",\"expression\":\"2d6\",\"max\":12}"]);
+		// not a label, so the parser reports the '='
+		let error = run(Args::parse_from(["dicexp", "1d20=5"])).unwrap_err();
+		assert!(error.to_string().contains("Unexpected '='"));
+	}
+
+	#[test]
+	fn markdown_checks() {
+		use crate::{Args, run};
+		use clap::Parser;
+		let (results, _) = run(Args::parse_from(["dicexp", "--markdown", "--sum", "5", "2*3[a|b]"])).unwrap();
+		assert_eq!(results, [
+			"| Expression | Total | Range | Average |\n| --- | ---: | :---: | ---: |",
+			"| 5 | 5 | 5-5 | 5 |",
+			"| 2*3[a\\|b] | 6 | 6-6 | 6 |",
+			"| **Sum** | **11** | | |"
+		]);
+		let (results, _) = run(Args::parse_from(["dicexp", "--markdown", "--dc", "6", "5"])).unwrap();
+		assert_eq!(results[1], "| 5 | 5 | 5-5 | 5 | FAILURE |");
+	}
+
+	#[test]
+	fn format_checks() {
+		use crate::Args;
+		use clap::Parser;
+		let mut args = Args::parse_from(["dicexp", "--format", "csv", "1d6"]);
+		args.apply_format();
+		assert!(args.csv && !args.json);
+		let mut args = Args::parse_from(["dicexp", "--format", "csv", "--json", "1d6"]);
+		args.apply_format();
+		assert!(args.json && !args.csv);
+		let mut args = Args::parse_from(["dicexp", "1d6"]);
+		args.apply_format();
+		assert!(!args.json && !args.csv && !args.markdown);
+	}
+
+	#[test]
+	fn table_checks() {
+		use crate::RandomTable;
+		use dicexp::{DiceBag, simple_rng};
+		use std::path::Path;
+		let table = RandomTable::parse("# loot\n01-50: gold\n51-99, a gem, cut\n00: {2d6*10} silver # lucky").unwrap();
+		assert_eq!(table.dice, "1d100");
+		assert_eq!(table.rows[0], (1, 50, "gold".to_string()));
+		assert_eq!(table.rows[1], (51, 99, "a gem, cut".to_string()));
+		assert_eq!(table.rows[2], (100, 100, "{2d6*10} silver".to_string()));
+		let mut dice = DiceBag::new(simple_rng(7));
+		for _ in 0..100 {
+			let (roll, result) = table.roll(&mut dice, Path::new("."), 0).unwrap();
+			match roll {
+				1..=50 => assert_eq!(result, "gold"),
+				51..=99 => assert_eq!(result, "a gem, cut"),
+				_ => assert!(result.ends_with("0 silver"))
+			}
+		}
+		let table = RandomTable::parse("dice: 1\n1: { {1d6, 1d8}kl1 } gold").unwrap();
+		assert!(table.roll(&mut dice, Path::new("."), 0).unwrap().1.ends_with(" gold"));
+		let table = RandomTable::parse("dice: 7\n1-6: nothing").unwrap();
+		assert!(table.roll(&mut dice, Path::new("."), 0).is_err());
+		assert!(RandomTable::parse("1-6 nothing").is_err());
+		assert!(RandomTable::parse("# empty").is_err());
+	}
+
+	#[test]
+	fn csv_checks() {
+		use crate::csv_field;
+		assert_eq!(csv_field("3d6+2"), "3d6+2");
+		assert_eq!(csv_field("a,b"), "\"a,b\"");
+		assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+	}
+
+	#[test]
+	fn bracket_checks() {
+		use crate::matching_bracket;
+		assert_eq!(matching_bracket("a {1d6} b", 2), Some(6));
+		assert_eq!(matching_bracket("{ {1d6, 1d8}kh1 }", 0), Some(16));
+		assert_eq!(matching_bracket("[goblins.txt]", 0), Some(12));
+		assert_eq!(matching_bracket("{ {1d6 }", 0), None);
+	}
+
+}
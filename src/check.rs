@@ -0,0 +1,142 @@
+use core::fmt::Formatter;
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+use crate::DiceRoll;
+
+/// How a roll against a target number (eg a difficulty class) turned out
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum Outcome {
+	/// The roll was the highest possible result, which succeeds even if it is below the target
+	CriticalSuccess,
+	/// The roll met or beat the target
+	Success,
+	/// The roll was below the target
+	Failure,
+	/// The roll was the lowest possible result, which fails even if it meets the target
+	CriticalFailure
+}
+
+impl Outcome {
+	/// Returns true for a success or critical success
+	pub fn is_success(&self) -> bool {
+		matches!(self, Outcome::CriticalSuccess | Outcome::Success)
+	}
+
+	/// Returns true for a critical success or critical failure
+	pub fn is_critical(&self) -> bool {
+		matches!(self, Outcome::CriticalSuccess | Outcome::CriticalFailure)
+	}
+}
+
+impl core::fmt::Display for Outcome {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", match self {
+			Outcome::CriticalSuccess => "critical success",
+			Outcome::Success => "success",
+			Outcome::Failure => "failure",
+			Outcome::CriticalFailure => "critical failure"
+		})
+	}
+}
+
+/// The result of rolling a dice expression against a target number (see `DiceBag.eval_vs(...)`)
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct VsRoll {
+	/// Whether the roll succeeded, and whether it was critical
+	pub outcome: Outcome,
+	/// How far the roll was above the target (or below it, if negative). A roll that exactly
+	/// meets the target has a margin of 0.
+	pub margin: i64,
+	/// The total, min, max, and average of the roll
+	pub roll: DiceRoll
+}
+
+impl VsRoll {
+	/// Works out the outcome of a roll against the target number. A roll succeeds if it meets or
+	/// beats the target, and is critical if it is the highest or lowest possible result (eg a
+	/// natural 20 or natural 1 on "1d20+5"), unless the expression can only have one result.
+	/// # Parameters
+	/// * `roll`: the rolled dice expression
+	/// * `target`: the number to meet or beat (eg a difficulty class)
+	pub fn new(roll: DiceRoll, target: i64) -> Self {
+		let outcome = match roll.total >= target {
+			_ if roll.min < roll.max && roll.total == roll.max => Outcome::CriticalSuccess,
+			_ if roll.min < roll.max && roll.total == roll.min => Outcome::CriticalFailure,
+			true => Outcome::Success,
+			false => Outcome::Failure
+		};
+		VsRoll{outcome, margin: roll.total.saturating_sub(target), roll}
+	}
+}
+
+impl core::fmt::Display for VsRoll {
+	/// core::fmt::Display implementation returns the total result and the outcome (eg "17 (success)")
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{} ({})", self.roll.total, self.outcome)
+	}
+}
+
+/// Which side won an opposed roll (see `DiceBag.opposed(...)`)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum Winner {
+	/// The first expression rolled higher
+	A,
+	/// The second expression rolled higher
+	B,
+	/// Both expressions rolled the same total
+	Tie
+}
+
+/// How to settle an opposed roll when both sides roll the same total
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum TieBreak {
+	/// Leave the result as a tie
+	#[default]
+	Tie,
+	/// The first expression wins ties (eg the defender, if it is rolled first)
+	FavorA,
+	/// The second expression wins ties
+	FavorB,
+	/// Roll both expressions again until one of them wins (or until it is clear that they can
+	/// only ever tie)
+	Reroll
+}
+
+/// The result of rolling two dice expressions against each other
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct OpposedRoll {
+	/// The roll of the first expression
+	pub a: DiceRoll,
+	/// The roll of the second expression
+	pub b: DiceRoll,
+	/// Which side won
+	pub winner: Winner,
+	/// How much higher the first expression rolled than the second (negative if it rolled lower)
+	pub margin: i64
+}
+
+impl OpposedRoll {
+	/// Works out the winner of an opposed roll
+	/// # Parameters
+	/// * `a`: the roll of the first expression
+	/// * `b`: the roll of the second expression
+	/// * `tie_break`: who wins if both rolled the same total (`TieBreak::Reroll` is treated
+	///   as `TieBreak::Tie`, since there is nothing to reroll here)
+	pub fn new(a: DiceRoll, b: DiceRoll, tie_break: TieBreak) -> Self {
+		let winner = match a.total.cmp(&b.total) {
+			core::cmp::Ordering::Greater => Winner::A,
+			core::cmp::Ordering::Less => Winner::B,
+			core::cmp::Ordering::Equal => match tie_break {
+				TieBreak::FavorA => Winner::A,
+				TieBreak::FavorB => Winner::B,
+				_ => Winner::Tie
+			}
+		};
+		OpposedRoll{margin: a.total.saturating_sub(b.total), a, b, winner}
+	}
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+/// The probability distribution of the possible results of a dice expression, as a map of each
+/// result to the probability of rolling it
+///
+/// # Example
+/// ```
+/// use dicexp::{DiceBag, simple_rng};
+/// let mut dice_bag = DiceBag::new(simple_rng(42));
+/// let dist = dice_bag.sample_distribution("2d6", 10000).expect("Error");
+/// println!("mean: {:.2}, std. dev.: {:.2}", dist.mean(), dist.std_dev());
+/// for (result, probability) in dist.iter() {
+///     println!("{}\t{:.1}%", result, 100. * probability);
+/// }
+/// ```
+#[derive(Clone, PartialEq, Default, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Distribution {
+	probabilities: BTreeMap<i64, f64>
+}
+
+impl Distribution {
+	/// Builds the observed distribution from a set of sampled results
+	/// # Parameters
+	/// * `samples`: the results of evaluating a dice expression many times
+	pub fn from_samples<I>(samples: I) -> Self where I: IntoIterator<Item=i64> {
+		let mut counts: BTreeMap<i64, u64> = BTreeMap::new();
+		let mut total = 0u64;
+		for sample in samples {
+			*counts.entry(sample).or_insert(0) += 1;
+			total += 1;
+		}
+		Distribution{
+			probabilities: counts.into_iter().map(|(k, n)| (k, n as f64 / total as f64)).collect()
+		}
+	}
+
+	/// Builds a distribution from the exact probability of each result
+	pub(crate) fn from_probabilities(probabilities: BTreeMap<i64, f64>) -> Self {
+		Distribution{probabilities: probabilities.into_iter().filter(|(_, p)| *p > 0.).collect()}
+	}
+
+	/// Returns the probability of the given result (0 if the result is not possible)
+	pub fn probability(&self, result: i64) -> f64 {
+		self.probabilities.get(&result).copied().unwrap_or(0.)
+	}
+
+	/// Iterates over every possible result and its probability, from smallest to largest result
+	pub fn iter(&self) -> impl Iterator<Item=(i64, f64)> + '_ {
+		self.probabilities.iter().map(|(k, p)| (*k, *p))
+	}
+
+	/// Returns every possible result and its probability as (result, probability) points, from
+	/// smallest to largest result, ready to be plotted
+	pub fn to_points(&self) -> Vec<(i64, f64)> {
+		self.iter().collect()
+	}
+
+	/// Writes out the distribution as comma-separated values (CSV) for a spreadsheet, with a
+	/// "result,probability" header row and then one row per possible result, from smallest to
+	/// largest
+	pub fn to_csv(&self) -> String {
+		let mut csv = String::from("result,probability\n");
+		for (result, p) in self.iter() {
+			csv.push_str(&format!("{},{}\n", result, p));
+		}
+		csv
+	}
+
+	/// Returns true if there are no results in this distribution
+	pub fn is_empty(&self) -> bool {
+		self.probabilities.is_empty()
+	}
+
+	/// Returns the smallest possible result, or `None` if the distribution is empty
+	pub fn min(&self) -> Option<i64> {
+		self.probabilities.keys().next().copied()
+	}
+
+	/// Returns the largest possible result, or `None` if the distribution is empty
+	pub fn max(&self) -> Option<i64> {
+		self.probabilities.keys().next_back().copied()
+	}
+
+	/// Returns the mean average result
+	pub fn mean(&self) -> f64 {
+		self.iter().map(|(k, p)| k as f64 * p).sum()
+	}
+
+	/// Returns the median result (the smallest result that is at least as likely to be rolled over
+	/// as under), or `None` if the distribution is empty
+	pub fn median(&self) -> Option<i64> {
+		let mut cumulative = 0.;
+		for (k, p) in self.iter() {
+			cumulative += p;
+			// allowing for rounding error in the sum, so that 3d6 has a median of 10 (not 11)
+			if cumulative >= 0.5 - 1e-9 {return Some(k);}
+		}
+		self.max()
+	}
+
+	/// Returns the most likely result (the smallest one if there is a tie), or `None` if the
+	/// distribution is empty
+	pub fn mode(&self) -> Option<i64> {
+		let mut mode: Option<(i64, f64)> = None;
+		for (k, p) in self.iter() {
+			if mode.is_none_or(|(_, best)| p > best) {
+				mode = Some((k, p));
+			}
+		}
+		mode.map(|(k, _)| k)
+	}
+
+	/// Returns every result that is tied for most likely, from smallest to largest (eg both 10
+	/// and 11 for "3d6"), or an empty `Vec` if the distribution is empty
+	pub fn modes(&self) -> Vec<i64> {
+		let best = self.iter().map(|(_, p)| p).fold(0., f64::max);
+		// exact probabilities can differ by rounding error when they should be equal
+		self.iter().filter(|(_, p)| best - p <= best * 1e-9).map(|(k, _)| k).collect()
+	}
+
+	/// Returns the probability of rolling the given result or higher
+	pub fn prob_at_least(&self, result: i64) -> f64 {
+		self.probabilities.range(result..).map(|(_, p)| *p).sum()
+	}
+
+	/// Returns the probability that a result from this distribution is greater than an independent
+	/// result from the other distribution
+	/// # Parameters
+	/// * `other`: the distribution to compare against
+	pub fn prob_greater_than(&self, other: &Distribution) -> f64 {
+		self.iter().map(|(k, p)| p * other.probabilities.range(..k).map(|(_, q)| *q).sum::<f64>()).sum()
+	}
+
+	/// Returns the standard deviation of the results
+	pub fn std_dev(&self) -> f64 {
+		let mean = self.mean();
+		self.iter().map(|(k, p)| p * (k as f64 - mean).powi(2)).sum::<f64>().sqrt()
+	}
+}
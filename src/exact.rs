@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedMul, Zero};
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+use crate::{expression, DiceBag, DieRoller, SyntaxError};
+
+/// The result of rolling a dice expression with `DiceBag.eval_exact(...)`, where the average and
+/// the probability of each result are exact fractions instead of floating-point numbers (eg the
+/// average of "1d6/2" is exactly 3/2)
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct ExactRoll {
+	/// The amount rolled
+	pub total: i64,
+	/// Minimum possible result
+	pub min: i64,
+	/// Maximum possible result
+	pub max: i64,
+	/// Average result
+	pub average: Rational64,
+	/// The probability of every possible result
+	pub probabilities: BTreeMap<i64, Rational64>
+}
+
+impl ExactRoll {
+	/// Returns the probability of the given result (0 if the result is not possible)
+	pub fn probability(&self, result: i64) -> Rational64 {
+		self.probabilities.get(&result).copied().unwrap_or_else(Rational64::zero)
+	}
+}
+
+impl core::fmt::Display for ExactRoll {
+	/// core::fmt::Display implementation returns the total result
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
+		std::fmt::Display::fmt(&self.total, f)
+	}
+}
+
+impl<R> DiceBag<R> where R: DieRoller {
+	/// Evaluates the given RPG dice notation expression, working out the average and the
+	/// probability of every possible result as exact fractions. Unlike `DiceBag.eval(...)`, the
+	/// average of a division is the average of the rolled (rounded down) results, so the average
+	/// of "1d6/2" is 3/2 rather than 7/4. Returns an error if there are too many combinations of
+	/// dice to work out (eg "100d100").
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	pub fn eval_exact(&mut self, dice_expression: &str) -> Result<ExactRoll,SyntaxError>{
+		expression_span!("eval_exact", expression = dice_expression);
+		let mut steps = self.steps();
+		let expr = expression::parse(dice_expression, &self.plugins, &mut steps)?;
+		let total = expr.roll(&mut self.rng, &mut None, &mut steps)?;
+		let probabilities: BTreeMap<i64, Rational64> = expr.pmf().ok_or_else(|| SyntaxError::from(
+			"Too many possible results to work them out exactly"
+		))?;
+		let average = probabilities.iter().try_fold(Rational64::zero(), |sum, (result, p)| {
+			sum.checked_add(&p.checked_mul(&Rational64::from_integer(*result))?)
+		}).ok_or_else(|| SyntaxError::from("Integer overflow (result is too big)"))?;
+		Ok(ExactRoll{
+			total,
+			min: probabilities.keys().next().copied().unwrap_or(total),
+			max: probabilities.keys().next_back().copied().unwrap_or(total),
+			average,
+			probabilities
+		})
+	}
+}
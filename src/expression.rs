@@ -0,0 +1,2507 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use crate::{DieRoller, ErrorKind, RolledDice, SyntaxDiagnostic, SyntaxError};
+use crate::plugin::{Callback, FunctionFn, ModifierFn, Plugins};
+
+/// Position of a node in the nodes of a parsed expression
+pub(crate) type NodeId = u32;
+
+/// A parsed dice expression, ready to be rolled or analyzed. The terms of the expression are kept
+/// in one flat list and refer to each other by position, so that parsing an expression fills a
+/// couple of buffers instead of allocating every term separately.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Expr {
+	nodes: Vec<Node>,
+	/// The members of every group and the arguments of every function call, each being a range
+	/// of this list
+	members: Vec<NodeId>,
+	/// The custom functions called by the expression, and their names
+	functions: Vec<(String, Callback<FunctionFn>)>,
+	/// The custom dice modifiers used by the expression, and their names
+	modifiers: Vec<(String, Callback<ModifierFn>)>,
+	root: NodeId
+}
+
+impl core::fmt::Display for Expr {
+	/// Writes out the expression in dice notation, which parses back into the same expression
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.write_str(&self.write(Notation::Plain))
+	}
+}
+
+/// A term of a parsed dice expression
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum Node {
+	/// A literal integer
+	Number(i64),
+	/// A literal decimal number, which can only be averaged (see `parse_decimal(...)`)
+	Decimal(f64),
+	/// A dice term (eg "3d6", "(1d4)d6", or "4d6kh3"), where `span` is its position in the
+	/// expression text if the number of dice, the number of sides, and the keep or drop count (if
+	/// any) are all written as literal numbers
+	Dice{count: NodeId, sides: NodeId, keep: Option<Keep>, span: Option<(usize, usize)>},
+	/// Negation
+	Neg(NodeId),
+	/// An arithmetic operation or comparison
+	Binary(BinOp, NodeId, NodeId),
+	/// `condition ? then : otherwise`, where any non-zero condition counts as true
+	Ternary(NodeId, NodeId, NodeId),
+	/// A group of expressions whose results are added up (eg "{4d6+2, 3d8, 1d20}kh1"), where a
+	/// keep or drop modifier picks from the results of the members rather than individual dice
+	Group{members: Range<usize>, keep: Option<Keep>},
+	/// A term tagged with a type of damage (eg "2d6[fire]"), whose results are added up by tag
+	Tagged(NodeId, String),
+	/// A dice term with a custom modifier (eg "6d10s8"), where `modifier` is the position of the
+	/// modifier in `Expr.modifiers`
+	CustomDice{count: NodeId, sides: NodeId, modifier: usize, arg: Option<NodeId>},
+	/// A call to a custom function (eg "max(1d20, 1d20)"), where `function` is the position of
+	/// the function in `Expr.functions`
+	Call{function: usize, args: Range<usize>},
+	/// A named variable (eg "atk" in "atk>=16 ? dmg : 0"), which is only allowed in expressions
+	/// parsed with `parse_with_variables(...)` and must be given a value with
+	/// `Expr::partial_eval(...)` before the expression can be rolled
+	Var(String)
+}
+
+/// Arithmetic and comparison operators (comparisons result in 1 if true and 0 if false)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum BinOp {
+	Add, Sub, Mul, Div, Gt, Ge, Lt, Le, Eq, Ne
+}
+
+/// How tightly each kind of node binds when written out in dice notation, from loosest to
+/// tightest (the same order as the parser's precedence levels)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Precedence {
+	Ternary, Comparison, Sum, Product, Unary, Tagged, Dice, Primary
+}
+
+/// The ways that an expression can be written out
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Notation {
+	/// Dice notation, which can be parsed again (eg "1d20+5 >= 15")
+	Plain,
+	/// LaTeX math mode (eg "1\mathrm{d}20 + 5 \geq 15")
+	Latex,
+	/// Unicode math symbols (eg "1d20 + 5 ≥ 15")
+	Unicode
+}
+
+impl Notation {
+	fn parens(&self, text: &str) -> String {
+		match self {
+			Notation::Latex => format!("\\left({}\\right)", text),
+			_ => format!("({})", text)
+		}
+	}
+
+	fn braces(&self) -> (&'static str, &'static str) {
+		match self {
+			Notation::Latex => ("\\{", "\\}"),
+			_ => ("{", "}")
+		}
+	}
+
+	fn minus(&self) -> &'static str {
+		match self {
+			Notation::Unicode => "\u{2212}",
+			_ => "-"
+		}
+	}
+
+	fn question(&self) -> &'static str {
+		match self {
+			Notation::Latex => " \\mathrel{?} ",
+			_ => " ? "
+		}
+	}
+
+	fn op(&self, op: BinOp) -> &'static str {
+		match (self, op) {
+			(Notation::Plain, op) => op.symbol(),
+			(_, BinOp::Add) => " + ",
+			(Notation::Latex, BinOp::Sub) => " - ",
+			(Notation::Latex, BinOp::Mul) => " \\cdot ",
+			(Notation::Latex, BinOp::Ge) => " \\geq ",
+			(Notation::Latex, BinOp::Le) => " \\leq ",
+			(Notation::Latex, BinOp::Eq) => " = ",
+			(Notation::Latex, BinOp::Ne) => " \\neq ",
+			(Notation::Unicode, BinOp::Sub) => " \u{2212} ",
+			(Notation::Unicode, BinOp::Mul) => " \u{22C5} ",
+			(Notation::Unicode, BinOp::Div) => " \u{F7} ",
+			(Notation::Unicode, BinOp::Ge) => " \u{2265} ",
+			(Notation::Unicode, BinOp::Le) => " \u{2264} ",
+			(Notation::Unicode, BinOp::Eq) => " = ",
+			(Notation::Unicode, BinOp::Ne) => " \u{2260} ",
+			(_, op) => op.symbol()
+		}
+	}
+
+	/// Writes out letters that are part of the notation (eg the "d" of "3d6")
+	fn text(&self, text: &str) -> String {
+		match self {
+			Notation::Latex => format!("\\mathrm{{{}}}", latex_escape(text)),
+			_ => text.to_string()
+		}
+	}
+
+	fn name(&self, name: &str) -> String {
+		match self {
+			Notation::Latex => format!("\\mathit{{{}}}", latex_escape(name)),
+			_ => name.to_string()
+		}
+	}
+
+	fn function(&self, name: &str) -> String {
+		match self {
+			Notation::Latex => format!("\\operatorname{{{}}}", latex_escape(name)),
+			_ => name.to_string()
+		}
+	}
+
+	fn tag(&self, tag: &str) -> String {
+		match self {
+			Notation::Latex => format!("\\,[\\text{{{}}}]", latex_escape(tag)),
+			Notation::Unicode => format!(" [{}]", tag),
+			Notation::Plain => format!("[{}]", tag)
+		}
+	}
+}
+
+/// Escapes the characters that have a special meaning in LaTeX
+fn latex_escape(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+				escaped.push('\\');
+				escaped.push(c);
+			}
+			'~' => escaped.push_str("\\textasciitilde{}"),
+			'^' => escaped.push_str("\\textasciicircum{}"),
+			'\\' => escaped.push_str("\\textbackslash{}"),
+			_ => escaped.push(c)
+		}
+	}
+	escaped
+}
+
+/// A keep or drop modifier (eg the "kh3" of "4d6kh3"), where the number of dice (or group
+/// members) to keep or drop can itself be an expression (eg "6d6kh(1d3)")
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) struct Keep {
+	pub mode: KeepMode,
+	pub count: NodeId
+}
+
+/// Keep the highest (kh or k), keep the lowest (kl), drop the highest (dh), or drop the lowest (dl)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum KeepMode {
+	KeepHighest, KeepLowest, DropHighest, DropLowest
+}
+
+/// The dice rolled while rolling an expression
+#[derive(Default, Debug)]
+pub(crate) struct Rolls {
+	/// Every dice term that was rolled, in the order that they were rolled
+	pub dice: Vec<RolledDice>,
+	/// The position in the expression text of each dice term written with literal numbers, and
+	/// its index in `dice`
+	pub literals: Vec<(usize, usize, usize)>,
+	/// The result of every tagged term that was rolled (eg "2d6[fire]"), and its tag
+	pub tags: Vec<(String, i64)>
+}
+
+/// Results that were rolled ahead of the rest of a part of an expression (see
+/// `Expr::roll_part(...)`), in the order that they are needed
+#[derive(Default)]
+struct RolledAhead {
+	/// The totals of the dice terms written with literal numbers
+	dice: std::vec::IntoIter<i64>,
+	/// The results of the conditional expressions
+	conditionals: std::vec::IntoIter<i64>
+}
+
+/// Limit on the number of possible results when working out the exact probabilities of an
+/// expression (eg to average a conditional expression)
+const MAX_OUTCOMES: usize = 100_000;
+
+/// Limit on the number of arguments of a custom function when estimating its range, which tries
+/// every combination of the extremes of its arguments
+const MAX_PLUGIN_ARGS: usize = 16;
+
+/// Counts the steps taken to parse and roll an expression (one for every token parsed, every part
+/// of the expression rolled, and every die rolled), to stop once a `DiceBag`'s step limit is reached
+#[derive(Clone, Copy, Default, Debug)]
+pub(crate) struct Steps {
+	taken: u64,
+	limit: Option<u64>
+}
+
+impl Steps {
+	pub fn new(limit: Option<u64>) -> Self {
+		Steps{taken: 0, limit}
+	}
+
+	/// Takes the given number of steps, returning an error if that goes over the limit
+	fn take(&mut self, n: u64) -> Result<(), SyntaxError> {
+		self.taken = self.taken.saturating_add(n);
+		match self.limit {
+			Some(limit) if self.taken > limit => Err(SyntaxError{
+				kind: ErrorKind::StepLimit,
+				..SyntaxError::from_string(format!("Evaluation took more than {} steps", limit))
+			}),
+			_ => Ok(())
+		}
+	}
+}
+
+/// Probability of each possible result of an expression
+pub(crate) type Pmf<P = f64> = BTreeMap<i64, P>;
+
+/// A probability, which is either a float or (with the `exact` feature) an exact fraction. Exact
+/// fractions can overflow, so the arithmetic returns `None` when the result cannot be represented.
+pub(crate) trait Probability: Copy {
+	fn zero() -> Self;
+	fn one() -> Self;
+	fn is_positive(&self) -> bool;
+	fn add(self, other: Self) -> Option<Self>;
+	fn sub(self, other: Self) -> Option<Self>;
+	fn mul(self, other: Self) -> Option<Self>;
+	/// Divides by a whole number
+	fn div(self, n: usize) -> Option<Self>;
+}
+
+impl Probability for f64 {
+	fn zero() -> Self {0.}
+	fn one() -> Self {1.}
+	fn is_positive(&self) -> bool {*self > 0.}
+	fn add(self, other: Self) -> Option<Self> {Some(self + other)}
+	fn sub(self, other: Self) -> Option<Self> {Some(self - other)}
+	fn mul(self, other: Self) -> Option<Self> {Some(self * other)}
+	fn div(self, n: usize) -> Option<Self> {Some(self / n as f64)}
+}
+
+#[cfg(feature = "exact")]
+impl Probability for num_rational::Rational64 {
+	fn zero() -> Self {num_traits::Zero::zero()}
+	fn one() -> Self {num_traits::One::one()}
+	fn is_positive(&self) -> bool {num_traits::Signed::is_positive(self)}
+	fn add(self, other: Self) -> Option<Self> {num_traits::CheckedAdd::checked_add(&self, &other)}
+	fn sub(self, other: Self) -> Option<Self> {num_traits::CheckedSub::checked_sub(&self, &other)}
+	fn mul(self, other: Self) -> Option<Self> {num_traits::CheckedMul::checked_mul(&self, &other)}
+	fn div(self, n: usize) -> Option<Self> {
+		num_traits::CheckedDiv::checked_div(&self, &Self::from_integer(i64::try_from(n).ok()?))
+	}
+}
+
+/// Parses a dice expression, which can use the given custom functions and dice modifiers
+pub(crate) fn parse(text: &str, plugins: &Plugins, steps: &mut Steps) -> Result<Expr, SyntaxError> {
+	parse_text(text, false, Variables::None, plugins, steps)
+}
+
+/// Parses a dice expression that may use variables (eg "1d20+prof"), which cannot be rolled until
+/// they are given values
+pub(crate) fn parse_with_variables(text: &str, plugins: &Plugins, steps: &mut Steps) -> Result<Expr, SyntaxError> {
+	parse_text(text, false, Variables::Unbound, plugins, steps)
+}
+
+/// Parses a dice expression that may use variables that already have values (eg the variables
+/// assigned by the earlier statements of a script), which are replaced by their values as they
+/// are parsed. A variable without a value is an error at the place it is used.
+pub(crate) fn parse_with_bindings(text: &str, plugins: &Plugins, steps: &mut Steps, value: &dyn Fn(&str) -> Option<i64>) -> Result<Expr, SyntaxError> {
+	parse_text(text, false, Variables::Bound(value), plugins, steps)
+}
+
+/// Parses a dice expression that may have decimal numbers in it (eg "1d6*1.5"), which can only be
+/// averaged
+pub(crate) fn parse_decimal(text: &str, plugins: &Plugins, steps: &mut Steps) -> Result<Expr, SyntaxError> {
+	parse_text(text, true, Variables::None, plugins, steps)
+}
+
+fn parse_text(text: &str, decimals: bool, variables: Variables, plugins: &Plugins, steps: &mut Steps) -> Result<Expr, SyntaxError> {
+	#[cfg(feature = "tracing")]
+	tracing::debug!(expression = text, "parsing dice expression");
+	let parsed = Parser::new(text, decimals, variables, plugins, steps).and_then(|mut parser| parser.expression(false));
+	#[cfg(feature = "tracing")]
+	match &parsed {
+		Ok(expr) => tracing::debug!(nodes = expr.nodes.len(), "parsed dice expression"),
+		Err(error) => tracing::debug!(%error, "failed to parse dice expression")
+	}
+	parsed
+}
+
+/// Parses a dice expression (that may use variables) without stopping at the first error,
+/// returning every error found in order (or none if it parses). A `statement` of a script skips
+/// the rest of the text after anything unexpected, rather than carrying on to find more errors.
+pub(crate) fn parse_errors(text: &str, plugins: &Plugins, statement: bool) -> Vec<SyntaxDiagnostic> {
+	let mut errors = Some(Vec::new());
+	let tokens = tokenize(text, false, plugins, &mut errors).unwrap_or_default();
+	let mut parser = Parser::from_tokens(text, tokens, Variables::Unbound, plugins, errors);
+	let parsed = parser.expression(statement);
+	let mut errors = parser.errors.take().unwrap_or_default();
+	// every error is collected rather than returned, but just in case
+	if let Err(error) = parsed {
+		let token = parser.peek();
+		errors.push(SyntaxDiagnostic{error, start: token.start, end: token.end});
+	}
+	errors.sort_by_key(|diagnostic| diagnostic.start);
+	errors
+}
+
+/// Records an error if every error is being collected, so that parsing can carry on, or
+/// otherwise returns it
+fn report(errors: &mut Option<Vec<SyntaxDiagnostic>>, error: SyntaxError, start: usize, end: usize) -> Result<(), SyntaxError> {
+	match errors {
+		Some(errors) => {
+			errors.push(SyntaxDiagnostic{error, start, end});
+			Ok(())
+		}
+		None => Err(error)
+	}
+}
+
+impl Expr {
+	fn node(&self, id: NodeId) -> &Node {
+		&self.nodes[id as usize]
+	}
+
+	fn members(&self, members: &Range<usize>) -> &[NodeId] {
+		&self.members[members.clone()]
+	}
+
+	/// Rolls the expression, recording the dice rolled in `rolls` (if given). Only the dice on the
+	/// taken side of a conditional expression are rolled.
+	pub(crate) fn roll<R: DieRoller + ?Sized>(&self, rng: &mut R, rolls: &mut Option<&mut Rolls>, steps: &mut Steps) -> Result<i64, SyntaxError> {
+		self.roll_part(self.root, rng, rolls, steps)
+	}
+
+	/// Rolls a part of the expression that is rolled on its own (the whole expression, or the
+	/// condition or a side of a conditional expression). Dice terms written with literal numbers
+	/// (eg "3d6") are rolled first, from left to right, then the conditional expressions inside it,
+	/// then whatever dice remain (eg "(1d4)d6") as the rest of it is worked out.
+	fn roll_part<R: DieRoller + ?Sized>(&self, id: NodeId, rng: &mut R, rolls: &mut Option<&mut Rolls>, steps: &mut Steps) -> Result<i64, SyntaxError> {
+		if let Node::Ternary(condition, then, otherwise) = self.node(id) {
+			return match self.roll_part(*condition, rng, rolls, steps)? {
+				0 => self.roll_part(*otherwise, rng, rolls, steps),
+				_ => self.roll_part(*then, rng, rolls, steps)
+			};
+		}
+		// nothing needs to be rolled ahead if every dice term is literal and there are no
+		// conditional expressions, since then everything is rolled from left to right anyway
+		let mut in_order = true;
+		self.each_node(id, &mut |_, node| {
+			in_order &= !matches!(node, Node::Ternary(..) | Node::Dice{span: None, ..});
+			Ok(())
+		})?;
+		if in_order {
+			return self.roll_node(id, rng, rolls, &mut RolledAhead::default(), steps);
+		}
+		let mut dice: Vec<i64> = Vec::new();
+		self.each_node(id, &mut |literal, node| {
+			if matches!(node, Node::Dice{span: Some(_), ..}) {
+				dice.push(self.roll_node(literal, rng, rolls, &mut RolledAhead::default(), steps)?);
+			}
+			Ok(())
+		})?;
+		let mut conditionals: Vec<i64> = Vec::new();
+		self.each_node(id, &mut |conditional, node| {
+			if matches!(node, Node::Ternary(..)) {
+				conditionals.push(self.roll_part(conditional, rng, rolls, steps)?);
+			}
+			Ok(())
+		})?;
+		self.roll_node(id, rng, rolls, &mut RolledAhead{dice: dice.into_iter(), conditionals: conditionals.into_iter()}, steps)
+	}
+
+	/// Calls `f` for the node and every node inside it, from left to right, except for the nodes
+	/// inside conditional expressions
+	fn each_node(&self, id: NodeId, f: &mut impl FnMut(NodeId, &Node) -> Result<(), SyntaxError>) -> Result<(), SyntaxError> {
+		let node = self.node(id);
+		f(id, node)?;
+		match node {
+			Node::Number(_) | Node::Decimal(_) | Node::Ternary(..) | Node::Var(_) => Ok(()),
+			Node::Dice{count, sides, keep, ..} => {
+				self.each_node(*count, f)?;
+				self.each_node(*sides, f)?;
+				match keep {
+					Some(keep) => self.each_node(keep.count, f),
+					None => Ok(())
+				}
+			}
+			Node::Neg(x) | Node::Tagged(x, _) => self.each_node(*x, f),
+			Node::Binary(_, left, right) => {
+				self.each_node(*left, f)?;
+				self.each_node(*right, f)
+			}
+			Node::Group{members, keep} => {
+				for member in self.members(members) {
+					self.each_node(*member, f)?;
+				}
+				match keep {
+					Some(keep) => self.each_node(keep.count, f),
+					None => Ok(())
+				}
+			}
+			Node::CustomDice{count, sides, arg, ..} => {
+				self.each_node(*count, f)?;
+				self.each_node(*sides, f)?;
+				match arg {
+					Some(arg) => self.each_node(*arg, f),
+					None => Ok(())
+				}
+			}
+			Node::Call{args, ..} => {
+				for arg in self.members(args) {
+					self.each_node(*arg, f)?;
+				}
+				Ok(())
+			}
+		}
+	}
+
+	fn roll_node<R: DieRoller + ?Sized>(&self, id: NodeId, rng: &mut R, rolls: &mut Option<&mut Rolls>, ahead: &mut RolledAhead, steps: &mut Steps) -> Result<i64, SyntaxError> {
+		steps.take(1)?;
+		match self.node(id) {
+			Node::Number(n) => Ok(*n),
+			Node::Decimal(_) => Err(decimal()),
+			Node::Dice{span: Some(_), ..} if !ahead.dice.as_slice().is_empty() => Ok(ahead.dice.next().unwrap_or(0)),
+			Node::Dice{count, sides, keep, span} => {
+				let n = dice_count(self.roll_node(*count, rng, rolls, ahead, steps)?)?;
+				let d = dice_sides(self.roll_node(*sides, rng, rolls, ahead, steps)?)?;
+				steps.take(n as u64)?;
+				let keep = match keep {
+					Some(keep) => Some((keep.mode, keep.mode.kept(n as usize, self.roll_node(keep.count, rng, rolls, ahead, steps)?)?)),
+					None => None
+				};
+				let Some(rolls) = rolls else {
+					// nothing to record, so the dice only need to be kept around to drop some of them
+					return match keep {
+						None => (0..n).try_fold(0i64, |total, _| Ok(total + roll_die(rng, d)? as i64)),
+						Some((mode, kept)) => {
+							let mut results = (0..n).map(|_| Ok(roll_die(rng, d)? as i64)).collect::<Result<Vec<i64>, SyntaxError>>()?;
+							results.sort_unstable();
+							kept_sum(&results, kept, mode.highest()).ok_or_else(overflow)
+						}
+					};
+				};
+				let results = (0..n).map(|_| roll_die(rng, d)).collect::<Result<Vec<u32>, SyntaxError>>()?;
+				let dropped = match keep {
+					Some((mode, kept)) => mode.dropped(&results, kept),
+					None => Vec::new()
+				};
+				let dice = RolledDice{count: n, sides: d, rolls: results, dropped};
+				let total = dice.total();
+				if let Some((start, end)) = span {
+					rolls.literals.push((*start, *end, rolls.dice.len()));
+				}
+				rolls.dice.push(dice);
+				Ok(total)
+			}
+			Node::Neg(x) => self.roll_node(*x, rng, rolls, ahead, steps)?.checked_neg().ok_or_else(overflow),
+			Node::Binary(op, left, right) => {
+				let left = self.roll_node(*left, rng, rolls, ahead, steps)?;
+				let right = self.roll_node(*right, rng, rolls, ahead, steps)?;
+				let result = op.apply(left, right);
+				#[cfg(feature = "tracing")]
+				if let Ok(result) = result {
+					tracing::trace!(?op, left, right, result, "applied operator");
+				}
+				result
+			}
+			Node::Ternary(..) => match ahead.conditionals.next() {
+				Some(result) => Ok(result),
+				None => self.roll_part(id, rng, rolls, steps)
+			},
+			Node::Group{members, keep: None} => self.members(members).iter()
+				.try_fold(0i64, |total, member| total.checked_add(self.roll_node(*member, rng, rolls, ahead, steps)?).ok_or_else(overflow)),
+			Node::Group{members, keep: Some(keep)} => {
+				let members = self.members(members);
+				let mut results: Vec<i64> = Vec::with_capacity(members.len());
+				// where each member's tagged terms are in `rolls.tags`
+				let mut tagged: Vec<(usize, usize)> = Vec::new();
+				for member in members {
+					let start = rolls.as_ref().map_or(0, |rolls| rolls.tags.len());
+					results.push(self.roll_node(*member, rng, rolls, ahead, steps)?);
+					if let Some(rolls) = rolls {
+						tagged.push((start, rolls.tags.len()));
+					}
+				}
+				let kept = keep.mode.kept(results.len(), self.roll_node(keep.count, rng, rolls, ahead, steps)?)?;
+				let Some(rolls) = rolls else {
+					results.sort_unstable();
+					return kept_sum(&results, kept, keep.mode.highest()).ok_or_else(overflow);
+				};
+				let dropped = keep.mode.dropped(&results, kept);
+				// the tagged terms of dropped members do not count toward their tags
+				for i in dropped.iter().rev() {
+					rolls.tags.drain(tagged[*i].0..tagged[*i].1);
+				}
+				results.iter().enumerate().filter(|(i, _)| dropped.binary_search(i).is_err())
+					.try_fold(0i64, |total, (_, r)| total.checked_add(*r).ok_or_else(overflow))
+			}
+			Node::Tagged(x, tag) => {
+				let total = self.roll_node(*x, rng, rolls, ahead, steps)?;
+				if let Some(rolls) = rolls {
+					rolls.tags.push((tag.clone(), total));
+				}
+				Ok(total)
+			}
+			Node::CustomDice{count, sides, modifier, arg} => {
+				let n = dice_count(self.roll_node(*count, rng, rolls, ahead, steps)?)?;
+				let d = dice_sides(self.roll_node(*sides, rng, rolls, ahead, steps)?)?;
+				steps.take(n as u64)?;
+				let arg = match arg {
+					Some(arg) => Some(self.roll_node(*arg, rng, rolls, ahead, steps)?),
+					None => None
+				};
+				let results = (0..n).map(|_| roll_die(rng, d)).collect::<Result<Vec<u32>, SyntaxError>>()?;
+				let total = (self.modifiers[*modifier].1.0)(&results, d, arg)?;
+				if let Some(rolls) = rolls {
+					rolls.dice.push(RolledDice{count: n, sides: d, rolls: results, dropped: Vec::new()});
+				}
+				Ok(total)
+			}
+			Node::Call{function, args} => {
+				let args = self.members(args).iter().map(|arg| self.roll_node(*arg, rng, rolls, ahead, steps))
+					.collect::<Result<Vec<i64>, SyntaxError>>()?;
+				(self.functions[*function].1.0)(&args)
+			}
+			Node::Var(name) => Err(unknown_variable(name))
+		}
+	}
+
+	/// Returns the result if every die rolled its lowest (ie all 1's) and the result if every die
+	/// rolled its highest. These are the minimum and maximum possible results of most expressions,
+	/// though not of every expression (eg "5-1d6" is 4 when every die rolls its lowest).
+	pub(crate) fn range(&self) -> Result<(i64, i64), SyntaxError> {
+		self.range_of(self.root)
+	}
+
+	fn range_of(&self, id: NodeId) -> Result<(i64, i64), SyntaxError> {
+		match self.node(id) {
+			Node::Number(n) => Ok((*n, *n)),
+			Node::Decimal(_) => Err(decimal()),
+			Node::Dice{count, sides, keep, ..} => {
+				let (count_min, count_max) = self.range_of(*count)?;
+				let (_, sides_max) = self.range_of(*sides)?;
+				let (n_min, n_max) = (dice_count(count_min)? as usize, dice_count(count_max)? as usize);
+				let (kept_min, kept_max) = match keep {
+					Some(keep) => {
+						let (keep_min, keep_max) = self.range_of(keep.count)?;
+						(keep.mode.kept(n_min, keep_min)?, keep.mode.kept(n_max, keep_max)?)
+					}
+					None => (n_min, n_max)
+				};
+				Ok((kept_min as i64, (kept_max as i64).checked_mul(dice_sides(sides_max)? as i64).ok_or_else(overflow)?))
+			}
+			Node::Neg(x) => {
+				let (min, max) = self.range_of(*x)?;
+				Ok((min.checked_neg().ok_or_else(overflow)?, max.checked_neg().ok_or_else(overflow)?))
+			}
+			Node::Binary(op, _, _) if op.is_comparison() => Ok(match self.pmf_of(id) {
+				Some(pmf) => (pmf.keys().next().copied().unwrap_or(0), pmf.keys().next_back().copied().unwrap_or(0)),
+				// too many possible results to work out exactly, so either result is possible
+				None => (0, 1)
+			}),
+			Node::Binary(op, left, right) => {
+				let (left_min, left_max) = self.range_of(*left)?;
+				let (right_min, right_max) = self.range_of(*right)?;
+				Ok((op.apply(left_min, right_min)?, op.apply(left_max, right_max)?))
+			}
+			Node::Ternary(condition, then, otherwise) => {
+				// unless the condition always comes out the same way, the result could come from
+				// either side
+				let (can_be_true, can_be_false) = match self.pmf_of(*condition) {
+					Some(pmf) => (pmf.keys().any(|k| *k != 0), pmf.contains_key(&0)),
+					None => (true, true)
+				};
+				let mut range: Option<(i64, i64)> = None;
+				for (side, possible) in [(then, can_be_true), (otherwise, can_be_false)] {
+					if !possible {continue;}
+					let (min, max) = self.range_of(*side)?;
+					range = Some(range.map_or((min, max), |(other_min, other_max)| (min.min(other_min), max.max(other_max))));
+				}
+				Ok(range.unwrap_or((0, 0)))
+			}
+			Node::Group{members, keep} => {
+				let ranges = self.members(members).iter().map(|member| self.range_of(*member)).collect::<Result<Vec<(i64, i64)>, SyntaxError>>()?;
+				let (mut mins, mut maxes): (Vec<i64>, Vec<i64>) = ranges.into_iter().unzip();
+				let (kept_min, kept_max, highest) = match keep {
+					Some(keep) => {
+						let (keep_min, keep_max) = self.range_of(keep.count)?;
+						(keep.mode.kept(mins.len(), keep_min)?, keep.mode.kept(maxes.len(), keep_max)?, keep.mode.highest())
+					}
+					None => (mins.len(), maxes.len(), true)
+				};
+				mins.sort_unstable();
+				maxes.sort_unstable();
+				Ok((kept_sum(&mins, kept_min, highest).ok_or_else(overflow)?, kept_sum(&maxes, kept_max, highest).ok_or_else(overflow)?))
+			}
+			Node::Tagged(x, _) => self.range_of(*x),
+			Node::CustomDice{..} | Node::Call{..} => match self.pmf_of(id) {
+				Some(pmf) => Ok((pmf.keys().next().copied().unwrap_or(0), pmf.keys().next_back().copied().unwrap_or(0))),
+				None => self.plugin_range(id)
+			},
+			Node::Var(name) => Err(unknown_variable(name))
+		}
+	}
+
+	/// Estimates the minimum and maximum results of a custom dice modifier or function from the
+	/// results it gives for the extremes of its inputs (which is exact if the results only go up
+	/// or only go down as each input goes up)
+	fn plugin_range(&self, id: NodeId) -> Result<(i64, i64), SyntaxError> {
+		let mut results: Vec<i64> = Vec::new();
+		match self.node(id) {
+			Node::CustomDice{count, sides, modifier, arg} => {
+				let (count_min, count_max) = self.range_of(*count)?;
+				let (sides_min, sides_max) = self.range_of(*sides)?;
+				let args = match arg {
+					Some(arg) => {
+						let (arg_min, arg_max) = self.range_of(*arg)?;
+						vec![Some(arg_min), Some(arg_max)]
+					}
+					None => vec![None]
+				};
+				for n in [count_min.max(0), count_max.max(0)] {
+					for d in [sides_min.max(1), sides_max.max(1)] {
+						let (n, d) = (dice_count(n)? as usize, dice_sides(d)?);
+						for dice in [vec![1; n], vec![d; n]] {
+							for arg in &args {
+								results.push((self.modifiers[*modifier].1.0)(&dice, d, *arg)?);
+							}
+						}
+					}
+				}
+			}
+			Node::Call{function, args} => {
+				let ranges = self.members(args).iter().map(|arg| self.range_of(*arg)).collect::<Result<Vec<(i64, i64)>, SyntaxError>>()?;
+				if ranges.len() > MAX_PLUGIN_ARGS {
+					return Err(SyntaxError::from("Too many arguments to work out the range of a custom function"));
+				}
+				// try every combination of the extremes of each argument
+				let mut values = vec![0; ranges.len()];
+				for corner in 0..1usize << ranges.len() {
+					for (i, (min, max)) in ranges.iter().enumerate() {
+						values[i] = if corner >> i & 1 == 0 {*min} else {*max};
+					}
+					results.push((self.functions[*function].1.0)(&values)?);
+				}
+			}
+			_ => return self.range_of(id)
+		}
+		Ok((*results.iter().min().unwrap_or(&0), *results.iter().max().unwrap_or(&0)))
+	}
+
+	/// Returns the probability of every possible result, or `None` if there are too many
+	/// combinations of dice to work them out exactly
+	pub(crate) fn pmf<P: Probability>(&self) -> Option<Pmf<P>> {
+		self.pmf_as(self.root)
+	}
+
+	/// Returns the average result. Division is averaged as the average of the numerator divided by
+	/// the average of the denominator.
+	pub(crate) fn mean(&self) -> Result<f64, SyntaxError> {
+		self.mean_of(self.root)
+	}
+
+	fn mean_of(&self, id: NodeId) -> Result<f64, SyntaxError> {
+		match self.node(id) {
+			Node::Number(n) => Ok(*n as f64),
+			Node::Decimal(x) => Ok(*x),
+			Node::Dice{count, sides, keep: None, ..} => Ok(self.mean_of(*count)? * 0.5 * (1. + self.mean_of(*sides)?)),
+			Node::Dice{count, sides, keep: Some(keep), ..} => match self.pmf_of(id) {
+				Some(pmf) => Ok(pmf_mean(&pmf)),
+				// too many combinations to work out exactly, so work it out from the averages of its
+				// parts
+				None => {
+					let (count, sides) = (self.mean_of(*count)?, self.mean_of(*sides)?);
+					let kept = self.mean_kept(keep, count)?;
+					Ok(match kept_mean(count, sides, kept, keep.mode.highest()) {
+						Some(mean) => mean,
+						// approximate the kept dice as average rolls
+						None => kept * 0.5 * (1. + sides)
+					})
+				}
+			},
+			Node::Neg(x) => Ok(-self.mean_of(*x)?),
+			Node::Binary(op, left, right) if op.is_comparison() => match self.pmf_of(id) {
+				Some(pmf) => Ok(1. - pmf.get(&0).copied().unwrap_or(0.)),
+				// too many possible results to work out exactly, so estimate the chance by treating the
+				// difference between both sides as normally distributed
+				None => {
+					let mean = self.mean_of(*left)? - self.mean_of(*right)?;
+					let variance = self.variance_of(*left)? + self.variance_of(*right)?;
+					Ok(normal_chance(*op, mean, variance, self.is_whole(id)))
+				}
+			},
+			Node::Binary(op, left, right) => {
+				let (left, right) = (self.mean_of(*left)?, self.mean_of(*right)?);
+				Ok(match op {
+					BinOp::Add => left + right,
+					BinOp::Sub => left - right,
+					BinOp::Mul => left * right,
+					_ => left / right
+				})
+			}
+			Node::Ternary(condition, then, otherwise) => {
+				let p = self.chance_true(*condition)?;
+				let then = if p > 0. {self.mean_of(*then)?} else {0.};
+				let otherwise = if p < 1. {self.mean_of(*otherwise)?} else {0.};
+				Ok(p * then + (1. - p) * otherwise)
+			}
+			Node::Group{members, keep: None} => self.members(members).iter().map(|member| self.mean_of(*member)).sum(),
+			Node::Group{members, keep: Some(keep)} => match self.pmf_of(id) {
+				Some(pmf) => Ok(pmf_mean(&pmf)),
+				// too many combinations to work out exactly, so approximate it by keeping or dropping
+				// members by their averages
+				None => {
+					let mut means = self.members(members).iter().map(|member| self.mean_of(*member)).collect::<Result<Vec<f64>, SyntaxError>>()?;
+					means.sort_by(|a, b| a.total_cmp(b));
+					if keep.mode.highest() {means.reverse();}
+					let kept = self.mean_kept(keep, means.len() as f64)?;
+					let whole = kept.floor() as usize;
+					let part = means.get(whole).map_or(0., |mean| mean * kept.fract());
+					Ok(means[..whole].iter().sum::<f64>() + part)
+				}
+			},
+			Node::Tagged(x, _) => self.mean_of(*x),
+			Node::CustomDice{..} | Node::Call{..} => match self.pmf_of(id) {
+				Some(pmf) => Ok(pmf_mean(&pmf)),
+				// too many combinations to work out exactly, so approximate the average as halfway
+				// between the min and max
+				None => {
+					let (min, max) = self.plugin_range(id)?;
+					Ok(0.5 * (min as f64 + max as f64))
+				}
+			},
+			Node::Var(name) => Err(unknown_variable(name))
+		}
+	}
+
+	/// Returns the probability that the result of a node is not 0
+	fn chance_true(&self, id: NodeId) -> Result<f64, SyntaxError> {
+		match self.pmf_of(id) {
+			Some(pmf) => Ok(1. - pmf.get(&0).copied().unwrap_or(0.)),
+			// too many possible results to work out exactly, so estimate the chance by treating the
+			// result as normally distributed (the average of a comparison is already its chance)
+			None => match self.node(id) {
+				Node::Binary(op, _, _) if op.is_comparison() => self.mean_of(id),
+				_ => Ok(normal_chance(BinOp::Ne, self.mean_of(id)?, self.variance_of(id)?, self.is_whole(id)))
+			}
+		}
+	}
+
+	/// Estimates how far the result of a node varies from its average (the variance), for
+	/// estimating chances when there are too many possible results to work them out exactly. This
+	/// is exact for sums of dice and approximate for the rest (eg kept dice are treated like
+	/// rolling only the kept dice).
+	fn variance_of(&self, id: NodeId) -> Result<f64, SyntaxError> {
+		if let Some(pmf) = self.pmf_of(id) {
+			return Ok(pmf_variance(&pmf));
+		}
+		match self.node(id) {
+			Node::Number(_) | Node::Decimal(_) => Ok(0.),
+			Node::Dice{count, sides, keep, ..} => {
+				let (count_mean, count_variance) = (self.mean_of(*count)?, self.variance_of(*count)?);
+				let (sides_mean, sides_variance) = (self.mean_of(*sides)?, self.variance_of(*sides)?);
+				let die_mean = 0.5 * (1. + sides_mean);
+				let die_variance = (sides_mean * sides_mean + sides_variance - 1.) / 12. + 0.25 * sides_variance;
+				let kept = match keep {
+					Some(keep) => self.mean_kept(keep, count_mean)?,
+					None => count_mean
+				};
+				Ok(kept * die_variance + count_variance * die_mean * die_mean)
+			}
+			Node::Neg(x) | Node::Tagged(x, _) => self.variance_of(*x),
+			Node::Binary(op, _, _) if op.is_comparison() => {
+				let p = self.mean_of(id)?;
+				Ok(p * (1. - p))
+			}
+			Node::Binary(op, left, right) => {
+				let (left_mean, right_mean) = (self.mean_of(*left)?, self.mean_of(*right)?);
+				let (left, right) = (self.variance_of(*left)?, self.variance_of(*right)?);
+				Ok(match op {
+					BinOp::Add | BinOp::Sub => left + right,
+					BinOp::Mul => left * right + left * right_mean * right_mean + right * left_mean * left_mean,
+					_ => (left + right * (left_mean / right_mean).powi(2)) / (right_mean * right_mean)
+				})
+			}
+			Node::Ternary(condition, then, otherwise) => {
+				let p = self.chance_true(*condition)?;
+				let (then_mean, otherwise_mean) = (self.mean_of(*then)?, self.mean_of(*otherwise)?);
+				let (then, otherwise) = (self.variance_of(*then)?, self.variance_of(*otherwise)?);
+				Ok(p * then + (1. - p) * otherwise + p * (1. - p) * (then_mean - otherwise_mean).powi(2))
+			}
+			Node::Group{members, keep} => {
+				let variances = self.members(members).iter().map(|member| self.variance_of(*member)).collect::<Result<Vec<f64>, SyntaxError>>()?;
+				let total: f64 = variances.iter().sum();
+				match keep {
+					// approximate the kept members as varying by the average amount
+					Some(keep) if !variances.is_empty() => Ok(total / variances.len() as f64 * self.mean_kept(keep, variances.len() as f64)?),
+					_ => Ok(total)
+				}
+			}
+			Node::CustomDice{..} | Node::Call{..} => {
+				// approximate it as equally likely to be anything between the min and max
+				let (min, max) = self.plugin_range(id)?;
+				Ok((max as f64 - min as f64).powi(2) / 12.)
+			}
+			Node::Var(name) => Err(unknown_variable(name))
+		}
+	}
+
+	/// Returns whether the result of a node is always a whole number (ie there are no decimal
+	/// numbers in it)
+	fn is_whole(&self, id: NodeId) -> bool {
+		let mut whole = true;
+		self.walk(id, &mut |node| whole &= !matches!(node, Node::Decimal(_)));
+		whole
+	}
+
+	/// Works out the probability of every possible result of a node as floats, or returns `None`
+	/// if there are too many possible results (or if a division by zero is possible)
+	fn pmf_of(&self, id: NodeId) -> Option<Pmf> {
+		self.pmf_as(id)
+	}
+
+	/// Works out the probability of every possible result of a node, or returns `None` if there
+	/// are too many possible results (or if a division by zero is possible, or the exact
+	/// probabilities overflow)
+	fn pmf_as<P: Probability>(&self, id: NodeId) -> Option<Pmf<P>> {
+		match self.node(id) {
+			Node::Number(n) => Some(Pmf::from([(*n, P::one())])),
+			Node::Decimal(_) => None,
+			Node::Dice{count, sides, keep, ..} => {
+				let (counts, sides) = (self.pmf_as::<P>(*count)?, self.pmf_as::<P>(*sides)?);
+				let keep_counts = match keep {
+					Some(keep) => self.pmf_as(keep.count)?,
+					None => Pmf::new()
+				};
+				let mut pmf = Pmf::new();
+				for (n, p_n) in counts {
+					for (d, p_d) in &sides {
+						let (n, d) = (dice_count(n).ok()? as usize, dice_sides(*d).ok()? as usize);
+						if n.saturating_mul(d) > MAX_OUTCOMES {return None;}
+						let totals = match keep {
+							None => dice_sum_pmf::<P>(n, d)?.into_iter().enumerate().map(|(total, p)| (total as i64, p)).collect(),
+							Some(keep) => {
+								let mut totals = Vec::new();
+								for (kept, p_kept) in keep.kept_pmf::<P>(n, &keep_counts)? {
+									for (total, p) in kept_dice_pmf::<P>(n, d, kept, keep.mode.highest())? {
+										totals.push((total, p.mul(p_kept)?));
+									}
+								}
+								totals
+							}
+						};
+						for (total, p) in totals {
+							if p.is_positive() {accumulate(&mut pmf, total, p_n.mul(*p_d)?.mul(p)?)?;}
+						}
+					}
+				}
+				Some(pmf)
+			}
+			Node::Neg(x) => self.pmf_as::<P>(*x)?.into_iter().map(|(k, p)| Some((k.checked_neg()?, p))).collect(),
+			Node::Binary(op, left, right) => combine(*op, &self.pmf_as(*left)?, &self.pmf_as(*right)?),
+			Node::Ternary(condition, then, otherwise) => {
+				let p_false = self.pmf_as::<P>(*condition)?.get(&0).copied().unwrap_or(P::zero());
+				let p_true = P::one().sub(p_false)?;
+				let mut pmf = Pmf::new();
+				if p_true.is_positive() {
+					for (k, p) in self.pmf_as::<P>(*then)? {accumulate(&mut pmf, k, p_true.mul(p)?)?;}
+				}
+				if p_false.is_positive() {
+					for (k, p) in self.pmf_as::<P>(*otherwise)? {accumulate(&mut pmf, k, p_false.mul(p)?)?;}
+				}
+				Some(pmf)
+			}
+			Node::Group{members, keep: None} => self.members(members).iter()
+				.try_fold(Pmf::from([(0, P::one())]), |pmf, member| combine(BinOp::Add, &pmf, &self.pmf_as(*member)?)),
+			Node::Group{members, keep: Some(keep)} => {
+				let members = self.members(members);
+				let pmfs = members.iter().map(|member| Some(self.pmf_as::<P>(*member)?.into_iter().collect()))
+					.collect::<Option<Vec<Vec<(i64, P)>>>>()?;
+				let kept = keep.kept_pmf(members.len(), &self.pmf_as(keep.count)?)?;
+				Some(keep_pmf(&pmfs, &kept, keep.mode.highest())?.into_iter().collect())
+			}
+			Node::Tagged(x, _) => self.pmf_as(*x),
+			Node::CustomDice{count, sides, modifier, arg} => {
+				let (counts, sides) = (self.pmf_as::<P>(*count)?, self.pmf_as::<P>(*sides)?);
+				let args: Vec<(Option<i64>, P)> = match arg {
+					Some(arg) => self.pmf_as::<P>(*arg)?.into_iter().map(|(a, p)| (Some(a), p)).collect(),
+					None => vec![(None, P::one())]
+				};
+				let mut pmf = Pmf::new();
+				for (n, p_n) in counts {
+					for (d, p_d) in &sides {
+						let (n, d) = (dice_count(n).ok()? as usize, dice_sides(*d).ok()?);
+						// try every sequence of rolls, which are all equally likely
+						let sequences = (d as usize).checked_pow(n as u32)?;
+						if sequences.saturating_mul(args.len()) > MAX_OUTCOMES * 10 {return None;}
+						let mut choice = vec![0; n];
+						let mut dice = vec![1; n];
+						loop {
+							for (i, c) in choice.iter().enumerate() {
+								dice[i] = *c as u32 + 1;
+							}
+							for (a, p_a) in &args {
+								let result = (self.modifiers[*modifier].1.0)(&dice, d, *a).ok()?;
+								accumulate(&mut pmf, result, p_n.mul(*p_d)?.mul(*p_a)?.div(sequences)?)?;
+							}
+							if !advance(&mut choice, |_| d as usize) {break;}
+						}
+					}
+				}
+				Some(pmf).filter(|pmf| pmf.len() <= MAX_OUTCOMES)
+			}
+			Node::Call{function, args} => {
+				let pmfs = self.members(args).iter().map(|arg| Some(self.pmf_as::<P>(*arg)?.into_iter().collect()))
+					.collect::<Option<Vec<Vec<(i64, P)>>>>()?;
+				let combinations = pmfs.iter().try_fold(1usize, |n, pmf| n.checked_mul(pmf.len()))?;
+				if combinations > MAX_OUTCOMES * 10 {return None;}
+				let mut pmf = Pmf::new();
+				let mut choice = vec![0; pmfs.len()];
+				let mut values = vec![0; pmfs.len()];
+				loop {
+					let mut p = P::one();
+					for (i, c) in choice.iter().enumerate() {
+						values[i] = pmfs[i][*c].0;
+						p = p.mul(pmfs[i][*c].1)?;
+					}
+					accumulate(&mut pmf, (self.functions[*function].1.0)(&values).ok()?, p)?;
+					if !advance(&mut choice, |i| pmfs[i].len()) {break;}
+				}
+				Some(pmf).filter(|pmf| pmf.len() <= MAX_OUTCOMES)
+			}
+			Node::Var(_) => None
+		}
+	}
+
+	/// Returns the average number of dice kept out of a dice term rolling `count_mean` dice on
+	/// average (or of the members of a group)
+	fn mean_kept(&self, keep: &Keep, count_mean: f64) -> Result<f64, SyntaxError> {
+		let n = self.mean_of(keep.count)?.clamp(0., count_mean);
+		Ok(match keep.mode {
+			KeepMode::KeepHighest | KeepMode::KeepLowest => n,
+			_ => count_mean - n
+		})
+	}
+
+	/// Returns the most dice that rolling the expression can roll (counting both sides of a
+	/// conditional expression)
+	pub(crate) fn dice_count(&self) -> Result<u64, SyntaxError> {
+		let mut total = 0u64;
+		for count in self.dice_terms() {
+			total = total.saturating_add(self.range_of(count)?.1.max(0) as u64);
+		}
+		Ok(total)
+	}
+
+	/// Returns the most sides that a die rolled by the expression can have, or 0 if there are no
+	/// dice
+	pub(crate) fn max_die_size(&self) -> Result<u32, SyntaxError> {
+		let mut sides = Vec::new();
+		self.walk(self.root, &mut |node| match node {
+			Node::Dice{sides: s, ..} | Node::CustomDice{sides: s, ..} => sides.push(*s),
+			_ => {}
+		});
+		let mut max = 0u32;
+		for id in sides {
+			max = max.max(u32::try_from(self.range_of(id)?.1.max(0)).unwrap_or(u32::MAX));
+		}
+		Ok(max)
+	}
+
+	/// Returns true if the expression has any dice in it
+	pub(crate) fn has_dice(&self) -> bool {
+		!self.dice_terms().is_empty()
+	}
+
+	/// Returns the names of the variables used by the expression, in the order that they first
+	/// appear
+	pub(crate) fn variables(&self) -> Vec<&str> {
+		let mut names: Vec<&str> = Vec::new();
+		self.walk(self.root, &mut |node| match node {
+			Node::Var(name) if !names.contains(&name.as_str()) => names.push(name),
+			_ => {}
+		});
+		names
+	}
+
+	/// Returns the number of dice of every dice term of the expression
+	fn dice_terms(&self) -> Vec<NodeId> {
+		let mut counts = Vec::new();
+		self.walk(self.root, &mut |node| match node {
+			Node::Dice{count, ..} | Node::CustomDice{count, ..} => counts.push(*count),
+			_ => {}
+		});
+		counts
+	}
+
+	/// Calls `visit` with the given node and every node under it, parents first (unlike
+	/// `each_node`, this includes both sides of conditional expressions)
+	fn walk<'a, F>(&'a self, id: NodeId, visit: &mut F) where F: FnMut(&'a Node) {
+		let node = self.node(id);
+		visit(node);
+		match node {
+			Node::Number(_) | Node::Decimal(_) | Node::Var(_) => {}
+			Node::Dice{count, sides, keep, ..} => {
+				self.walk(*count, visit);
+				self.walk(*sides, visit);
+				if let Some(keep) = keep {self.walk(keep.count, visit);}
+			}
+			Node::Neg(x) | Node::Tagged(x, _) => self.walk(*x, visit),
+			Node::Binary(_, left, right) => {
+				self.walk(*left, visit);
+				self.walk(*right, visit);
+			}
+			Node::Ternary(condition, then, otherwise) => {
+				self.walk(*condition, visit);
+				self.walk(*then, visit);
+				self.walk(*otherwise, visit);
+			}
+			Node::Group{members, keep} => {
+				for member in self.members(members) {self.walk(*member, visit);}
+				if let Some(keep) = keep {self.walk(keep.count, visit);}
+			}
+			Node::CustomDice{count, sides, arg, ..} => {
+				self.walk(*count, visit);
+				self.walk(*sides, visit);
+				if let Some(arg) = arg {self.walk(*arg, visit);}
+			}
+			Node::Call{args, ..} => {
+				for arg in self.members(args) {self.walk(*arg, visit);}
+			}
+		}
+	}
+
+	/// Returns a simplified copy of the expression, where the variables that have a value are
+	/// replaced by it and everything that does not depend on dice or the remaining variables is
+	/// worked out, leaving the dice terms as they are (eg "1d20+prof+3" becomes "1d20+5" when
+	/// prof is 2). The numbers added to or subtracted from a sum are combined at its end.
+	pub(crate) fn partial_eval<F>(&self, value: F) -> Result<Expr, SyntaxError> where F: Fn(&str) -> Option<i64> {
+		let mut folded = Expr{nodes: Vec::with_capacity(self.nodes.len()), members: Vec::new(),
+			functions: self.functions.clone(), modifiers: self.modifiers.clone(), root: 0};
+		folded.root = self.fold(self.root, &mut folded, &value)?;
+		Ok(folded)
+	}
+
+	/// Adds the simplified copy of a node to `out`, returning its position. A node that is worked
+	/// out to a number only adds that number to `out`.
+	fn fold<F>(&self, id: NodeId, out: &mut Expr, value: &F) -> Result<NodeId, SyntaxError> where F: Fn(&str) -> Option<i64> {
+		let (nodes, members) = (out.nodes.len(), out.members.len());
+		let folded = self.fold_node(id, out, value)?;
+		match out.constant(folded) {
+			Some(n) if out.nodes.len() > nodes + 1 => {
+				out.nodes.truncate(nodes);
+				out.members.truncate(members);
+				Ok(out.push(Node::Number(n)))
+			}
+			_ => Ok(folded)
+		}
+	}
+
+	fn fold_node<F>(&self, id: NodeId, out: &mut Expr, value: &F) -> Result<NodeId, SyntaxError> where F: Fn(&str) -> Option<i64> {
+		Ok(match self.node(id) {
+			Node::Number(n) => out.push(Node::Number(*n)),
+			Node::Decimal(x) => out.push(Node::Decimal(*x)),
+			Node::Var(name) => match value(name) {
+				Some(n) => out.push(Node::Number(n)),
+				None => out.push(Node::Var(name.clone()))
+			},
+			Node::Dice{count, sides, keep, ..} => {
+				let (count, sides) = (self.fold(*count, out, value)?, self.fold(*sides, out, value)?);
+				let keep = match keep {
+					Some(keep) => Some(Keep{mode: keep.mode, count: self.fold(keep.count, out, value)?}),
+					None => None
+				};
+				// the folded expression has no text for the dice to have a position in
+				out.push(Node::Dice{count, sides, keep, span: None})
+			}
+			Node::Neg(x) => {
+				let x = self.fold(*x, out, value)?;
+				match out.constant(x) {
+					Some(n) => out.push(Node::Number(n.checked_neg().ok_or_else(overflow)?)),
+					None => out.push(Node::Neg(x))
+				}
+			}
+			Node::Binary(BinOp::Add | BinOp::Sub, ..) => {
+				let mut terms = Vec::new();
+				self.sum_terms(id, false, &mut terms);
+				let mut constant = 0i64;
+				let mut sum: Option<NodeId> = None;
+				for (negative, term) in terms {
+					let term = self.fold(term, out, value)?;
+					if let Some(n) = out.constant(term) {
+						out.nodes.pop();
+						let n = match negative {
+							true => n.checked_neg().ok_or_else(overflow)?,
+							false => n
+						};
+						constant = constant.checked_add(n).ok_or_else(overflow)?;
+						continue;
+					}
+					sum = Some(match (sum, negative) {
+						(None, false) => term,
+						(None, true) => out.push(Node::Neg(term)),
+						(Some(sum), false) => out.push(Node::Binary(BinOp::Add, sum, term)),
+						(Some(sum), true) => out.push(Node::Binary(BinOp::Sub, sum, term))
+					});
+				}
+				match (sum, constant.checked_neg()) {
+					(None, _) => out.push(Node::Number(constant)),
+					(Some(sum), _) if constant == 0 => sum,
+					(Some(sum), Some(minus)) if constant < 0 => {
+						let minus = out.push(Node::Number(minus));
+						out.push(Node::Binary(BinOp::Sub, sum, minus))
+					}
+					(Some(sum), _) => {
+						let plus = out.push(Node::Number(constant));
+						out.push(Node::Binary(BinOp::Add, sum, plus))
+					}
+				}
+			}
+			Node::Binary(op, left, right) => {
+				let (left, right) = (self.fold(*left, out, value)?, self.fold(*right, out, value)?);
+				match (out.constant(left), out.constant(right)) {
+					(Some(l), Some(r)) => out.push(Node::Number(op.apply(l, r)?)),
+					_ => out.push(Node::Binary(*op, left, right))
+				}
+			}
+			Node::Ternary(condition, then, otherwise) => {
+				let condition = self.fold(*condition, out, value)?;
+				match out.constant(condition) {
+					Some(n) => {
+						out.nodes.pop();
+						self.fold(if n != 0 {*then} else {*otherwise}, out, value)?
+					}
+					None => {
+						let (then, otherwise) = (self.fold(*then, out, value)?, self.fold(*otherwise, out, value)?);
+						out.push(Node::Ternary(condition, then, otherwise))
+					}
+				}
+			}
+			Node::Group{members, keep} => {
+				let folded = self.members(members).iter().map(|member| self.fold(*member, out, value))
+					.collect::<Result<Vec<NodeId>, SyntaxError>>()?;
+				let keep = match keep {
+					Some(keep) => Some(Keep{mode: keep.mode, count: self.fold(keep.count, out, value)?}),
+					None => None
+				};
+				let start = out.members.len();
+				out.members.extend(folded);
+				out.push(Node::Group{members: start..out.members.len(), keep})
+			}
+			Node::Tagged(x, tag) => {
+				let x = self.fold(*x, out, value)?;
+				out.push(Node::Tagged(x, tag.clone()))
+			}
+			Node::CustomDice{count, sides, modifier, arg} => {
+				let (count, sides) = (self.fold(*count, out, value)?, self.fold(*sides, out, value)?);
+				let arg = match arg {
+					Some(arg) => Some(self.fold(*arg, out, value)?),
+					None => None
+				};
+				out.push(Node::CustomDice{count, sides, modifier: *modifier, arg})
+			}
+			Node::Call{function, args} => {
+				let folded = self.members(args).iter().map(|arg| self.fold(*arg, out, value))
+					.collect::<Result<Vec<NodeId>, SyntaxError>>()?;
+				match folded.iter().map(|arg| out.constant(*arg)).collect::<Option<Vec<i64>>>() {
+					Some(values) => out.push(Node::Number((self.functions[*function].1.0)(&values)?)),
+					None => {
+						let start = out.members.len();
+						out.members.extend(folded);
+						out.push(Node::Call{function: *function, args: start..out.members.len()})
+					}
+				}
+			}
+		})
+	}
+
+	/// Lists the terms that are added up by a sum (eg "a", "b", and "c" for "a-b+c"), and whether
+	/// each one is subtracted
+	fn sum_terms(&self, id: NodeId, negative: bool, terms: &mut Vec<(bool, NodeId)>) {
+		match self.node(id) {
+			Node::Binary(BinOp::Add, left, right) => {
+				self.sum_terms(*left, negative, terms);
+				self.sum_terms(*right, negative, terms);
+			}
+			Node::Binary(BinOp::Sub, left, right) => {
+				self.sum_terms(*left, negative, terms);
+				self.sum_terms(*right, !negative, terms);
+			}
+			_ => terms.push((negative, id))
+		}
+	}
+
+	/// Returns the value of a node if it is a number
+	fn constant(&self, id: NodeId) -> Option<i64> {
+		match self.node(id) {
+			Node::Number(n) => Some(*n),
+			_ => None
+		}
+	}
+
+	fn push(&mut self, node: Node) -> NodeId {
+		self.nodes.push(node);
+		(self.nodes.len() - 1) as NodeId
+	}
+
+	/// Writes out the expression in the given notation
+	pub(crate) fn write(&self, notation: Notation) -> String {
+		self.render(self.root, notation).0
+	}
+
+	/// Writes out a node, returning the text and its precedence (see `Precedence`), so that the
+	/// node's parent knows whether to put it in parentheses
+	fn render(&self, id: NodeId, notation: Notation) -> (String, Precedence) {
+		match self.node(id) {
+			Node::Number(n) if *n < 0 => (format!("{}{}", notation.minus(), n.unsigned_abs()), Precedence::Unary),
+			Node::Number(n) => (n.to_string(), Precedence::Primary),
+			Node::Decimal(x) => (x.to_string(), Precedence::Primary),
+			Node::Var(name) => (notation.name(name), Precedence::Primary),
+			Node::Dice{count, sides, keep, ..} => {
+				let mut text = format!("{}{}{}", self.render_operand(*count, notation), notation.text("d"),
+					self.render_operand(*sides, notation));
+				if let Some(keep) = keep {
+					text.push_str(&notation.text(keep.mode.symbol()));
+					text.push_str(&self.render_operand(keep.count, notation));
+				}
+				(text, Precedence::Dice)
+			}
+			Node::Neg(x) => (format!("{}{}", notation.minus(), self.render_at(*x, Precedence::Unary, notation)), Precedence::Unary),
+			// division is written as a fraction, which needs no parentheses
+			Node::Binary(BinOp::Div, left, right) if notation == Notation::Latex => (format!("\\frac{{{}}}{{{}}}",
+				self.render(*left, notation).0, self.render(*right, notation).0), Precedence::Primary),
+			Node::Binary(op, left, right) => {
+				let precedence = op.precedence();
+				// operators are left-associative, so an equal operator on the right needs parentheses
+				(format!("{}{}{}", self.render_at(*left, precedence, notation), notation.op(*op),
+					self.render_above(*right, precedence, notation)), precedence)
+			}
+			Node::Ternary(condition, then, otherwise) => (format!("{}{}{} : {}",
+				self.render_at(*condition, Precedence::Comparison, notation), notation.question(),
+				self.render_at(*then, Precedence::Ternary, notation), self.render_at(*otherwise, Precedence::Ternary, notation)),
+				Precedence::Ternary),
+			Node::Group{members, keep} => {
+				let members: Vec<String> = self.members(members).iter().map(|member| self.render(*member, notation).0).collect();
+				let (open, close) = notation.braces();
+				let mut text = format!("{}{}{}", open, members.join(", "), close);
+				if let Some(keep) = keep {
+					text.push_str(&notation.text(keep.mode.symbol()));
+					text.push_str(&self.render_operand(keep.count, notation));
+				}
+				(text, Precedence::Primary)
+			}
+			Node::Tagged(x, tag) => (format!("{}{}", self.render_at(*x, Precedence::Tagged, notation), notation.tag(tag)),
+				Precedence::Tagged),
+			Node::CustomDice{count, sides, modifier, arg} => {
+				let mut text = format!("{}{}{}{}", self.render_operand(*count, notation), notation.text("d"),
+					self.render_operand(*sides, notation), notation.text(&self.modifiers[*modifier].0));
+				if let Some(arg) = arg {
+					text.push_str(&self.render_operand(*arg, notation));
+				}
+				(text, Precedence::Dice)
+			}
+			Node::Call{function, args} => {
+				let args: Vec<String> = self.members(args).iter().map(|arg| self.render(*arg, notation).0).collect();
+				(format!("{}{}", notation.function(&self.functions[*function].0), notation.parens(&args.join(", "))),
+					Precedence::Primary)
+			}
+		}
+	}
+
+	/// Writes out a node, in parentheses if it binds less tightly than `precedence`
+	fn render_at(&self, id: NodeId, precedence: Precedence, notation: Notation) -> String {
+		match self.render(id, notation) {
+			(text, p) if p < precedence => notation.parens(&text),
+			(text, _) => text
+		}
+	}
+
+	/// Writes out a node, in parentheses unless it binds more tightly than `precedence`
+	fn render_above(&self, id: NodeId, precedence: Precedence, notation: Notation) -> String {
+		match self.render(id, notation) {
+			(text, p) if p <= precedence => notation.parens(&text),
+			(text, _) => text
+		}
+	}
+
+	/// Writes out the number of dice or sides of a dice term, a keep or drop count, or the
+	/// argument of a custom dice modifier, which are only left without parentheses if they are
+	/// plain numbers
+	fn render_operand(&self, id: NodeId, notation: Notation) -> String {
+		match self.node(id) {
+			Node::Number(n) if *n >= 0 => n.to_string(),
+			_ => notation.parens(&self.render(id, notation).0)
+		}
+	}
+}
+
+impl Keep {
+	/// Returns the probability of each number of dice kept out of `dice` dice, given the
+	/// probabilities of each keep or drop count, or `None` if any count is negative
+	fn kept_pmf<P: Probability>(&self, dice: usize, counts: &Pmf<P>) -> Option<Vec<(usize, P)>> {
+		counts.iter().map(|(n, p)| Some((self.mode.kept(dice, *n).ok()?, *p))).collect()
+	}
+}
+
+impl KeepMode {
+	fn symbol(&self) -> &'static str {
+		match self {
+			KeepMode::KeepHighest => "kh",
+			KeepMode::KeepLowest => "kl",
+			KeepMode::DropHighest => "dh",
+			KeepMode::DropLowest => "dl"
+		}
+	}
+
+	/// Returns true if the highest dice are the ones kept
+	fn highest(&self) -> bool {
+		matches!(self, KeepMode::KeepHighest | KeepMode::DropLowest)
+	}
+
+	/// Returns how many out of `dice` dice are kept when keeping or dropping `n` of them
+	fn kept(&self, dice: usize, n: i64) -> Result<usize, SyntaxError> {
+		let n = match usize::try_from(n) {
+			Ok(n) => n.min(dice),
+			Err(_) => return Err(SyntaxError::from_string(match self {
+				KeepMode::KeepHighest | KeepMode::KeepLowest => format!("Cannot keep {} dice", n),
+				_ => format!("Cannot drop {} dice", n)
+			}))
+		};
+		Ok(match self {
+			KeepMode::KeepHighest | KeepMode::KeepLowest => n,
+			_ => dice - n
+		})
+	}
+
+	/// Returns the positions (in ascending order) of the dice (or group members) that are dropped
+	/// when keeping `kept` of them. Of equal results, the ones rolled first are kept first.
+	fn dropped<T: Ord + Copy>(&self, results: &[T], kept: usize) -> Vec<usize> {
+		let mut order: Vec<usize> = (0..results.len()).collect();
+		match self.highest() {
+			true => order.sort_by_key(|i| std::cmp::Reverse(results[*i])),
+			false => order.sort_by_key(|i| results[*i])
+		}
+		let mut dropped = order.split_off(kept.min(results.len()));
+		dropped.sort_unstable();
+		dropped
+	}
+}
+
+impl BinOp {
+	fn symbol(&self) -> &'static str {
+		match self {
+			BinOp::Add => "+",
+			BinOp::Sub => "-",
+			BinOp::Mul => "*",
+			BinOp::Div => "/",
+			BinOp::Gt => " > ",
+			BinOp::Ge => " >= ",
+			BinOp::Lt => " < ",
+			BinOp::Le => " <= ",
+			BinOp::Eq => " == ",
+			BinOp::Ne => " != "
+		}
+	}
+
+	fn precedence(&self) -> Precedence {
+		match self {
+			BinOp::Add | BinOp::Sub => Precedence::Sum,
+			BinOp::Mul | BinOp::Div => Precedence::Product,
+			_ => Precedence::Comparison
+		}
+	}
+
+	fn is_comparison(&self) -> bool {
+		matches!(self, BinOp::Gt | BinOp::Ge | BinOp::Lt | BinOp::Le | BinOp::Eq | BinOp::Ne)
+	}
+
+	/// Compares two numbers, if the operator is a comparison
+	fn compare<T: PartialOrd>(&self, left: T, right: T) -> bool {
+		match self {
+			BinOp::Gt => left > right,
+			BinOp::Ge => left >= right,
+			BinOp::Lt => left < right,
+			BinOp::Le => left <= right,
+			BinOp::Eq => left == right,
+			BinOp::Ne => left != right,
+			_ => false
+		}
+	}
+
+	/// Applies the operator using integer math
+	fn apply(&self, left: i64, right: i64) -> Result<i64, SyntaxError> {
+		match self {
+			BinOp::Add => left.checked_add(right).ok_or_else(overflow),
+			BinOp::Sub => left.checked_sub(right).ok_or_else(overflow),
+			BinOp::Mul => left.checked_mul(right).ok_or_else(overflow),
+			BinOp::Div => match right {
+				0 => Err(SyntaxError::from("Division by zero")),
+				_ => left.checked_div(right).ok_or_else(overflow)
+			},
+			_ => Ok(self.compare(left, right) as i64)
+		}
+	}
+}
+
+/// Works out the probability of every possible result of applying an operator to two independent
+/// results, or returns `None` if there are too many possible results (or the operator fails)
+fn combine<P: Probability>(op: BinOp, left: &Pmf<P>, right: &Pmf<P>) -> Option<Pmf<P>> {
+	if left.len().saturating_mul(right.len()) > MAX_OUTCOMES * 10 {return None;}
+	let mut pmf = Pmf::new();
+	for (l, p_l) in left {
+		for (r, p_r) in right {
+			accumulate(&mut pmf, op.apply(*l, *r).ok()?, p_l.mul(*p_r)?)?;
+		}
+	}
+	if pmf.len() > MAX_OUTCOMES {return None;}
+	Some(pmf)
+}
+
+/// Adds to the probability of a result, or returns `None` if the exact probability overflows
+fn accumulate<P: Probability>(pmf: &mut Pmf<P>, result: i64, p: P) -> Option<()> {
+	let total = pmf.entry(result).or_insert(P::zero());
+	*total = total.add(p)?;
+	Some(())
+}
+
+/// The error for rolling (or working out the range or average of) a variable that has no value
+pub(crate) fn unknown_variable(name: &str) -> SyntaxError {
+	SyntaxError::from_string(format!("Unknown variable '{}'", name))
+}
+
+/// Rolls one die with `d` sides
+fn roll_die<R: DieRoller + ?Sized>(rng: &mut R, d: u32) -> Result<u32, SyntaxError> {
+	let result = rng.roll_die(d)?;
+	#[cfg(feature = "tracing")]
+	tracing::trace!(sides = d, result, "rolled die");
+	Ok(result)
+}
+
+/// Returns the average of a probability distribution
+fn pmf_mean(pmf: &Pmf) -> f64 {
+	pmf.iter().map(|(k, p)| *k as f64 * p).sum()
+}
+
+/// Returns the variance of a probability distribution
+fn pmf_variance(pmf: &Pmf) -> f64 {
+	let mean = pmf_mean(pmf);
+	pmf.iter().map(|(k, p)| (*k as f64 - mean).powi(2) * p).sum()
+}
+
+/// Estimates the chance that a normally distributed value with the given mean and variance
+/// compares with 0 the way `op` says. If the value is always a whole number, then each whole
+/// number is treated as covering half way to its neighbours.
+fn normal_chance(op: BinOp, mean: f64, variance: f64, whole: bool) -> f64 {
+	if variance <= 0. {
+		return op.compare(mean, 0.) as i64 as f64;
+	}
+	let half = if whole {0.5} else {0.};
+	let above = |x: f64| 1. - normal_cdf((x - mean) / variance.sqrt());
+	match op {
+		BinOp::Gt => above(half),
+		BinOp::Ge => above(-half),
+		BinOp::Lt => 1. - above(-half),
+		BinOp::Le => 1. - above(half),
+		BinOp::Eq => above(-half) - above(half),
+		_ => 1. - above(-half) + above(half)
+	}
+}
+
+/// Returns the chance that a value from the standard normal distribution is below `x`, using the
+/// Abramowitz and Stegun approximation of the error function (accurate to about 1e-7)
+fn normal_cdf(x: f64) -> f64 {
+	let z = x.abs() / std::f64::consts::SQRT_2;
+	let t = 1. / (1. + 0.327_591_1 * z);
+	let poly = t * (0.254_829_592 + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+	let tail = 0.5 * poly * (-z * z).exp();
+	if x < 0. {tail} else {1. - tail}
+}
+
+fn decimal() -> SyntaxError {
+	SyntaxError::from("Found '.', but decimal numbers are not supported (integer math only)")
+}
+
+pub(crate) fn overflow() -> SyntaxError {
+	SyntaxError::from("Integer overflow (result is too big)")
+}
+
+fn dice_count(n: i64) -> Result<u32, SyntaxError> {
+	u32::try_from(n).map_err(|_| SyntaxError::from_string(format!("Cannot roll {} dice", n)))
+}
+
+fn dice_sides(d: i64) -> Result<u32, SyntaxError> {
+	match u32::try_from(d) {
+		Ok(d) if d > 0 => Ok(d),
+		_ => Err(SyntaxError::from_string(format!("Cannot roll dice with {} sides", d)))
+	}
+}
+
+/// Probability of each total (the index) when rolling `n` dice with `d` sides, or `None` if the
+/// exact probabilities overflow
+fn dice_sum_pmf<P: Probability>(n: usize, d: usize) -> Option<Vec<P>> {
+	let mut pmf = vec![P::one()];
+	for _ in 0..n {
+		// each total is the sum of the d previous totals, divided by d (a sliding window)
+		let mut next = vec![P::zero(); pmf.len() + d];
+		let mut window = P::zero();
+		for (total, p) in next.iter_mut().enumerate() {
+			if total >= 1 && total <= pmf.len() {window = window.add(pmf[total - 1])?;}
+			if total > d && total - d - 1 < pmf.len() {window = window.sub(pmf[total - d - 1])?;}
+			*p = window.div(d)?;
+		}
+		pmf = next;
+	}
+	Some(pmf)
+}
+
+/// Works out the average of the highest (or lowest) `kept` of `count` dice with `sides` sides, or
+/// returns `None` if the number of dice or sides is not a whole number or there are too many
+/// dice. This does not need to try every combination of dice, because the average of the highest
+/// `kept` dice is the sum over each result x of the average of the smaller of `kept` and the number
+/// of dice that rolled at least x.
+fn kept_mean(count: f64, sides: f64, kept: f64, highest: bool) -> Option<f64> {
+	if count.fract() != 0. || sides.fract() != 0. || sides < 1. || count * sides > 1e7 {return None;}
+	let (n, d) = (count as usize, sides as usize);
+	// a fractional number of kept dice (eg an average) falls between two whole numbers
+	let (low, high) = (kept.floor(), kept.ceil());
+	let weight = kept - low;
+	let mut mean = 0.;
+	for x in 1..=d {
+		// the number of dice rolling x or more has a binomial distribution
+		let q = (d - x + 1) as f64 / d as f64;
+		let (ln_q, ln_not_q) = (q.ln(), (1. - q).ln());
+		let mut ln_choose = 0.;
+		for i in 0..=n {
+			if i > 0 {ln_choose += ((n - i + 1) as f64).ln() - (i as f64).ln();}
+			let p = match q {
+				1. => if i == n {1.} else {0.},
+				_ => (ln_choose + i as f64 * ln_q + (n - i) as f64 * ln_not_q).exp()
+			};
+			let i = i as f64;
+			mean += p * ((1. - weight) * i.min(low) + weight * i.min(high));
+		}
+	}
+	Some(match highest {
+		true => mean,
+		// the lowest dice are the highest dice counted down from the top (ie d+1 minus each die)
+		false => kept * (sides + 1.) - mean
+	})
+}
+
+/// Adds up the highest (or lowest) `kept` of some sorted results, or returns `None` on overflow
+fn kept_sum(sorted: &[i64], kept: usize, highest: bool) -> Option<i64> {
+	match highest {
+		true => sorted[sorted.len() - kept..].iter().try_fold(0i64, |a, b| a.checked_add(*b)),
+		false => sorted[..kept].iter().try_fold(0i64, |a, b| a.checked_add(*b))
+	}
+}
+
+/// Works out the probability of each total of the `kept` highest (or lowest) of `n` dice with `d`
+/// sides from the order statistics of the dice: going through the results from highest to
+/// lowest, some of the dice that are left roll each result, and the first `kept` of them are kept.
+/// Returns `None` if that would take too long.
+fn kept_dice_pmf<P: Probability>(n: usize, d: usize, kept: usize, highest: bool) -> Option<Vec<(i64, P)>> {
+	let max_total = kept.checked_mul(d)?;
+	let work = d.checked_mul(n + 1)?.checked_mul(n + 1)?.checked_mul(max_total + 1)?;
+	if work > MAX_OUTCOMES * 10 {return None;}
+	// choose[r][c] is the number of ways to pick which c of r dice roll a result
+	let mut choose = vec![vec![P::zero(); n + 1]; n + 1];
+	for r in 0..=n {
+		choose[r][0] = P::one();
+		for c in 1..=r {
+			choose[r][c] = choose[r - 1][c - 1].add(if c < r {choose[r - 1][c]} else {P::zero()})?;
+		}
+	}
+	// powers[c] is the chance of c dice all rolling a given result
+	let mut powers = vec![P::one(); n + 1];
+	for c in 1..=n {
+		powers[c] = powers[c - 1].mul(P::one().div(d)?)?;
+	}
+	// pmf[m][total] is the chance of m dice rolling the results so far, with the given total kept
+	let mut pmf = vec![vec![P::zero(); max_total + 1]; n + 1];
+	pmf[0][0] = P::one();
+	for result in (1..=d).rev() {
+		let mut next = vec![vec![P::zero(); max_total + 1]; n + 1];
+		for m in 0..=n {
+			for (total, p) in pmf[m].iter().enumerate() {
+				if !p.is_positive() {continue;}
+				for c in 0..=n - m {
+					let kept_total = total + ((m + c).min(kept) - m.min(kept)) * result;
+					next[m + c][kept_total] = next[m + c][kept_total].add(p.mul(choose[n - m][c])?.mul(powers[c])?)?;
+				}
+			}
+		}
+		pmf = next;
+	}
+	let mut totals: Vec<(i64, P)> = pmf.pop()?.into_iter().enumerate().filter(|(_, p)| p.is_positive())
+		.map(|(total, p)| (total as i64, p)).collect();
+	if !highest {
+		// the lowest dice are the highest of the dice turned upside down (eg a 1 on a d6 as a 6)
+		totals = totals.into_iter().rev().map(|(total, p)| ((kept * (d + 1)) as i64 - total, p)).collect();
+	}
+	Some(totals)
+}
+
+/// Probability of each total when adding up the highest (or lowest) of several independent
+/// results (eg dice), given the probabilities of each result and of how many results are kept.
+/// This tries every combination of results, so it returns `None` if there are too many.
+fn keep_pmf<P: Probability>(results: &[Vec<(i64, P)>], kept: &[(usize, P)], highest: bool) -> Option<Vec<(i64, P)>> {
+	let combinations = results.iter().try_fold(1usize, |n, pmf| n.checked_mul(pmf.len()))?;
+	if combinations > MAX_OUTCOMES * 10 {return None;}
+	let mut pmf = Pmf::new();
+	let mut choice = vec![0; results.len()];
+	let mut sorted = vec![0; results.len()];
+	loop {
+		let mut p = P::one();
+		for (i, c) in choice.iter().enumerate() {
+			sorted[i] = results[i][*c].0;
+			p = p.mul(results[i][*c].1)?;
+		}
+		sorted.sort_unstable();
+		for (kept, p_kept) in kept {
+			accumulate(&mut pmf, kept_sum(&sorted, *kept, highest)?, p.mul(*p_kept)?)?;
+		}
+		if !advance(&mut choice, |i| results[i].len()) {
+			return Some(pmf.into_iter().collect());
+		}
+	}
+}
+
+/// Moves on to the next combination of choices, like counting with an odometer, where `len(i)`
+/// is how many options there are for choice `i`. Returns false once every combination has been
+/// tried (and the choices are all back to 0).
+fn advance<F>(choice: &mut [usize], len: F) -> bool where F: Fn(usize) -> usize {
+	for (i, c) in choice.iter_mut().enumerate() {
+		if *c + 1 < len(i) {
+			*c += 1;
+			return true;
+		}
+		*c = 0;
+	}
+	false
+}
+
+/// Returns true if the text is a variable name on its own (eg "atk"), read the same way as the
+/// variables of a dice expression
+pub(crate) fn is_variable_name(text: &str, plugins: &Plugins) -> bool {
+	match tokenize(text, false, plugins, &mut None).as_deref() {
+		// "x" on its own is a name, since it only means multiply after a value (eg "2x3")
+		Ok([name, _]) => matches!(name.kind, TokenKind::Ident | TokenKind::Times) && text != "*"
+			&& name.start == 0 && name.end == text.len(),
+		_ => false
+	}
+}
+
+/// Returns true if the word is part of the dice notation (eg the "d" of "3d6" or the "kh" of
+/// "4d6kh3") rather than the name of a variable
+pub(crate) fn is_keyword(word: &str) -> bool {
+	matches!(word.to_ascii_lowercase().as_str(), "d" | "dh" | "dl" | "k" | "kh" | "kl" | "x")
+}
+
+/// What the parser does with names that are not custom functions
+#[derive(Clone, Copy)]
+enum Variables<'a> {
+	/// Names are errors
+	None,
+	/// Names are variables, which must be given values before rolling
+	Unbound,
+	/// Names are replaced by their values, and are errors if they have none
+	Bound(&'a dyn Fn(&str) -> Option<i64>)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TokenKind {
+	Number(i64), Decimal, Dice, Keep(KeepMode), Percent, Plus, Minus, Times, Divide, Open, Close,
+	OpenBrace, CloseBrace, Comma, Tag, Ident, Assign, Question, Colon, Compare(BinOp), End
+}
+
+/// A token of a dice expression and where it is in the expression text
+#[derive(Clone, Copy, Debug)]
+struct Token {
+	kind: TokenKind,
+	start: usize,
+	end: usize,
+	line: u64,
+	col: u64
+}
+
+impl Token {
+	fn error<T>(&self, msg: T) -> SyntaxError where T: Into<String> {
+		SyntaxError{msg: Some(msg.into()), line: Some(self.line), col: Some(self.col), cause: None, kind: ErrorKind::Expression}
+	}
+}
+
+/// Splits a dice expression into tokens. Decimal numbers (eg "1.5") are an error unless
+/// `decimals` is true. Stops at the first error unless `errors` is collecting every error (in
+/// which case the problem characters are skipped).
+fn tokenize(text: &str, decimals: bool, plugins: &Plugins, errors: &mut Option<Vec<SyntaxDiagnostic>>) -> Result<Vec<Token>, SyntaxError> {
+	// there are never more tokens than characters (plus the end), so this is the only allocation
+	let mut tokens: Vec<Token> = Vec::with_capacity(text.len() + 1);
+	let mut chars = text.char_indices().peekable();
+	let (mut line, mut col) = (1, 0);
+	while let Some((start, c)) = chars.next() {
+		col += 1;
+		if c == '\n' {
+			line += 1;
+			col = 0;
+		}
+		if c.is_whitespace() {continue;}
+		let token_col = col;
+		let error = |msg: String, col: u64| SyntaxError{msg: Some(msg), line: Some(line), col: Some(col), cause: None, kind: ErrorKind::Expression};
+		let kind = match c {
+			'0'..='9' | '.' => {
+				let mut decimal = c == '.';
+				while let Some((_, c)) = chars.peek().copied() {
+					if c == '.' && !decimal {
+						decimal = true;
+					} else if !c.is_ascii_digit() {
+						break;
+					}
+					col += 1;
+					chars.next();
+				}
+				let end = chars.peek().map_or(text.len(), |(i, _)| *i);
+				match decimal {
+					// decimals allowed when averaging, but otherwise it is ints-only
+					true if !decimals => {
+						let dot = text[start..end].find('.').unwrap_or(0);
+						report(errors, error("Found '.', but decimal numbers are not supported (integer math only)".into(), token_col + dot as u64),
+							start + dot, start + dot + 1)?;
+						// when collecting every error, carry on as if it was a whole number
+						TokenKind::Number(0)
+					}
+					true if end - start == 1 => {
+						report(errors, error("Unexpected '.'".into(), token_col), start, end)?;
+						TokenKind::Number(0)
+					}
+					true => TokenKind::Decimal,
+					false => match text[start..end].parse::<i64>() {
+						Ok(n) => TokenKind::Number(n),
+						// too big for integer math, but it can still be averaged
+						Err(_) if decimals => TokenKind::Decimal,
+						Err(e) => {
+							report(errors, SyntaxError{
+								msg: Some(format!("Number {} is too big", &text[start..end])),
+								line: Some(line), col: Some(token_col), cause: Some(Box::new(e)), kind: ErrorKind::Expression
+							}, start, end)?;
+							TokenKind::Number(0)
+						}
+					}
+				}
+			}
+			c if c.is_ascii_alphabetic() || c == '_' => {
+				let word_end = |from: usize, digits: bool| text[from..]
+					.find(|c: char| !(c.is_ascii_alphabetic() || c == '_' || (digits && c.is_ascii_digit())))
+					.map_or(text.len(), |i| from + i);
+				// letters are read as a word first, so that "d6kh3" is read as "d", "6", "kh", "3"
+				let letters_end = word_end(start, false);
+				let before_digit = text[letters_end..].starts_with(|c: char| c.is_ascii_digit());
+				let letters = &text[start..letters_end];
+				let end = if is_keyword(letters) || plugins.modifier(letters).is_some() {
+					// a custom dice modifier is followed by its argument, eg "s8"
+					letters_end
+				} else if before_digit && letters_end - start > 1 && text[..letters_end].ends_with(['d', 'D']) {
+					// a variable right before the "d" of a dice term is the number of dice (eg "Nd10")
+					letters_end - 1
+				} else {
+					word_end(start, true)
+				};
+				while chars.peek().is_some_and(|(i, _)| *i < end) {
+					col += 1;
+					chars.next();
+				}
+				match text[start..end].to_ascii_lowercase().as_str() {
+					"d" => TokenKind::Dice,
+					"dh" => TokenKind::Keep(KeepMode::DropHighest),
+					"dl" => TokenKind::Keep(KeepMode::DropLowest),
+					"kl" => TokenKind::Keep(KeepMode::KeepLowest),
+					// "k" on its own means "kh"
+					"k" | "kh" => TokenKind::Keep(KeepMode::KeepHighest),
+					"x" => TokenKind::Times,
+					_ => TokenKind::Ident
+				}
+			}
+			'%' => TokenKind::Percent,
+			'+' => TokenKind::Plus,
+			'-' => TokenKind::Minus,
+			'*' => TokenKind::Times,
+			'/' => TokenKind::Divide,
+			'(' => TokenKind::Open,
+			')' => TokenKind::Close,
+			'{' => TokenKind::OpenBrace,
+			'}' => TokenKind::CloseBrace,
+			',' => TokenKind::Comma,
+			'[' => {
+				// a tag is any text up to the closing ]
+				let closed = loop {
+					match chars.peek().copied() {
+						Some((_, ']')) => break true,
+						Some((_, '\n')) | None => break false,
+						Some(_) => col += 1
+					}
+					chars.next();
+				};
+				if !closed {
+					let end = chars.peek().map_or(text.len(), |(i, _)| *i);
+					report(errors, error("Found '[' without matching ']'".into(), token_col), start, end)?;
+					continue;
+				}
+				chars.next();
+				col += 1;
+				TokenKind::Tag
+			}
+			'?' => TokenKind::Question,
+			':' => TokenKind::Colon,
+			'>' | '<' | '=' | '!' => {
+				let or_equal = chars.peek().is_some_and(|(_, c)| *c == '=');
+				if or_equal {
+					col += 1;
+					chars.next();
+				}
+				match (c, or_equal) {
+					('=', false) => TokenKind::Assign,
+					('>', false) => TokenKind::Compare(BinOp::Gt),
+					('>', true) => TokenKind::Compare(BinOp::Ge),
+					('<', false) => TokenKind::Compare(BinOp::Lt),
+					('<', true) => TokenKind::Compare(BinOp::Le),
+					('=', true) => TokenKind::Compare(BinOp::Eq),
+					('!', true) => TokenKind::Compare(BinOp::Ne),
+					_ => {
+						report(errors, error(format!("Unexpected '{}' (use == or != to compare numbers)", c), token_col), start, start + 1)?;
+						continue;
+					}
+				}
+			}
+			c => {
+				report(errors, error(format!("Unexpected character '{}'", c), token_col), start, start + c.len_utf8())?;
+				continue;
+			}
+		};
+		let end = chars.peek().map_or(text.len(), |(i, _)| *i);
+		tokens.push(Token{kind, start, end, line, col: token_col});
+	}
+	tokens.push(Token{kind: TokenKind::End, start: text.len(), end: text.len(), line, col: col + 1});
+	Ok(tokens)
+}
+
+/// Recursive descent parser, from lowest to highest precedence: conditionals, comparisons,
+/// addition and subtraction, multiplication and division, negation, tags, dice (and their keep or
+/// drop modifiers), then numbers, parentheses, and groups
+struct Parser<'a> {
+	text: &'a str,
+	tokens: Vec<Token>,
+	pos: usize,
+	/// The nodes of the expression being parsed
+	nodes: Vec<Node>,
+	/// The members of the groups of the expression being parsed
+	members: Vec<NodeId>,
+	/// The members of the groups that are still being parsed (groups can be nested)
+	open_members: Vec<NodeId>,
+	/// The custom functions and dice modifiers that can be used
+	plugins: &'a Plugins,
+	/// The custom functions and dice modifiers used by the expression being parsed
+	functions: Vec<(String, Callback<FunctionFn>)>,
+	modifiers: Vec<(String, Callback<ModifierFn>)>,
+	/// What names that are not custom functions are
+	variables: Variables<'a>,
+	/// Every error found so far, when parsing carries on after errors to find all of them
+	errors: Option<Vec<SyntaxDiagnostic>>
+}
+
+impl<'a> Parser<'a> {
+	fn new(text: &'a str, decimals: bool, variables: Variables<'a>, plugins: &'a Plugins, steps: &mut Steps) -> Result<Self, SyntaxError> {
+		let tokens = tokenize(text, decimals, plugins, &mut None)?;
+		// checked before parsing, since deeply nested parentheses take deeply nested calls
+		steps.take(tokens.len() as u64)?;
+		Ok(Parser::from_tokens(text, tokens, variables, plugins, None))
+	}
+
+	fn from_tokens(text: &'a str, tokens: Vec<Token>, variables: Variables<'a>, plugins: &'a Plugins, errors: Option<Vec<SyntaxDiagnostic>>) -> Self {
+		// most tokens become one node, so this is usually the only allocation for the nodes
+		let nodes = Vec::with_capacity(tokens.len());
+		Parser{text, tokens, pos: 0, nodes, members: Vec::new(), open_members: Vec::new(),
+			plugins, functions: Vec::new(), modifiers: Vec::new(), variables, errors}
+	}
+
+	/// Records an error found at the token when every error is being collected, or otherwise
+	/// returns it
+	fn report(&mut self, error: SyntaxError, token: Token) -> Result<(), SyntaxError> {
+		let text = self.text;
+		// a problem at or right after one that has already been reported (eg at the end of
+		// "1d6+[fire") is usually caused by it
+		let caused = self.errors.as_ref().is_some_and(|errors| errors.iter().any(|reported| reported.start == token.start
+			|| (reported.end <= token.start && text[reported.end..token.start].trim().is_empty())));
+		match caused {
+			true => Ok(()),
+			false => report(&mut self.errors, error, token.start, token.end)
+		}
+	}
+
+	/// Parses the whole text as one expression. When collecting every error, whatever is
+	/// unexpected after it is skipped to carry on, or (for a `statement` of a script, where it is
+	/// usually a missing ';') the rest of the text is skipped.
+	fn expression(&mut self, statement: bool) -> Result<Expr, SyntaxError> {
+		let root = self.ternary()?;
+		while self.peek().kind != TokenKind::End {
+			let token = self.next();
+			self.report(self.unexpected(token), token)?;
+			match statement {
+				true => self.pos = self.tokens.len() - 1,
+				false => if self.peek().kind != TokenKind::End {self.ternary()?;}
+			}
+		}
+		Ok(Expr{
+			nodes: std::mem::take(&mut self.nodes),
+			members: std::mem::take(&mut self.members),
+			functions: std::mem::take(&mut self.functions),
+			modifiers: std::mem::take(&mut self.modifiers),
+			root
+		})
+	}
+
+	/// Moves the members of a group (or the arguments of a function call) that has been parsed
+	/// from `open_members` into `members`, where `first` is where they start in `open_members`
+	fn close_members(&mut self, first: usize) -> Range<usize> {
+		let start = self.members.len();
+		let open_members = self.open_members.drain(first..);
+		self.members.extend(open_members);
+		start..self.members.len()
+	}
+
+	fn push(&mut self, node: Node) -> NodeId {
+		self.nodes.push(node);
+		(self.nodes.len() - 1) as NodeId
+	}
+
+	fn peek(&self) -> Token {
+		self.tokens[self.pos]
+	}
+
+	fn next(&mut self) -> Token {
+		let token = self.tokens[self.pos];
+		if token.kind != TokenKind::End {self.pos += 1;}
+		token
+	}
+
+	fn unexpected(&self, token: Token) -> SyntaxError {
+		match token.kind {
+			TokenKind::Assign => token.error("Unexpected '=' (use == or != to compare numbers)"),
+			_ => token.error(format!("Unexpected '{}'", &self.text[token.start..token.end]))
+		}
+	}
+
+	fn expect(&mut self, kind: TokenKind, what: &str) -> Result<Token, SyntaxError> {
+		let token = self.peek();
+		if token.kind == kind {
+			return Ok(self.next());
+		}
+		// when collecting every error, carry on as if it was there
+		self.report(token.error(format!("Expected {}", what)), token)?;
+		Ok(token)
+	}
+
+	fn ternary(&mut self) -> Result<NodeId, SyntaxError> {
+		let condition = self.comparison()?;
+		if self.peek().kind != TokenKind::Question {
+			return Ok(condition);
+		}
+		self.next();
+		let then = self.ternary()?;
+		self.expect(TokenKind::Colon, "':' after the '?' of a conditional expression")?;
+		let otherwise = self.ternary()?;
+		Ok(self.push(Node::Ternary(condition, then, otherwise)))
+	}
+
+	fn comparison(&mut self) -> Result<NodeId, SyntaxError> {
+		let mut left = self.sum()?;
+		while let TokenKind::Compare(op) = self.peek().kind {
+			self.next();
+			let right = self.sum()?;
+			left = self.push(Node::Binary(op, left, right));
+		}
+		Ok(left)
+	}
+
+	fn sum(&mut self) -> Result<NodeId, SyntaxError> {
+		let mut left = self.product()?;
+		loop {
+			let op = match self.peek().kind {
+				TokenKind::Plus => BinOp::Add,
+				TokenKind::Minus => BinOp::Sub,
+				_ => return Ok(left)
+			};
+			self.next();
+			let right = self.product()?;
+			left = self.push(Node::Binary(op, left, right));
+		}
+	}
+
+	fn product(&mut self) -> Result<NodeId, SyntaxError> {
+		let mut left = self.unary()?;
+		loop {
+			let op = match self.peek().kind {
+				TokenKind::Times => {self.next(); BinOp::Mul},
+				TokenKind::Divide => {self.next(); BinOp::Div},
+				// a number right before ( means multiply, eg "4(2d6+3)"
+				TokenKind::Open => BinOp::Mul,
+				_ => return Ok(left)
+			};
+			let right = self.unary()?;
+			left = self.push(Node::Binary(op, left, right));
+		}
+	}
+
+	fn unary(&mut self) -> Result<NodeId, SyntaxError> {
+		match self.peek().kind {
+			TokenKind::Minus => {
+				self.next();
+				let x = self.unary()?;
+				Ok(self.push(Node::Neg(x)))
+			}
+			TokenKind::Plus => {
+				self.next();
+				self.unary()
+			}
+			_ => self.tagged()
+		}
+	}
+
+	fn tagged(&mut self) -> Result<NodeId, SyntaxError> {
+		let mut expr = self.dice()?;
+		while self.peek().kind == TokenKind::Tag {
+			let token = self.next();
+			let tag = self.text[token.start+1..token.end-1].trim();
+			expr = self.push(Node::Tagged(expr, tag.to_string()));
+		}
+		Ok(expr)
+	}
+
+	fn dice(&mut self) -> Result<NodeId, SyntaxError> {
+		let first = self.peek();
+		let (mut left, mut literal) = match first.kind {
+			TokenKind::Dice => return Err(first.error("Missing number of dice before 'd'")),
+			TokenKind::Number(_) => (self.primary()?, true),
+			_ => (self.primary()?, false)
+		};
+		while self.peek().kind == TokenKind::Dice {
+			self.next();
+			let sides_token = self.peek();
+			let sides = self.primary()?;
+			literal &= matches!(sides_token.kind, TokenKind::Number(_) | TokenKind::Percent);
+			let (keep, end) = match self.keep()? {
+				Some((keep, end, keep_literal)) => {
+					literal &= keep_literal;
+					(Some(keep), end)
+				}
+				None => (None, sides_token.end)
+			};
+			let span = match literal {
+				true => Some((first.start, end)),
+				false => None
+			};
+			left = match self.custom_modifier(keep.is_some())? {
+				Some((modifier, arg)) => self.push(Node::CustomDice{count: left, sides, modifier, arg}),
+				None => self.push(Node::Dice{count: left, sides, keep, span})
+			};
+			// the result of a dice roll is not a literal number, eg "2d6d4"
+			literal = false;
+		}
+		Ok(left)
+	}
+
+	/// Parses a keep or drop modifier (if there is one), returning it, where it ends in the
+	/// expression text, and whether its count is a literal number
+	fn keep(&mut self) -> Result<Option<(Keep, usize, bool)>, SyntaxError> {
+		let mode = match self.peek().kind {
+			TokenKind::Keep(mode) => mode,
+			_ => return Ok(None)
+		};
+		let modifier_token = self.next();
+		let count_token = self.peek();
+		Ok(Some(match count_token.kind {
+			TokenKind::Number(_) | TokenKind::Percent => (Keep{mode, count: self.primary()?}, count_token.end, true),
+			TokenKind::Open => (Keep{mode, count: self.primary()?}, count_token.end, false),
+			// "4d6k" means "4d6kh1"
+			_ => (Keep{mode, count: self.push(Node::Number(1))}, modifier_token.end, true)
+		}))
+	}
+
+	/// Parses a custom dice modifier (if there is one) and its argument (if any), returning the
+	/// position of the modifier in `modifiers` and the argument
+	fn custom_modifier(&mut self, keep: bool) -> Result<Option<(usize, Option<NodeId>)>, SyntaxError> {
+		let token = self.peek();
+		let name = &self.text[token.start..token.end];
+		let modifier = match (token.kind, self.plugins.modifier(name)) {
+			(TokenKind::Ident, Some(modifier)) => modifier,
+			_ => return Ok(None)
+		};
+		self.next();
+		if keep {
+			self.report(token.error(format!("Cannot use the '{}' modifier together with a keep or drop modifier", name)), token)?;
+		}
+		let arg = match self.peek().kind {
+			TokenKind::Number(_) | TokenKind::Percent | TokenKind::Open => Some(self.primary()?),
+			_ => None
+		};
+		self.modifiers.push(self.plugins.modifiers[modifier].clone());
+		Ok(Some((self.modifiers.len() - 1, arg)))
+	}
+
+	/// Parses the arguments of a call to a custom function, after its name
+	fn call(&mut self, function: usize) -> Result<NodeId, SyntaxError> {
+		self.expect(TokenKind::Open, "'(' after the name of a function")?;
+		let first = self.open_members.len();
+		if self.peek().kind != TokenKind::Close {
+			loop {
+				let arg = self.ternary()?;
+				self.open_members.push(arg);
+				if self.peek().kind != TokenKind::Comma {break;}
+				self.next();
+			}
+		}
+		self.expect(TokenKind::Close, "')' after the arguments of a function")?;
+		let args = self.close_members(first);
+		self.functions.push(self.plugins.functions[function].clone());
+		Ok(self.push(Node::Call{function: self.functions.len() - 1, args}))
+	}
+
+	/// Parses the name of a variable, which is an error unless variables are allowed (or, if the
+	/// variables have values, unless it has one)
+	fn variable(&mut self, token: Token) -> Result<NodeId, SyntaxError> {
+		let name = &self.text[token.start..token.end];
+		let value = match self.variables {
+			Variables::Unbound => return Ok(self.push(Node::Var(name.to_string()))),
+			Variables::Bound(value) => value(name),
+			Variables::None => None
+		};
+		match value {
+			Some(n) => Ok(self.push(Node::Number(n))),
+			None => Err(token.error(format!("Unknown variable '{}'", name)))
+		}
+	}
+
+	fn primary(&mut self) -> Result<NodeId, SyntaxError> {
+		let token = self.next();
+		match token.kind {
+			TokenKind::Number(n) => Ok(self.push(Node::Number(n))),
+			TokenKind::Decimal => {
+				let x = self.text[token.start..token.end].parse::<f64>()?;
+				Ok(self.push(Node::Decimal(x)))
+			}
+			// d% means d100
+			TokenKind::Percent => Ok(self.push(Node::Number(100))),
+			TokenKind::Open => {
+				let expr = self.ternary()?;
+				self.expect(TokenKind::Close, "')' to match '('")?;
+				Ok(expr)
+			}
+			TokenKind::OpenBrace => {
+				let first = self.open_members.len();
+				loop {
+					let member = self.ternary()?;
+					self.open_members.push(member);
+					if self.peek().kind != TokenKind::Comma {break;}
+					self.next();
+				}
+				self.expect(TokenKind::CloseBrace, "'}' to match '{'")?;
+				let members = self.close_members(first);
+				let keep = self.keep()?.map(|(keep, _, _)| keep);
+				Ok(self.push(Node::Group{members, keep}))
+			}
+			TokenKind::Ident => match self.plugins.function(&self.text[token.start..token.end]) {
+				Some(function) if self.peek().kind == TokenKind::Open => self.call(function),
+				_ => self.variable(token)
+			},
+			// "x" only means multiply when it comes between two values (eg "2x3")
+			TokenKind::Times if &self.text[token.start..token.end] != "*" => self.variable(token),
+			TokenKind::End => {
+				self.report(token.error("Unexpected end of dice expression"), token)?;
+				Ok(self.push(Node::Number(0)))
+			}
+			_ => {
+				self.report(token.error("Expected a number or '('"), token)?;
+				// when collecting every error, carry on as if the number was missing, leaving the
+				// token for whatever comes next (eg the "*" of "1+*2")
+				self.pos -= 1;
+				Ok(self.push(Node::Number(0)))
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::{Expr, Node, NodeId, Plugins, Steps};
+	use crate::SyntaxError;
+
+	fn parse(text: &str) -> Result<Expr, SyntaxError> {
+		super::parse(text, &Plugins::default(), &mut Steps::default())
+	}
+
+	fn parse_decimal(text: &str) -> Result<Expr, SyntaxError> {
+		super::parse_decimal(text, &Plugins::default(), &mut Steps::default())
+	}
+
+	/// Writes out the structure of a parsed expression, eg "(+ 2 (* 3 4))"
+	fn tree(expr: &Expr) -> String {
+		fn node(expr: &Expr, id: NodeId) -> String {
+			match expr.node(id) {
+				Node::Number(n) => n.to_string(),
+				Node::Decimal(x) => x.to_string(),
+				Node::Dice{count, sides, keep, span} => format!("(d {} {}{}{})", node(expr, *count), node(expr, *sides),
+					keep.map_or(String::new(), |keep| format!(" {:?} {}", keep.mode, node(expr, keep.count))),
+					span.map_or(String::new(), |(start, end)| format!(" @{}..{}", start, end))),
+				Node::Neg(x) => format!("(- {})", node(expr, *x)),
+				Node::Binary(op, left, right) => format!("({:?} {} {})", op, node(expr, *left), node(expr, *right)),
+				Node::Ternary(condition, then, otherwise) => format!("(? {} {} {})",
+					node(expr, *condition), node(expr, *then), node(expr, *otherwise)),
+				Node::Group{members, keep} => format!("{{{}}}{}",
+					expr.members(members).iter().map(|member| node(expr, *member)).collect::<Vec<String>>().join(", "),
+					keep.map_or(String::new(), |keep| format!(" {:?} {}", keep.mode, node(expr, keep.count)))),
+				Node::Tagged(x, tag) => format!("({} [{}])", node(expr, *x), tag),
+				Node::CustomDice{count, sides, modifier, arg} => format!("(d {} {} #{}{})", node(expr, *count), node(expr, *sides),
+					modifier, arg.map_or(String::new(), |arg| format!(" {}", node(expr, arg)))),
+				Node::Call{function, args} => format!("(#{} {})", function,
+					expr.members(args).iter().map(|arg| node(expr, *arg)).collect::<Vec<String>>().join(" ")),
+				Node::Var(name) => name.clone()
+			}
+		}
+		node(expr, expr.root)
+	}
+
+	#[test]
+	fn parse_checks() {
+		assert_eq!(tree(&parse("2+3*4").unwrap()), "(Add 2 (Mul 3 4))");
+		assert_eq!(tree(&parse("4(1-2)x3").unwrap()), "(Mul (Mul 4 (Sub 1 2)) 3)");
+		assert_eq!(tree(&parse("1 ? 2 : 0 ? 3 : 4").unwrap()), "(? 1 2 (? 0 3 4))");
+		assert_eq!(tree(&parse("1<2>=3").unwrap()), "(Ge (Lt 1 2) 3)");
+		assert_eq!(tree(&parse(" 3d6").unwrap()), "(d 3 6 @1..4)");
+		assert_eq!(tree(&parse("(2)d6").unwrap()), "(d 2 6)");
+		assert_eq!(tree(&parse("1d%").unwrap()), "(d 1 100 @0..3)");
+		assert_eq!(tree(&parse("4d6kh3").unwrap()), "(d 4 6 KeepHighest 3 @0..6)");
+		assert_eq!(tree(&parse("2d20k").unwrap()), "(d 2 20 KeepHighest 1 @0..5)");
+		assert_eq!(tree(&parse("6d6DL(1d3)").unwrap()), "(d 6 6 DropLowest (d 1 3 @6..9))");
+		assert_eq!(tree(&parse("2d6d4").unwrap()), "(d (d 2 6 @0..3) 4)");
+		assert!(parse("d20").is_err());
+		assert!(parse("4kh3").is_err());
+		assert!(parse("4d6kh3kl1").is_err());
+		assert_eq!(tree(&parse("{1d6+2, 3, 1d8}kl2").unwrap()), "{(Add (d 1 6 @1..4) 2), 3, (d 1 8 @11..14)} KeepLowest 2");
+		assert_eq!(tree(&parse("{{1, 2}k, {3}}").unwrap()), "{{1, 2} KeepHighest 1, {3}}");
+		assert!(parse("{1d6,}").is_err());
+		assert!(parse("{1d6, 2").is_err());
+		assert_eq!(tree(&parse("3[ fire ]").unwrap()), "(3 [fire])");
+		assert_eq!(tree(&parse("-(1d6+2)[acid]").unwrap()), "(- ((Add (d 1 6 @2..5) 2) [acid]))");
+		assert!(parse("2d6[fire").is_err());
+		assert!(parse("[fire]").is_err());
+		assert_eq!(parse("2d6x3").unwrap(), parse("2d6*3").unwrap());
+		assert_eq!(parse("2x x").unwrap_err().msg.as_deref(), Some("Unknown variable 'x'"));
+		assert_eq!(parse("Nd10").unwrap_err().msg.as_deref(), Some("Unknown variable 'N'"));
+		assert!(matches!(parse("1d20 = 5").unwrap_err().msg.as_deref(), Some(msg) if msg.contains("use ==")));
+		assert!(parse("1 ? 2").is_err());
+		assert!(parse("(1+2").is_err());
+		assert!(parse("1+2)").is_err());
+		assert!(parse("").is_err());
+		let err = parse("1d6+\n2.5").unwrap_err();
+		assert_eq!((err.line, err.col), (Some(2), Some(2)));
+		assert_eq!(tree(&parse_decimal("1d6*1.5+.5").unwrap()), "(Add (Mul (d 1 6 @0..3) 1.5) 0.5)");
+		assert!(parse_decimal("1..5").is_err());
+	}
+
+	#[test]
+	fn plugin_checks() {
+		use std::sync::Arc;
+		use crate::plugin::Callback;
+		let mut plugins = Plugins::default();
+		plugins.functions.push(("max".into(), Callback(Arc::new(|args: &[i64]| Ok(args.iter().copied().max().unwrap_or(0))))));
+		plugins.modifiers.push(("s".into(), Callback(Arc::new(|dice: &[u32], _: u32, arg: Option<i64>|
+			Ok(dice.iter().filter(|r| **r as i64 >= arg.unwrap_or(1)).count() as i64)))));
+		let parse = |text: &str| super::parse(text, &plugins, &mut Steps::default());
+		assert_eq!(tree(&parse("max(1d20, 1d20+2) + 1").unwrap()), "(Add (#0 (d 1 20 @4..8) (Add (d 1 20 @10..14) 2)) 1)");
+		assert_eq!(tree(&parse("max()").unwrap()), "(#0 )");
+		assert_eq!(tree(&parse("6d10s8").unwrap()), "(d 6 10 #0 8)");
+		assert_eq!(tree(&parse("6d10s(1d4)*2").unwrap()), "(Mul (d 6 10 #0 (d 1 4 @6..9)) 2)");
+		assert_eq!(tree(&parse("2d6s").unwrap()), "(d 2 6 #0)");
+		// without a '(', the name of a function is an unknown variable
+		assert!(parse("max").is_err());
+		assert!(super::parse("max(2)", &Plugins::default(), &mut Steps::default()).is_err());
+		assert!(super::parse("6d10s8", &Plugins::default(), &mut Steps::default()).is_err());
+		assert!(parse("max(1, 2").is_err());
+		assert!(parse("max(1,)").is_err());
+		assert!(parse("4d6kh3s4").is_err());
+		let expr = parse("max(1d4, 1d4)").unwrap();
+		assert_eq!(expr.range().unwrap(), (1, 4));
+		assert!((expr.mean().unwrap() - 50. / 16.).abs() < 1e-9);
+		let expr = parse("3d6s5").unwrap();
+		assert_eq!(expr.range().unwrap(), (0, 3));
+		assert!((expr.mean().unwrap() - 1.).abs() < 1e-9);
+		// too many combinations to work out exactly
+		let expr = parse("40d6s5").unwrap();
+		assert_eq!(expr.range().unwrap(), (0, 40));
+		assert_eq!(expr.mean().unwrap(), 20.);
+	}
+
+	#[test]
+	fn render_checks() {
+		/// The structure of an expression, without the positions of its dice in the text
+		fn unspanned(expr: &Expr) -> String {
+			let mut text = tree(expr);
+			while let Some(start) = text.find(" @") {
+				let end = start + text[start..].find(')').unwrap_or(text.len() - start);
+				text.replace_range(start..end, "");
+			}
+			text
+		}
+		let parse = |text: &str| super::parse_with_variables(text, &Plugins::default(), &mut Steps::default());
+		for (text, rendered) in [
+			("2d6 + 3", "2d6+3"), ("2D20KL1", "2d20kl1"), ("4d6kh3", "4d6kh3"), ("4d6k", "4d6kh1"), ("1d%", "1d100"),
+			("(1d4)d6", "(1d4)d6"), ("6d6dl(1d3)", "6d6dl(1d3)"), ("2*(3+4)", "2*(3+4)"), ("10-(3-2)", "10-(3-2)"),
+			("10-3-2", "10-3-2"), ("4(2d6+3)", "4*(2d6+3)"), ("-(1d6)", "-1d6"), ("-(2+3)", "-(2+3)"), ("x*2", "x*2"),
+			("1d20+5>=15 ? 2d6[fire] : 0", "1d20+5 >= 15 ? 2d6[fire] : 0"), ("(2d6+3)[fire]", "(2d6+3)[fire]"),
+			("2d6[fire][magic]", "2d6[fire][magic]"), ("{1d20+2,1d20}kh1", "{1d20+2, 1d20}kh1"),
+			("a ? b ? 1 : 2 : 3", "a ? b ? 1 : 2 : 3"), ("(a ? 1 : 2) ? 3 : 4", "(a ? 1 : 2) ? 3 : 4"),
+			("(1 < 2) == 1", "1 < 2 == 1"), ("1 < (2 == 1)", "1 < (2 == 1)"), ("(lvl)d6", "(lvl)d6")
+		] {
+			let expr = parse(text).unwrap();
+			assert_eq!(expr.to_string(), rendered);
+			assert_eq!(unspanned(&parse(rendered).unwrap()), unspanned(&expr));
+		}
+	}
+
+	#[test]
+	fn partial_eval_checks() {
+		use std::sync::Arc;
+		use crate::plugin::Callback;
+		let mut plugins = Plugins::default();
+		plugins.functions.push(("max".into(), Callback(Arc::new(|args: &[i64]| Ok(args.iter().copied().max().unwrap_or(0))))));
+		plugins.modifiers.push(("s".into(), Callback(Arc::new(|dice: &[u32], _: u32, arg: Option<i64>|
+			Ok(dice.iter().filter(|r| **r as i64 >= arg.unwrap_or(1)).count() as i64)))));
+		let fold = |text: &str| super::parse_with_variables(text, &plugins, &mut Steps::default()).unwrap()
+			.partial_eval(|name| [("prof", 2), ("str", 3), ("lvl", 7)].iter().find(|(var, _)| *var == name).map(|(_, v)| *v));
+		let folded = |text: &str| fold(text).unwrap().to_string();
+		assert_eq!(folded("1d20+prof+str"), "1d20+5");
+		assert_eq!(folded("1d20+prof-4"), "1d20-2");
+		assert_eq!(folded("prof+1d20"), "1d20+2");
+		assert_eq!(folded("5-1d20"), "-1d20+5");
+		assert_eq!(folded("1d20+(prof-2)"), "1d20");
+		assert_eq!(folded("(prof+str)d6"), "5d6");
+		assert_eq!(folded("2*3+1d4*(1+1)"), "1d4*2+6");
+		assert_eq!(folded("lvl >= 5 ? 2d6 : 1d6"), "2d6");
+		assert_eq!(folded("dex >= 5 ? 2d6+prof : 1d6"), "dex >= 5 ? 2d6+2 : 1d6");
+		assert_eq!(folded("{1d20+str, 1d20+str}kh1"), "{1d20+3, 1d20+3}kh1");
+		assert_eq!(folded("2d6[fire]+1+prof"), "2d6[fire]+3");
+		assert_eq!(folded("1d4-1d4+dex"), "1d4-1d4+dex");
+		assert_eq!(folded("str*(lvl+1)"), "24");
+		assert_eq!(folded("max(1, str) + 1d4"), "1d4+3");
+		assert_eq!(folded("max(1d4, prof)"), "max(1d4, 2)");
+		assert_eq!(folded("6d10s(lvl+1)"), "6d10s8");
+		assert!(fold("1d4+10/(str-3)").is_err());
+		// only the nodes that are still needed are kept
+		let expr = fold("1d20+(prof+3)*str").unwrap();
+		assert_eq!(tree(&expr), "(Add (d 1 20) 15)");
+		assert_eq!(expr.nodes.len(), 5);
+	}
+
+	#[test]
+	fn roll_checks() {
+		use super::Rolls;
+		// rolling with and without recording the dice gives the same totals for the same rolls
+		for text in ["4d6kh3", "6d6dl(1d3)+2", "{1d20+5, 1d20+5}kl1", "{2d6[fire], 1d8[cold]}kh1+1d4", "{1, 2d4}",
+				"(1d4)d6 + 1d8", "1d2-1 ? 1d6 : 1d8", "{0 ? 1d6 : 1d8, 1d10}"] {
+			let expr = parse(text).unwrap();
+			let (mut a, mut b) = (crate::simple_rng(7), crate::simple_rng(7));
+			for _ in 0..100 {
+				let mut rolls = Rolls::default();
+				assert_eq!(expr.roll(&mut a, &mut None, &mut Steps::default()).unwrap(), expr.roll(&mut b, &mut Some(&mut rolls), &mut Steps::default()).unwrap());
+			}
+		}
+		// dice written with literal numbers are rolled first, then conditional expressions, then
+		// the rest of the dice
+		let mut rolls = Rolls::default();
+		parse("(1d4)d6 + (1 ? 1d10 : 0) + 1d8").unwrap().roll(&mut crate::simple_rng(7), &mut Some(&mut rolls), &mut Steps::default()).unwrap();
+		assert_eq!(rolls.dice.iter().map(|d| d.sides).collect::<Vec<u32>>(), vec![4, 8, 10, 6]);
+		assert_eq!(rolls.literals.iter().map(|(start, end, _)| (*start, *end)).collect::<Vec<_>>(), vec![(1, 4), (27, 30), (15, 19)]);
+		assert!(parse("1/(1d2-1)*0").unwrap().range().is_err());
+	}
+
+	#[test]
+	fn range_checks() {
+		// the extremes are what the expression comes out as if every die rolls its lowest or its
+		// highest
+		assert_eq!(parse("5-1d6").unwrap().range().unwrap(), (4, -1));
+		assert_eq!(parse("-(1d6)").unwrap().range().unwrap(), (-1, -6));
+		assert_eq!(parse("10/(1d3)").unwrap().range().unwrap(), (10, 3));
+		assert_eq!(parse("(1d4)d6dl(1d2)").unwrap().range().unwrap(), (0, 12));
+		assert_eq!(parse("{1d6, 1d4-5}kl1").unwrap().range().unwrap(), (-4, -1));
+		assert_eq!(parse("1d2-1 ? 2d6 : 0").unwrap().range().unwrap(), (0, 12));
+		assert_eq!(parse("1d6 ? 1d4 : 10").unwrap().range().unwrap(), (1, 4));
+		assert_eq!(parse("101d1000 >= 50000").unwrap().range().unwrap(), (0, 1));
+	}
+
+	#[test]
+	fn mean_checks() {
+		use super::kept_mean;
+		assert_eq!(parse("7/2").unwrap().mean().unwrap(), 3.5);
+		assert_eq!(parse("(1d4)d6").unwrap().mean().unwrap(), 8.75);
+		assert_eq!(parse_decimal("2.5*2").unwrap().mean().unwrap(), 5.);
+		// decimal numbers have no exact probabilities, so this estimates the chance
+		assert!((parse_decimal("1d6 >= 3.5").unwrap().mean().unwrap() - 0.5).abs() < 1e-9);
+		// too many possible results to work out exactly (the exact chance is about 0.575)
+		assert!((parse("101d1000 >= 50000").unwrap().mean().unwrap() - 0.575).abs() < 0.01);
+		assert!(parse("101d1000 >= 60000").unwrap().mean().unwrap() < 0.01);
+		assert!((parse("101d1000 == 50550").unwrap().mean().unwrap() - 1.375e-4).abs() < 1e-5);
+		// the chance of a condition that is not a comparison is estimated the same way
+		let p = 1. - parse("101d1000 == 50500").unwrap().mean().unwrap();
+		assert!((parse("(101d1000-50500) ? 10 : 0").unwrap().mean().unwrap() - 10. * p).abs() < 1e-9);
+		assert!(parse("(101d1000-50500) ? 10 : 0").unwrap().mean().unwrap() < 10.);
+		assert!((kept_mean(4., 6., 3., true).unwrap() - 15869. / 1296.).abs() < 1e-9);
+		assert!((kept_mean(2., 20., 1., false).unwrap() - 7.175).abs() < 1e-9);
+		assert!(kept_mean(2.5, 6., 1., true).is_none());
+	}
+
+	#[test]
+	fn pmf_checks() {
+		use super::{dice_sum_pmf, keep_pmf, kept_dice_pmf, kept_mean};
+		let pmf = dice_sum_pmf::<f64>(2, 6).unwrap();
+		assert_eq!(pmf.len(), 13);
+		assert!((pmf[7] - 6. / 36.).abs() < 1e-12);
+		assert!((pmf.iter().sum::<f64>() - 1.).abs() < 1e-12);
+		let expr = parse("1d4 ? 1d6 : 0").unwrap();
+		let pmf = expr.pmf_of(expr.root).unwrap();
+		assert_eq!(pmf.len(), 6);
+		let expr = parse("1d6/(1d3-2)").unwrap();
+		assert!(expr.pmf_of(expr.root).is_none());
+		let d20: Vec<(i64, f64)> = (1..=20).map(|r| (r, 0.05)).collect();
+		let pmf = keep_pmf(&[d20.clone(), d20.clone()], &[(1, 1.)], true).unwrap();
+		assert_eq!((pmf[0].0, pmf[19].0), (1, 20));
+		assert!((pmf[19].1 - 39. / 400.).abs() < 1e-12);
+		assert!((pmf[0].1 - 1. / 400.).abs() < 1e-12);
+		let pmf = keep_pmf(&[d20.clone(), d20.clone(), d20], &[(0, 1.)], false).unwrap();
+		assert!(pmf.len() == 1 && (pmf[0].1 - 1.).abs() < 1e-12);
+		let expr = parse("100d100kh50").unwrap();
+		assert!(expr.pmf_of(expr.root).is_none());
+		// the order statistics of identical dice match trying every combination of them
+		let d6: Vec<(i64, f64)> = (1..=6).map(|r| (r, 1. / 6.)).collect();
+		for (n, kept) in [(1, 1), (3, 0), (3, 2), (4, 3), (5, 1), (5, 5)] {
+			for highest in [true, false] {
+				let fast = kept_dice_pmf::<f64>(n, 6, kept, highest).unwrap();
+				let slow = keep_pmf(&vec![d6.clone(); n], &[(kept, 1.)], highest).unwrap();
+				assert_eq!(fast.len(), slow.len());
+				for ((total, p), (slow_total, slow_p)) in fast.iter().zip(slow.iter()) {
+					assert!(total == slow_total && (p - slow_p).abs() < 1e-12);
+				}
+				let mean: f64 = fast.iter().map(|(total, p)| *total as f64 * p).sum();
+				assert!((kept_mean(n as f64, 6., kept as f64, highest).unwrap() - mean).abs() < 1e-9);
+			}
+		}
+		let pmf = kept_dice_pmf::<f64>(10, 10, 4, true).unwrap();
+		let mean: f64 = pmf.iter().map(|(total, p)| *total as f64 * p).sum();
+		assert!((kept_mean(10., 10., 4., true).unwrap() - mean).abs() < 1e-9);
+		assert!((kept_mean(2., 20., 1., true).unwrap() - 13.825).abs() < 1e-9);
+		assert!((kept_mean(2., 20., 1., false).unwrap() - 7.175).abs() < 1e-9);
+	}
+}
@@ -0,0 +1,110 @@
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::{simple_rng, verify_roll, DiceBag, DiceRoll, SyntaxError};
+
+/// Returns the commitment to a seed: the SHA-256 hash of the seed written as a decimal number, in
+/// lowercase hexadecimal (so it can also be checked with other tools, eg
+/// `echo -n 1234 | sha256sum`)
+/// # Parameters
+/// * `seed`: the secret seed to commit to
+pub fn commit_seed(seed: u64) -> String {
+	Sha256::digest(seed.to_string().as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Combines the roller's secret seed with the seed picked by the other player, by taking the first
+/// 8 bytes of the SHA-256 hash of "SEED:PLAYER_SEED" as a little-endian number
+fn combine_seeds(seed: u64, player_seed: u64) -> u64 {
+	let hash = Sha256::digest(format!("{}:{}", seed, player_seed).as_bytes());
+	let mut bytes = [0u8; 8];
+	bytes.copy_from_slice(&hash[..8]);
+	u64::from_le_bytes(bytes)
+}
+
+/// Rolls dice with a commit/reveal protocol, so that a remote roll can be trusted without trusting
+/// the roller:
+/// 1. the roller picks a secret seed and publishes `FairRoller.commitment()`
+/// 2. the other player picks a seed of their own and sends it to the roller
+/// 3. the roller calls `FairRoller.roll(...)` and publishes the resulting `FairRoll`, which reveals
+///    the secret seed
+/// 4. anyone can check the roll with `FairRoll.verify(...)`
+///
+/// The commitment stops the roller from changing their seed after seeing the other player's seed,
+/// and the other player's seed stops the roller from picking a seed that rolls well.
+///
+/// # Example
+/// ```
+/// use dicexp::FairRoller;
+/// let roller = FairRoller::new(8675309);
+/// let commitment = roller.commitment().to_string(); // sent to the other player before rolling
+/// let roll = roller.roll(42, "1d20+5").expect("Error"); // 42 is the other player's seed
+/// assert!(roll.verify(&commitment));
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FairRoller {
+	seed: u64,
+	commitment: String
+}
+
+impl FairRoller {
+	/// Constructs a new `FairRoller`
+	/// # Parameters
+	/// * `seed`: the secret seed, which should be picked at random (eg with `rand::random()`) and
+	///   not shared until after rolling
+	pub fn new(seed: u64) -> Self {
+		FairRoller{seed, commitment: commit_seed(seed)}
+	}
+
+	/// Returns the commitment to publish before rolling (see `commit_seed(...)`)
+	pub fn commitment(&self) -> &str {
+		&self.commitment
+	}
+
+	/// Rolls the given RPG dice notation expression with the secret seed combined with the other
+	/// player's seed, returning the roll along with everything needed to verify it
+	/// # Parameters
+	/// * `player_seed`: the seed picked by the other player after seeing the commitment
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	pub fn roll(&self, player_seed: u64, dice_expression: &str) -> Result<FairRoll, SyntaxError> {
+		let roll = DiceBag::new(simple_rng(combine_seeds(self.seed, player_seed))).eval(dice_expression)?;
+		Ok(FairRoll{
+			expression: dice_expression.to_string(),
+			seed: self.seed,
+			player_seed,
+			roll
+		})
+	}
+}
+
+/// A roll made with `FairRoller.roll(...)`, which reveals the roller's secret seed so that anyone
+/// can check it against the commitment published before rolling
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct FairRoll {
+	/// The dice expression that was rolled
+	pub expression: String,
+	/// The roller's secret seed
+	pub seed: u64,
+	/// The seed picked by the other player
+	pub player_seed: u64,
+	/// The roll
+	pub roll: DiceRoll
+}
+
+impl FairRoll {
+	/// Returns true if the seed matches the commitment and rolling the expression again with the
+	/// combined seeds gives the same roll
+	/// # Parameters
+	/// * `commitment`: the commitment that was published before rolling
+	pub fn verify(&self, commitment: &str) -> bool {
+		commit_seed(self.seed).eq_ignore_ascii_case(commitment.trim())
+			&& verify_roll(combine_seeds(self.seed, self.player_seed), &self.expression, &self.roll)
+	}
+}
+
+impl core::fmt::Display for FairRoll {
+	/// core::fmt::Display implementation returns the total result
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
+		std::fmt::Display::fmt(&self.roll.total, f)
+	}
+}
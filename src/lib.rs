@@ -1,17 +1,57 @@
 #![deny(unused_must_use)]
 use std::error::Error;
 use core::fmt::{Debug, Formatter};
-use std::collections::HashSet;
-use std::hash::Hasher;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::num::{ParseFloatError, ParseIntError};
-use rand;
+use std::sync::Arc;
 use rand::RngCore;
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
 
+/// Enters a tracing span named after the `DiceBag` method for the rest of the enclosing block
+/// (when the `tracing` feature is enabled), so that the events emitted while parsing and rolling
+/// an expression can be traced back to the expression
+macro_rules! expression_span {
+	($method:literal, $($field:tt)*) => {
+		#[cfg(feature = "tracing")]
+		let _span = tracing::debug_span!($method, $($field)*).entered();
+	};
+}
+
+mod check;
+mod distribution;
+#[cfg(feature = "exact")]
+mod exact;
+mod expression;
+#[cfg(feature = "fair")]
+mod fair;
+mod plugin;
+mod roller;
+mod shared;
+#[cfg(feature = "i18n")]
+mod locale;
+pub use check::{OpposedRoll, Outcome, TieBreak, VsRoll, Winner};
+pub use distribution::Distribution;
+pub use roller::{DieRoller, FixedRolls, FnRoller};
+pub use shared::SharedDiceBag;
+#[cfg(feature = "exact")]
+pub use exact::ExactRoll;
+#[cfg(feature = "exact")]
+pub use num_rational::Rational64;
+#[cfg(feature = "fair")]
+pub use fair::{FairRoll, FairRoller, commit_seed};
+#[cfg(feature = "i18n")]
+pub use locale::Locale;
+use expression::Notation;
+use expression::Steps;
+use plugin::{Callback, Plugins};
+
 /// The DiceBag struct is use to evaluate RPG dice notation expressions (eg "2d6+3")
 ///
-/// If the provided RNG implements any of `Debug`, `Clone`, `PartialEq`, `Eq`, `Hash`, or `Default`,
+/// Any RNG from the rand crate can be used to roll the dice, as can any other `DieRoller` (such
+/// as a closure wrapped in a `FnRoller`). If the provided RNG implements any of `Debug`, `Clone`, `PartialEq`, `Eq`, `Hash`, or `Default`,
 /// then `DiceBag` will implement the same.
 ///
 /// # Example
@@ -23,15 +63,17 @@ use serde::{Deserialize, Serialize};
 /// println!("Rolled {}: {}", dice_exp, dice_roll);
 /// println!("The average result is {:.1}", dice_roll.average);
 /// ```
-pub struct DiceBag <R: rand::Rng>{
-	rng: R
+pub struct DiceBag <R: DieRoller>{
+	rng: R,
+	plugins: Plugins,
+	step_limit: Option<u64>
 }
-impl <R>Clone for DiceBag<R> where R: rand::Rng+Clone{
+impl <R>Clone for DiceBag<R> where R: DieRoller+Clone{
 	fn clone(&self) -> Self {
-		DiceBag{rng: self.rng.clone()}
+		DiceBag{rng: self.rng.clone(), plugins: self.plugins.clone(), step_limit: self.step_limit}
 	}
 }
-impl <R>Debug for DiceBag<R> where R: rand::Rng+Debug{
+impl <R>Debug for DiceBag<R> where R: DieRoller+Debug{
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
 		write!(f, "DiceBag{{")?;
 		self.rng.fmt(f)?;
@@ -39,266 +81,416 @@ impl <R>Debug for DiceBag<R> where R: rand::Rng+Debug{
 	}
 }
 
-impl <R>PartialEq for DiceBag<R> where R: rand::Rng+PartialEq{
+impl <R>PartialEq for DiceBag<R> where R: DieRoller+PartialEq{
 	fn eq(&self, other: &Self) -> bool {
-		self.rng.eq(&other.rng)
+		self.rng.eq(&other.rng) && self.plugins == other.plugins && self.step_limit == other.step_limit
 	}
 }
 
-impl <R>Eq for DiceBag<R> where R: rand::Rng+Eq{}
+impl <R>Eq for DiceBag<R> where R: DieRoller+Eq{}
 
-impl <R> std::hash::Hash for DiceBag<R> where R: rand::Rng+std::hash::Hash{
+impl <R> std::hash::Hash for DiceBag<R> where R: DieRoller+std::hash::Hash{
 	fn hash<H: Hasher>(&self, state: &mut H) {
 		self.rng.hash(state)
 	}
 }
 
-impl <R>Default for DiceBag<R> where R: rand::Rng+Default{
+impl <R>Default for DiceBag<R> where R: DieRoller+Default{
 	fn default() -> Self {
-		DiceBag{rng: R::default()}
+		DiceBag{rng: R::default(), plugins: Plugins::default(), step_limit: None}
 	}
 }
 
-impl <R> DiceBag<R> where R: rand::Rng {
+impl <R> DiceBag<R> where R: DieRoller {
 	/// Constructs a new `DiceBag` instance
 	/// # Parameters
 	/// * `rng`: A random number generator to use for rolling dice
-	pub fn new(rng: R) -> Self { DiceBag{rng} }
+	pub fn new(rng: R) -> Self { DiceBag{rng, plugins: Plugins::default(), step_limit: None} }
+
+	/// Limits how much work each call can do before it gives up with an error (see
+	/// `SyntaxError.is_step_limit()`), to bound the time spent on hostile expressions (eg
+	/// "1000000000d6" or thousands of nested parentheses) on a server. Every token of the
+	/// expression (and its end), every part of the expression that is rolled, and every die rolled
+	/// is a step, so "2d6+3" takes 13 steps. Methods that roll many times (eg
+	/// `DiceBag.sample_distribution(...)`) count the steps of all of the rolls. There is no limit
+	/// by default.
+	/// # Parameters
+	/// * `limit`: the most steps that a call can take, or `None` for no limit
+	pub fn set_step_limit(&mut self, limit: Option<u64>) {
+		self.step_limit = limit;
+	}
+
+	/// Returns the step limit (see `DiceBag.set_step_limit(...)`)
+	pub fn step_limit(&self) -> Option<u64> {
+		self.step_limit
+	}
+
+	/// Starts counting the steps of a call
+	fn steps(&self) -> Steps {
+		Steps::new(self.step_limit)
+	}
+
+	/// Registers a custom function that dice expressions can call by name (eg "max(1d20, 1d20)"
+	/// after registering "max"). Where there are few enough possible results, the min, max, and
+	/// average of an expression that calls the function are worked out by calling it with every
+	/// combination of arguments. Otherwise the min and max are estimated from the extremes of its
+	/// arguments (which is exact if its result only goes up or only goes down as each argument
+	/// goes up), and the average is taken as halfway between the min and max.
+	/// # Parameters
+	/// * `name`: the name of the function (letters, digits, and underscores, not starting with a
+	///   digit)
+	/// * `function`: takes the results of the arguments and returns the result of the function
+	pub fn register_function<F>(&mut self, name: &str, function: F) -> Result<(),SyntaxError>
+		where F: Fn(&[i64]) -> Result<i64,SyntaxError> + Send + Sync + 'static {
+		self.plugins.check_name(name, false)?;
+		self.plugins.functions.push((name.to_string(), Callback(Arc::new(function))));
+		Ok(())
+	}
+
+	/// Registers a custom dice modifier that comes after a dice term, optionally followed by a
+	/// number or an expression in parentheses (eg "6d10s8" after registering "s" to count the
+	/// dice that roll 8 or more). A dice term cannot have both a custom modifier and a keep or
+	/// drop modifier. The min, max, and average are worked out in the same way as for custom
+	/// functions (see `DiceBag.register_function(...)`), where the extremes of the dice are all of
+	/// them rolling 1 or all of them rolling their highest result.
+	/// # Parameters
+	/// * `name`: the name of the modifier (letters and underscores only)
+	/// * `modifier`: takes the results of the dice (in the order that they were rolled), the
+	///   number of sides of the dice, and the modifier's argument (if any), and returns the result
+	///   of the dice term
+	pub fn register_modifier<F>(&mut self, name: &str, modifier: F) -> Result<(),SyntaxError>
+		where F: Fn(&[u32], u32, Option<i64>) -> Result<i64,SyntaxError> + Send + Sync + 'static {
+		self.plugins.check_name(name, true)?;
+		self.plugins.modifiers.push((name.to_string(), Callback(Arc::new(modifier))));
+		Ok(())
+	}
 
 	/// Rolls a number of dice and returns the result
 	/// # Parameters
 	/// * `n`: number of dice to roll
 	/// * `d`: number of sides per die
 	/// * `m`: number to add to the total
+	///
+	/// Panics if the dice cannot be rolled (see `DiceBag.try_roll(...)`), which never happens with
+	/// the RNGs of the rand crate.
 	pub fn roll(&mut self, n: u32, d: u32, m: i64) -> i64 {
+		self.try_roll(n, d, m).expect("Failed to roll the dice")
+	}
+
+	/// Rolls a number of dice and returns the result, or an error if the die roller fails to roll
+	/// a die (eg when a `FixedRolls` runs out of rolls) or the total is too big
+	/// # Parameters
+	/// * `n`: number of dice to roll
+	/// * `d`: number of sides per die
+	/// * `m`: number to add to the total
+	pub fn try_roll(&mut self, n: u32, d: u32, m: i64) -> Result<i64,SyntaxError> {
 		let mut total = 0i64;
 		for _ in 0..n {
-			let roll: u32 = self.rng.gen_range(1..=d);
-			total += roll as i64;
+			let roll: u32 = self.rng.roll_die(d)?;
+			total = total.checked_add(roll as i64).ok_or_else(expression::overflow)?;
 		}
-		return total + m;
+		total.checked_add(m).ok_or_else(expression::overflow)
 	}
 
 	/// Evaluates the given RPG dice notation expression
 	/// # Parameters
 	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
 	pub fn eval(&mut self, dice_expression: &str) -> Result<DiceRoll,SyntaxError>{
-		Ok(DiceRoll{
-			total: self.eval_total(dice_expression)?,
-			min: self.eval_min(dice_expression)?,
-			max: self.eval_max(dice_expression)?,
-			average: self.eval_ave(dice_expression)?,
-		})
+		expression_span!("eval", expression = dice_expression);
+		let mut steps = self.steps();
+		let expr = expression::parse(dice_expression, &self.plugins, &mut steps)?;
+		let total = expr.roll(&mut self.rng, &mut None, &mut steps)?;
+		roll_stats(&expr, total)
 	}
 
 
+	/// Parses the given RPG dice notation expression without rolling it, so that it can be
+	/// inspected (eg to refuse to roll expressions with too many dice) and then rolled any number
+	/// of times with `DiceBag.eval_expr(...)`
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	pub fn parse(&self, dice_expression: &str) -> Result<DiceExpr,SyntaxError>{
+		let mut steps = self.steps();
+		Ok(DiceExpr{expr: expression::parse_with_variables(dice_expression, &self.plugins, &mut steps)?})
+	}
+
+	/// Parses the given RPG dice notation expression like `DiceBag.parse(...)`, but carries on
+	/// after each syntax error to find the rest of them, returning every error (in the order that
+	/// they appear) along with where it is in the expression, or nothing if the expression parses.
+	/// This is for showing every problem in an expression at once (eg by underlining them in a
+	/// form). Problems that are only found when rolling (eg unknown variables) are not included.
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	pub fn parse_all_errors(&self, dice_expression: &str) -> Vec<SyntaxDiagnostic>{
+		expression::parse_errors(dice_expression, &self.plugins, false)
+	}
+
+	/// Finds every syntax error in a script of dice expressions separated by semicolons (see
+	/// `DiceBag.eval_script(...)` and `DiceBag.parse_all_errors(...)`), such as a long macro
+	/// # Parameters
+	/// * `script`: the statements to check, separated by semicolons
+	pub fn parse_script_all_errors(&self, script: &str) -> Vec<SyntaxDiagnostic>{
+		let statements = split_statements(script);
+		if statements.is_empty() {
+			let error = SyntaxError::from("Script has no dice expressions");
+			return vec![SyntaxDiagnostic{error, start: script.len(), end: script.len()}];
+		}
+		let mut errors = Vec::new();
+		for (start, statement) in statements {
+			let (start, dice_expression) = match find_assignment(statement, &self.plugins) {
+				Some(eq) => (start + eq + 1, &statement[eq+1..]),
+				None => (start, statement)
+			};
+			for mut diagnostic in expression::parse_errors(dice_expression, &self.plugins, true) {
+				diagnostic.shift(&script[..start]);
+				errors.push(diagnostic);
+			}
+		}
+		errors
+	}
+
+	/// Evaluates an expression that was parsed with `DiceBag.parse(...)`
+	/// # Parameters
+	/// * `dice_expression`: the parsed dice expression
+	pub fn eval_expr(&mut self, dice_expression: &DiceExpr) -> Result<DiceRoll,SyntaxError>{
+		let mut steps = self.steps();
+		let total = dice_expression.expr.roll(&mut self.rng, &mut None, &mut steps)?;
+		roll_stats(&dice_expression.expr, total)
+	}
+
 	/// Evaluates the given RPG dice notation expression and returns the total dice roll
 	/// # Parameters
 	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
 	pub fn eval_total(&mut self, dice_expression: &str) -> Result<i64,SyntaxError>{
-		self.eval_as(dice_expression, EvalMode::Roll)?.parse::<i64>().map_err(|e| SyntaxError::from(e))
+		expression_span!("eval_total", expression = dice_expression);
+		let mut steps = self.steps();
+		self.total_with(dice_expression, &mut steps)
+	}
+
+	/// Evaluates the given RPG dice notation expression and returns the total dice roll, counting
+	/// the steps taken in `steps`
+	fn total_with(&mut self, dice_expression: &str, steps: &mut Steps) -> Result<i64,SyntaxError>{
+		expression::parse(dice_expression, &self.plugins, steps)?.roll(&mut self.rng, &mut None, steps)
 	}
 
 	/// Evaluates the given RPG dice notation expression and returns the minimum dice roll
 	/// # Parameters
 	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
 	pub fn eval_min(&mut self, dice_expression: &str) -> Result<i64,SyntaxError>{
-		self.eval_as(dice_expression, EvalMode::Minimum)?.parse::<i64>().map_err(|e| SyntaxError::from(e))
+		let mut steps = self.steps();
+		Ok(expression::parse(dice_expression, &self.plugins, &mut steps)?.range()?.0)
 	}
 
 	/// Evaluates the given RPG dice notation expression and returns the maximum dice roll
 	/// # Parameters
 	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
 	pub fn eval_max(&mut self, dice_expression: &str) -> Result<i64,SyntaxError>{
-		self.eval_as(dice_expression, EvalMode::Maximum)?.parse::<i64>().map_err(|e| SyntaxError::from(e))
+		let mut steps = self.steps();
+		Ok(expression::parse(dice_expression, &self.plugins, &mut steps)?.range()?.1)
 	}
 
 	/// Evaluates the given RPG dice notation expression and returns the average dice roll
 	/// # Parameters
 	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
 	pub fn eval_ave(&mut self, dice_expression: &str) -> Result<f64,SyntaxError>{
-		self.eval_as(dice_expression, EvalMode::Average)?.parse::<f64>().map_err(|e| SyntaxError::from(e))
-	}
-
-	fn eval_as(&mut self, dice_expression: &str, mode: EvalMode) -> Result<String, SyntaxError> {
-		if dice_expression.starts_with("-") || dice_expression.starts_with("+"){
-			// must start with a number or there will be problems
-			let mut new_exp = String::from("0");
-			new_exp.push_str(dice_expression);
-			return self.eval_as(new_exp.as_str(), mode);
-		}
-		let mut x = String::new();
-		// need to remove all whitespace, also using this opportunity to throw common exceptions
-		let mut line = 1;
-		let mut col = 0;
-		let mut last_c = ' ';
-		for c in dice_expression.chars() {
-			if c == '\n' {
-				line += 1;
-				col = 0;
-			}
-			col += 1;
-			if c.is_whitespace() {continue;}
-			match mode{
-				// decimals allowed in average mode, but otherwise it is ints-only
-				EvalMode::Average => {},
-				_ => {
-					if c == '.' {return Err(SyntaxError{
-						msg: Some("Found '.', but decimal numbers are not supported (integer math only)".into()),
-						line: Some(line), col: Some(col), cause: None
-					});}
-				}
-			}
-			if c == '%' {
-				// d% means d100
-				x.push_str("100")
-			} else if c == 'x' || c == 'X' {
-				// multiplication old-school notation
-				x.push('*');
-			} else if c == '-' && last_c != '+' && last_c != '/' && last_c != '*' {
-				// turn - into +- to avoid confusion over subtraction vs negative numbers
-				x.push_str("+-")
-			} else if c == '(' && (last_c.is_digit(10) || last_c == '.') {
-				// number right before ( means multiply
-				x.push_str("*(")
-			} else {
-				x.push(c);
-			}
-			last_c = c;
-		}
-		#[cfg(test)]
-		eprintln!(">> {}", x);
-		// Parentheses
-		while match x.find("(") {
-			None => false,
-			Some(i) => {
-				let cpy =  x.clone();
-				let x_str = cpy.as_str();
-				let (open, close) = find_enclosure_from(x_str, i, '(', ')')?
-					.ok_or_else(|| SyntaxError::from("Error: unmatched parentheses"))?;
-				let middle = self.eval_as(&x_str[open+1 .. close-1], mode)?;
-				let front = &x_str[0..open];
-				let back = &x_str[close..];
-				x.clear();
-				x.push_str(front);
-				x.push_str(middle.as_str());
-				x.push_str(back);
-				true
-			}
-		}{}
-		// Dice
-		while match x.find("d") {
-			None => false,
-			Some(i) => {
-				let cpy =  x.clone();
-				let x_str = cpy.as_str();
-				let (start, end) = find_operator_params(x_str, i)?;
-				let n = &x_str[start..i].parse::<u32>().map_err(|e| SyntaxError::from(e.clone()))?;
-				let d = &x_str[i+1..end].parse::<u32>().map_err(|e| SyntaxError::from(e.clone()))?;
-				let middle: String;
-				match mode {
-					EvalMode::Roll => middle = format!("{}", self.roll(*n, *d, 0)),
-					EvalMode::Average => middle = format!("{:.1}", *n as f64 * 0.5 * (1f64 + *d as f64)),
-					EvalMode::Minimum => middle = format!("{}", n),
-					EvalMode::Maximum => middle = format!("{}", n * d),
-				}
-				let front = &x_str[0..start];
-				let back = &x_str[end..];
-				x.clear();
-				x.push_str(front);
-				x.push_str(middle.as_str());
-				x.push_str(back);
-				true
+		let mut steps = self.steps();
+		expression::parse_decimal(dice_expression, &self.plugins, &mut steps)?.mean()
+	}
+
+	/// Evaluates the given RPG dice notation expression, returning the result along with all of
+	/// the individual dice that were rolled
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	pub fn eval_detailed(&mut self, dice_expression: &str) -> Result<DetailedRoll,SyntaxError>{
+		expression_span!("eval_detailed", expression = dice_expression);
+		let mut steps = self.steps();
+		let expr = expression::parse(dice_expression, &self.plugins, &mut steps)?;
+		let mut state = expression::Rolls::default();
+		let total = expr.roll(&mut self.rng, &mut Some(&mut state), &mut steps)?;
+		// break the expression text down into the dice written with literal numbers (eg "3d6")
+		// and the text between them
+		let mut breakdown: Vec<BreakdownPart> = Vec::new();
+		let mut text_start = 0;
+		state.literals.sort();
+		for (start, end, index) in &state.literals {
+			if *start > text_start {
+				breakdown.push(BreakdownPart::Text(dice_expression[text_start..*start].into()));
 			}
-		}{}
-		// multiply and divide
-		while match find_one_of(x.as_str(), &['*', '/']) {
-			None => false,
-			Some(i) => {
-				let cpy =  x.clone();
-				let x_str = cpy.as_str();
-				let op = &x_str[i..i+1];
-				let (start, end) = find_operator_params(x_str, i)?;
-				let middle: String;
-				match mode {
-					EvalMode::Average => {
-						let left = &x_str[start..i].parse::<f64>().map_err(|e| SyntaxError::from(e.clone()))?;
-						let right = &x_str[i+1..end].parse::<f64>().map_err(|e| SyntaxError::from(e.clone()))?;
-						if op == "/" {
-							middle = format!("{:.}", *left / *right);
-						} else {
-							middle = format!("{:.}", *left * *right);
-						}
-					}
-					_ => {
-						let left = &x_str[start..i].parse::<i64>().map_err(|e| SyntaxError::from(e.clone()))?;
-						let right = &x_str[i+1..end].parse::<i64>().map_err(|e| SyntaxError::from(e.clone()))?;
-						if op == "/" {
-							middle = format!("{}", *left / *right);
-						} else {
-							middle = format!("{}", *left * *right);
-						}
-					}
-				}
-				let front = &x_str[0..start];
-				let back = &x_str[end..];
-				x.clear();
-				x.push_str(front);
-				x.push_str(middle.as_str());
-				x.push_str(back);
-				true
+			breakdown.push(BreakdownPart::Dice(state.dice[*index].clone()));
+			text_start = *end;
+		}
+		if text_start < dice_expression.len() {
+			breakdown.push(BreakdownPart::Text(dice_expression[text_start..].into()));
+		}
+		let mut tags: BTreeMap<String, i64> = BTreeMap::new();
+		for (tag, subtotal) in state.tags {
+			let sum = tags.entry(tag).or_insert(0);
+			*sum = sum.saturating_add(subtotal);
+		}
+		Ok(DetailedRoll{
+			roll: roll_stats(&expr, total)?,
+			dice: state.dice,
+			breakdown,
+			tags
+		})
+	}
+
+	/// Runs a short script of dice expressions separated by semicolons, where each statement
+	/// can assign its result to a variable for the statements after it to use (eg
+	/// "atk = 1d20+7; dmg = 2d6+4; atk>=16 ? dmg : 0"). Returns the result of the last statement
+	/// along with the value given to each variable.
+	/// # Parameters
+	/// * `script`: the statements to run, separated by semicolons
+	pub fn eval_script(&mut self, script: &str) -> Result<ScriptRoll,SyntaxError>{
+		expression_span!("eval_script", script);
+		let mut steps = self.steps();
+		let mut bindings: Vec<(String, i64)> = Vec::new();
+		let mut total: Option<i64> = None;
+		for (start, statement) in split_statements(script) {
+			let (name, start, dice_expression) = match find_assignment(statement, &self.plugins) {
+				Some(eq) => (Some(statement[..eq].trim()), start + eq + 1, &statement[eq+1..]),
+				None => (None, start, statement)
+			};
+			// each variable has the last value given to it
+			let value = |var: &str| bindings.iter().rev().find(|(name, _)| name == var).map(|(_, value)| *value);
+			let result = expression::parse_with_bindings(dice_expression, &self.plugins, &mut steps, &value)
+				.and_then(|expr| expr.roll(&mut self.rng, &mut None, &mut steps))
+				.map_err(|mut error| {
+					// point at the statement in the script rather than in itself
+					error.shift(&script[..start]);
+					error
+				})?;
+			if let Some(name) = name {
+				bindings.push((name.to_string(), result));
 			}
-		}{}
-
-		// add and subtract (subtraction already replaced with +-)
-		while match x.find('+') { // start at 1 in case of negative number on left side
-			None => false,
-			Some(i) => {
-				let cpy =  x.clone();
-				let x_str = cpy.as_str();
-				let (start, end) = find_operator_params(x_str, i)?;
-				let mut left_str = &x_str[start..i];
-				let mut right_str = &x_str[i+1..end];
-				if left_str.starts_with("--") {
-					// double negative equals a positive
-					left_str = &left_str[2..];
-				}
-				if right_str.starts_with("--") {
-					right_str = &right_str[2..];
-				}
-				let middle: String;
-				match mode {
-					EvalMode::Average => {
-						let left = left_str.parse::<f64>().map_err(|e| SyntaxError::from(e.clone()))?;
-						let right = right_str.parse::<f64>().map_err(|e| SyntaxError::from(e.clone()))?;
-						middle = format!("{:.}", left + right);
-					}
-					_ => {
-						let left = left_str.parse::<i64>().map_err(|e| SyntaxError::from(e.clone()))?;
-						let right = right_str.parse::<i64>().map_err(|e| SyntaxError::from(e.clone()))?;
-						middle = format!("{}", left + right);
-					}
-				}
-				let front = &x_str[0..start];
-				let back = &x_str[end..];
-				x.clear();
-				x.push_str(front);
-				x.push_str(middle.as_str());
-				x.push_str(back);
-				true
+			total = Some(result);
+		}
+		let total = total.ok_or_else(|| SyntaxError::from("Script has no dice expressions"))?;
+		Ok(ScriptRoll{total, bindings})
+	}
+
+	/// Fills in the {placeholders} of a dice expression template with the given values (see
+	/// `fill_template(...)`) and then evaluates it
+	/// # Parameters
+	/// * `template`: An RPG dice notation expression with placeholders (eg "1d20+{prof}+{str_mod}")
+	/// * `values`: the value of each placeholder, by name
+	pub fn eval_template<K, S>(&mut self, template: &str, values: &HashMap<K, i64, S>) -> Result<DiceRoll,SyntaxError>
+		where K: Borrow<str> + Hash + Eq, S: BuildHasher {
+		self.eval(fill_template(template, values)?.as_str())
+	}
+
+	/// Evaluates the given RPG dice notation expression against a target number, such as a
+	/// difficulty class. The roll succeeds if it meets or beats the target, and is a critical
+	/// success or failure if it rolls the highest or lowest possible result (see `VsRoll`).
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "1d20+5")
+	/// * `target`: the number to meet or beat
+	pub fn eval_vs(&mut self, dice_expression: &str, target: i64) -> Result<VsRoll,SyntaxError>{
+		Ok(VsRoll::new(self.eval(dice_expression)?, target))
+	}
+
+	/// Rolls two RPG dice notation expressions against each other (eg a stealth check against a
+	/// perception check) and reports which one rolled higher. Ties are left as ties (see
+	/// `DiceBag.opposed_with(...)` to settle them).
+	/// # Parameters
+	/// * `expression_a`: the first RPG dice notation expression (eg "1d20+5")
+	/// * `expression_b`: the second RPG dice notation expression
+	pub fn opposed(&mut self, expression_a: &str, expression_b: &str) -> Result<OpposedRoll,SyntaxError>{
+		self.opposed_with(expression_a, expression_b, TieBreak::Tie)
+	}
+
+	/// Rolls two RPG dice notation expressions against each other and reports which one rolled
+	/// higher, settling ties as given
+	/// # Parameters
+	/// * `expression_a`: the first RPG dice notation expression (eg "1d20+5")
+	/// * `expression_b`: the second RPG dice notation expression
+	/// * `tie_break`: how to settle a tie
+	pub fn opposed_with(&mut self, expression_a: &str, expression_b: &str, tie_break: TieBreak) -> Result<OpposedRoll,SyntaxError>{
+		expression_span!("opposed", expression_a, expression_b, ?tie_break);
+		let mut steps = self.steps();
+		let (expr_a, expr_b) = (expression::parse(expression_a, &self.plugins, &mut steps)?, expression::parse(expression_b, &self.plugins, &mut steps)?);
+		let mut rerolls = 0;
+		loop {
+			let roll_a = roll_stats(&expr_a, expr_a.roll(&mut self.rng, &mut None, &mut steps)?)?;
+			let roll_b = roll_stats(&expr_b, expr_b.roll(&mut self.rng, &mut None, &mut steps)?)?;
+			// expressions that always have the same result can only ever tie
+			let can_differ = roll_a.min != roll_a.max || roll_b.min != roll_b.max;
+			if tie_break != TieBreak::Reroll || roll_a.total != roll_b.total || !can_differ || rerolls >= MAX_REROLLS {
+				return Ok(OpposedRoll::new(roll_a, roll_b, tie_break));
 			}
-		}{}
-		// DONE!
-		Ok(x)
+			rerolls += 1;
+		}
+	}
+
+	/// Returns the probability distribution of the results of the given RPG dice notation
+	/// expression, which is worked out exactly when there are few enough combinations of dice, and
+	/// is otherwise estimated from `DISTRIBUTION_SAMPLES` rolls (eg for "100d100")
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "4d6kh3")
+	pub fn distribution(&mut self, dice_expression: &str) -> Result<Distribution,SyntaxError>{
+		expression_span!("distribution", expression = dice_expression);
+		if let Some(distribution) = self.exact_distribution(dice_expression)? {
+			return Ok(distribution);
+		}
+		let mut totals = vec![0; DISTRIBUTION_SAMPLES];
+		self.sample_totals(dice_expression, &mut totals)?;
+		Ok(Distribution::from_samples(totals))
+	}
+
+	/// Works out the exact probability distribution of the results of the given RPG dice notation
+	/// expression without rolling any dice, or returns `None` if there are too many combinations of
+	/// dice to work it out (in which case `DiceBag.distribution(...)` estimates it instead)
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "4d6kh3")
+	pub fn exact_distribution(&self, dice_expression: &str) -> Result<Option<Distribution>,SyntaxError>{
+		let mut steps = self.steps();
+		let expr = expression::parse(dice_expression, &self.plugins, &mut steps)?;
+		Ok(expr.pmf().map(Distribution::from_probabilities))
+	}
+
+	/// Rolls the given RPG dice notation expression many times and returns the observed
+	/// distribution of the results
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	/// * `samples`: number of times to roll the dice expression
+	pub fn sample_distribution(&mut self, dice_expression: &str, samples: usize) -> Result<Distribution,SyntaxError>{
+		let mut totals: Vec<i64> = vec![0; samples];
+		self.sample_totals(dice_expression, &mut totals)?;
+		Ok(Distribution::from_samples(totals))
 	}
 
+	/// Rolls the given RPG dice notation expression once for every slot of the buffer, filling it
+	/// with the totals. The expression is only parsed once, so this is the fastest way to roll the
+	/// same expression many times (eg for a Monte Carlo simulation).
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	/// * `out`: the buffer to fill with rolled totals
+	pub fn sample_totals(&mut self, dice_expression: &str, out: &mut [i64]) -> Result<(),SyntaxError>{
+		expression_span!("sample_totals", expression = dice_expression, samples = out.len());
+		let mut steps = self.steps();
+		let expr = expression::parse(dice_expression, &self.plugins, &mut steps)?;
+		for total in out.iter_mut() {
+			*total = expr.roll(&mut self.rng, &mut None, &mut steps)?;
+		}
+		Ok(())
+	}
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum EvalMode {
-	Roll, Average, Minimum, Maximum
+/// Puts together the total rolled for a parsed dice expression with its minimum, maximum, and
+/// average results
+fn roll_stats(expr: &expression::Expr, total: i64) -> Result<DiceRoll, SyntaxError> {
+	let (min, max) = expr.range()?;
+	Ok(DiceRoll{total, min, max, average: expr.mean()?})
 }
 
+/// Limit on how many times an opposed roll is rerolled to settle a tie (in case the expressions
+/// are very unlikely to ever differ)
+const MAX_REROLLS: usize = 1000;
+
+/// Number of rolls that `DiceBag.distribution(...)` samples when the distribution of an expression
+/// cannot be worked out exactly
+pub const DISTRIBUTION_SAMPLES: usize = 10_000;
+
 /// The result of rolling the provided dice expression, including the average and minimum and
 /// maximum possible results.
 #[derive(Clone, Copy, PartialEq, Default, Debug)]
@@ -314,6 +506,16 @@ pub struct DiceRoll {
 	pub average: f64
 }
 
+impl DiceRoll {
+	/// Returns the average rounded down, which is how Dungeons & Dragons stat blocks show it (eg
+	/// "11 (2d8 + 2)" for an average of 11.0, or "7 (2d6)" for an average of 7.0 and "4 (1d8)" for
+	/// an average of 4.5)
+	pub fn floor_average(&self) -> i64 {
+		// a tiny margin so that float rounding error (eg 10.999999999) does not lose a whole point
+		(self.average + 1e-9).floor() as i64
+	}
+}
+
 impl core::fmt::Display for DiceRoll {
 	/// core::fmt::Display implementation returns the total result
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -321,12 +523,215 @@ impl core::fmt::Display for DiceRoll {
 	}
 }
 
+/// A parsed dice expression (see `DiceBag.parse(...)`), which can be inspected before it is rolled
+///
+/// # Example
+/// ```
+/// use dicexp::{DiceBag, simple_rng};
+/// let mut dice_bag = DiceBag::new(simple_rng(42));
+/// let expr = dice_bag.parse("8d6+4d4+2").expect("Error");
+/// assert_eq!(expr.dice_count().expect("Error"), 12);
+/// assert_eq!(expr.max_die_size().expect("Error"), 6);
+/// if expr.dice_count().expect("Error") <= 100 {
+///     println!("{}", dice_bag.eval_expr(&expr).expect("Error"));
+/// }
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+pub struct DiceExpr {
+	expr: expression::Expr
+}
+
+impl DiceExpr {
+	/// Returns the most dice that rolling the expression can roll (eg 12 for "8d6+4d4+2", or 4
+	/// for "(1d4)d6"), counting the dice on both sides of a conditional expression. Returns an
+	/// error if the number of dice depends on a variable.
+	pub fn dice_count(&self) -> Result<u64,SyntaxError> {
+		self.expr.dice_count()
+	}
+
+	/// Returns the most sides that any die rolled by the expression can have (eg 20 for
+	/// "1d20+1d6"), or 0 if there are no dice. Returns an error if the number of sides depends on
+	/// a variable.
+	pub fn max_die_size(&self) -> Result<u32,SyntaxError> {
+		self.expr.max_die_size()
+	}
+
+	/// Returns true if the expression rolls any dice (eg to only show a "reroll" button for
+	/// expressions that can roll something different)
+	pub fn has_dice(&self) -> bool {
+		self.expr.has_dice()
+	}
+
+	/// Returns the names of the variables used by the expression (eg "atk" and "dmg" for
+	/// "atk>=16 ? dmg : 0"), in the order that they first appear
+	pub fn variables(&self) -> Vec<&str> {
+		self.expr.variables()
+	}
+
+	/// Returns a simplified copy of the expression, where the given variables are replaced by
+	/// their values and everything that does not depend on dice (or on the other variables) is
+	/// worked out, leaving the dice terms to be rolled later. The numbers added to or subtracted
+	/// from a sum are combined at its end, so a character builder can show "1d20+prof+str_mod" as
+	/// "1d20+5" (see the `Display` implementation).
+	/// # Parameters
+	/// * `vars`: the value of each variable, by name
+	pub fn partial_eval<K, S>(&self, vars: &HashMap<K, i64, S>) -> Result<DiceExpr,SyntaxError>
+		where K: Borrow<str> + Hash + Eq, S: BuildHasher {
+		Ok(DiceExpr{expr: self.expr.partial_eval(|name| vars.get(name).copied())?})
+	}
+
+	/// Writes out the expression as LaTeX math (without the surrounding "$" signs), for
+	/// typesetting it in a rulebook or a blog post (eg "\\frac{2\\mathrm{d}6}{2} + 3" for "2d6/2+3")
+	pub fn to_latex(&self) -> String {
+		self.expr.write(Notation::Latex)
+	}
+
+	/// Writes out the expression with Unicode math symbols, such as proper minus signs,
+	/// multiplication dots, and comparison symbols (eg "1d20 + 5 ≥ 15")
+	pub fn to_unicode(&self) -> String {
+		self.expr.write(Notation::Unicode)
+	}
+
+	/// Returns true if this expression has exactly the same chance of rolling every result as the
+	/// other one (eg "2d6*2" and "2*(2d6)", but not "2d6" and "1d11+1"), such as to check that
+	/// rewriting a formula did not change the odds for the players. The chances are compared as
+	/// exact fractions with the `exact` feature, and otherwise allowing for floating-point
+	/// rounding error (one part in a billion). Returns an error if either expression uses a
+	/// variable, or if there are too many possible results to work out exactly.
+	/// # Parameters
+	/// * `other`: the expression to compare with
+	pub fn dist_eq(&self, other: &DiceExpr) -> Result<bool,SyntaxError> {
+		if let Some(name) = self.variables().into_iter().chain(other.variables()).next() {
+			return Err(expression::unknown_variable(name));
+		}
+		#[cfg(feature = "exact")]
+		if let (Some(a), Some(b)) = (self.expr.pmf::<Rational64>(), other.expr.pmf::<Rational64>()) {
+			let zero = Rational64::from_integer(0);
+			return Ok(a.into_iter().filter(|(_, p)| *p > zero).eq(b.into_iter().filter(|(_, p)| *p > zero)));
+		}
+		let (Some(a), Some(b)) = (self.expr.pmf::<f64>(), other.expr.pmf::<f64>()) else {
+			return Err(SyntaxError::from("Too many possible results to work them out exactly"));
+		};
+		let a: Vec<(i64, f64)> = a.into_iter().filter(|(_, p)| *p > 0.).collect();
+		let b: Vec<(i64, f64)> = b.into_iter().filter(|(_, p)| *p > 0.).collect();
+		// exact probabilities can differ by rounding error when they should be equal
+		Ok(a.len() == b.len() && a.iter().zip(&b).all(|((a, p_a), (b, p_b))| a == b && (p_a - p_b).abs() <= 1e-9 * p_a.max(*p_b)))
+	}
+}
+
+impl core::fmt::Display for DiceExpr {
+	/// core::fmt::Display implementation writes out the expression in dice notation (eg "2d6+3"),
+	/// which can be parsed again
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		std::fmt::Display::fmt(&self.expr, f)
+	}
+}
+
+/// The result of running a script of dice expressions (see `DiceBag.eval_script(...)`)
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct ScriptRoll {
+	/// The result of the last statement of the script
+	pub total: i64,
+	/// The value given to each variable, in the order that they were assigned (a variable that
+	/// was assigned more than once appears once for each assignment)
+	pub bindings: Vec<(String, i64)>
+}
+
+impl ScriptRoll {
+	/// Returns the last value given to the named variable, or `None` if it was never assigned
+	pub fn get(&self, name: &str) -> Option<i64> {
+		self.bindings.iter().rev().find(|(var, _)| var == name).map(|(_, value)| *value)
+	}
+}
+
+
+/// The individual dice rolled for a single dice term (eg "3d6") of a dice expression
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct RolledDice {
+	/// Number of dice rolled
+	pub count: u32,
+	/// Number of sides per die
+	pub sides: u32,
+	/// The result of each die
+	pub rolls: Vec<u32>,
+	/// The positions in `rolls` of the dice that were dropped by a keep or drop modifier (eg the
+	/// lowest die of "4d6kh3"), in ascending order
+	#[cfg_attr(feature = "serde_support", serde(default, skip_serializing_if = "Vec::is_empty"))]
+	pub dropped: Vec<usize>
+}
+
+impl RolledDice {
+	/// Returns the sum of the dice that were kept
+	pub fn total(&self) -> i64 {
+		self.kept().map(|r| r as i64).sum()
+	}
+
+	/// Iterates over the results of the dice that were kept (ie not dropped)
+	pub fn kept(&self) -> impl Iterator<Item=u32> + '_ {
+		self.rolls.iter().enumerate().filter(|(i, _)| !self.is_dropped(*i)).map(|(_, r)| *r)
+	}
+
+	/// Returns true if the die at the given position in `rolls` was dropped
+	pub fn is_dropped(&self, index: usize) -> bool {
+		self.dropped.binary_search(&index).is_ok()
+	}
+}
+
+/// A piece of a rolled dice expression, used to show how the total was worked out. The dice
+/// expression is broken down into the dice that were rolled and the text in between them.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum BreakdownPart {
+	/// Text from the original dice expression (numbers, operators, parentheses, etc)
+	Text(String),
+	/// A dice term (eg "3d6") from the original dice expression and the dice that it rolled
+	Dice(RolledDice)
+}
+
+/// The result of rolling the provided dice expression (see `DiceRoll`), plus the individual dice
+/// that were rolled along the way
+#[derive(Clone, PartialEq, Default, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct DetailedRoll {
+	/// The total, min, max, and average of the roll
+	pub roll: DiceRoll,
+	/// Every dice term that was rolled, in the order that they were rolled
+	pub dice: Vec<RolledDice>,
+	/// The original dice expression broken down into the dice that were rolled and the text in
+	/// between them
+	pub breakdown: Vec<BreakdownPart>,
+	/// The subtotal of each tag (eg "fire" for "2d6[fire] + 1d4[cold]"), which is the sum of
+	/// the results of the terms with that tag
+	#[cfg_attr(feature = "serde_support", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+	pub tags: BTreeMap<String, i64>
+}
+
+impl core::fmt::Display for DetailedRoll {
+	/// core::fmt::Display implementation returns the total result
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		std::fmt::Display::fmt(&self.roll, f)
+	}
+}
+
 /// Error returns when a `DiceBag` fails to interpret or evaluate a dice expression
 pub struct SyntaxError {
 	pub msg: Option<String>,
 	pub line: Option<u64>,
 	pub col: Option<u64>,
-	pub cause: Option<Box<dyn Error>>
+	pub cause: Option<Box<dyn Error>>,
+	pub(crate) kind: ErrorKind
+}
+
+/// What kind of problem a `SyntaxError` is, for the problems that callers may want to handle
+/// differently from a mistake in the expression
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ErrorKind {
+	/// A problem with the expression (or with rolling it)
+	Expression,
+	/// The evaluation went over the step limit of the `DiceBag`
+	StepLimit
 }
 
 impl SyntaxError{
@@ -354,9 +759,25 @@ impl SyntaxError{
 		Ok(())
 	}
 
+	/// Returns true if this error stopped an evaluation that went over the step limit of the
+	/// `DiceBag` (see `DiceBag.set_step_limit(...)`), rather than a problem with the expression
+	pub fn is_step_limit(&self) -> bool {
+		self.kind == ErrorKind::StepLimit
+	}
+
+	/// Moves the line and column of an error in part of a text to where that part is in the whole
+	/// text, given the text that comes before it
+	fn shift(&mut self, before: &str) {
+		let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+		if self.line == Some(1) {
+			self.col = self.col.map(|col| col + before[line_start..].chars().count() as u64);
+		}
+		self.line = self.line.map(|line| line + before.matches('\n').count() as u64);
+	}
+
 	fn from_string<T>(msg: T) -> Self where T: Into<String> {
 		SyntaxError{
-			msg: Some(msg.into()), line: None, col: None, cause: None,
+			msg: Some(msg.into()), line: None, col: None, cause: None, kind: ErrorKind::Expression
 		}
 	}
 }
@@ -364,7 +785,7 @@ impl SyntaxError{
 impl From<&str> for SyntaxError{
 	fn from(msg: &str) -> Self {
 		SyntaxError{
-			msg: Some(msg.into()), line: None, col: None, cause: None,
+			msg: Some(msg.into()), line: None, col: None, cause: None, kind: ErrorKind::Expression
 		}
 	}
 }
@@ -372,13 +793,13 @@ impl From<&str> for SyntaxError{
 
 impl From<ParseIntError> for SyntaxError {
 	fn from(value: ParseIntError) -> Self {
-		SyntaxError{msg: Some("Failed to parse string as integer".into()), line: None, col: None, cause: Some(Box::from(value)) }
+		SyntaxError{msg: Some("Failed to parse string as integer".into()), line: None, col: None, cause: Some(Box::from(value)), kind: ErrorKind::Expression}
 	}
 }
 
 impl From<ParseFloatError> for SyntaxError {
 	fn from(value: ParseFloatError) -> Self {
-		SyntaxError{msg: Some("Failed to parse string as decimal number".into()), line: None, col: None, cause: Some(Box::from(value)) }
+		SyntaxError{msg: Some("Failed to parse string as decimal number".into()), line: None, col: None, cause: Some(Box::from(value)), kind: ErrorKind::Expression}
 	}
 }
 
@@ -396,6 +817,82 @@ impl core::fmt::Display for SyntaxError {
 
 impl Error for SyntaxError {}
 
+/// A syntax error found by `DiceBag.parse_all_errors(...)`, along with where the problem is in
+/// the expression text (eg to underline it in a form)
+#[derive(Debug)]
+pub struct SyntaxDiagnostic {
+	/// The error, including its line and column
+	pub error: SyntaxError,
+	/// Where the problem starts in the expression text (as a byte offset)
+	pub start: usize,
+	/// Where the problem ends in the expression text (as a byte offset, just after its last
+	/// character)
+	pub end: usize
+}
+
+impl SyntaxDiagnostic {
+	/// Moves a diagnostic for part of a text to where that part is in the whole text, given the
+	/// text that comes before it
+	fn shift(&mut self, before: &str) {
+		self.error.shift(before);
+		self.start += before.len();
+		self.end += before.len();
+	}
+}
+
+impl core::fmt::Display for SyntaxDiagnostic {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		std::fmt::Display::fmt(&self.error, f)
+	}
+}
+
+
+/// Returns true if the text is a variable name on its own (eg "atk" or "dmg_2"), read the same
+/// way as the variables of `DiceBag.eval_script(...)`: letters, digits, and underscores (starting
+/// with a letter or underscore), but not one of the letters used by dice notation (eg "d" or "kh")
+/// # Parameters
+/// * `text`: the text to check
+///
+/// # Example
+/// ```
+/// use dicexp::is_variable_name;
+/// assert!(is_variable_name("atk"));
+/// assert!(!is_variable_name("1d20") && !is_variable_name("kh"));
+/// ```
+pub fn is_variable_name(text: &str) -> bool {
+	expression::is_variable_name(text, &Plugins::default())
+}
+
+/// Fills in the {placeholders} of a dice expression template with the given values, such as the
+/// stats of a character (eg "1d20+{prof}+{str_mod}"). Negative values are put in parentheses.
+/// Braces around anything other than a single name are left alone, since they are groups (eg
+/// "{1d20+{str}, 1d20+{str}}kh1"). Returns an error listing every placeholder that has no value.
+/// # Parameters
+/// * `template`: An RPG dice notation expression with placeholders
+/// * `values`: the value of each placeholder, by name
+pub fn fill_template<K, S>(template: &str, values: &HashMap<K, i64, S>) -> Result<String,SyntaxError>
+	where K: Borrow<str> + Hash + Eq, S: BuildHasher {
+	let mut output = String::with_capacity(template.len());
+	let mut unresolved: Vec<&str> = Vec::new();
+	let mut text_start = 0;
+	for (start, end, name) in placeholders(template) {
+		output.push_str(&template[text_start..start]);
+		match values.get(name) {
+			Some(value) if *value < 0 => output.push_str(format!("({})", value).as_str()),
+			Some(value) => output.push_str(format!("{}", value).as_str()),
+			None => if !unresolved.contains(&name) {unresolved.push(name)}
+		}
+		text_start = end;
+	}
+	output.push_str(&template[text_start..]);
+	match unresolved.len() {
+		0 => Ok(output),
+		_ => Err(SyntaxError::from_string(format!("No value for placeholder{} {}",
+			if unresolved.len() > 1 {"s"} else {""},
+			unresolved.iter().map(|name| format!("{{{}}}", name)).collect::<Vec<String>>().join(", ")
+		)))
+	}
+}
 
 /// Creates a new random number generator (RNG) from the provided seed using the default
 /// [rand crate](https://crates.io/crates/rand) `rand::rngs::StdRng` RNG
@@ -412,7 +909,43 @@ pub fn simple_rng(seed: u64) -> rand::rngs::StdRng {
 	let mut seeder_rng = StdRng::from_seed(seeder_rng_seed);
 	let mut rng_seed: <StdRng as SeedableRng>::Seed = <StdRng as SeedableRng>::Seed::default();
 	seeder_rng.fill_bytes(&mut rng_seed);
-	return StdRng::from_seed(rng_seed);
+	StdRng::from_seed(rng_seed)
+}
+
+/// Checks a claimed roll (eg one posted in a play-by-post game) by rolling the expression again
+/// with a `DiceBag` made from `simple_rng(seed)`, returning true if the roll matches the claim.
+/// The claim must be the first roll made with that seed, such as the roll from
+/// `dicexp -s SEED EXPRESSION`. Returns false if the expression cannot be evaluated.
+/// # Parameters
+/// * `seed`: the seed that the roll was made with
+/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+/// * `claimed`: the roll to check
+///
+/// # Example
+/// ```
+/// use dicexp::{DiceBag, simple_rng, verify_roll};
+/// let roll = DiceBag::new(simple_rng(1234)).eval("1d20+5").expect("Error");
+/// assert!(verify_roll(1234, "1d20+5", &roll));
+/// ```
+pub fn verify_roll(seed: u64, dice_expression: &str, claimed: &DiceRoll) -> bool {
+	DiceBag::new(simple_rng(seed)).eval(dice_expression).is_ok_and(|roll| roll == *claimed)
+}
+
+/// Checks a claimed roll like `verify_roll(...)`, but also checks every die that was rolled (eg
+/// a claim of "2d20kh1 => 17 [17, 3]" when the seed rolls 17 with [3, 17] is not valid)
+/// # Parameters
+/// * `seed`: the seed that the roll was made with
+/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+/// * `claimed`: the roll to check, including its dice
+///
+/// # Example
+/// ```
+/// use dicexp::{DiceBag, simple_rng, verify_detailed};
+/// let roll = DiceBag::new(simple_rng(1234)).eval_detailed("2d20kh1").expect("Error");
+/// assert!(verify_detailed(1234, "2d20kh1", &roll));
+/// ```
+pub fn verify_detailed(seed: u64, dice_expression: &str, claimed: &DetailedRoll) -> bool {
+	DiceBag::new(simple_rng(seed)).eval_detailed(dice_expression).is_ok_and(|roll| roll == *claimed)
 }
 
 /// Creates a new random number generator (RNG) from the provided seed using the default
@@ -425,64 +958,72 @@ pub fn new_simple_rng() -> rand::rngs::StdRng {
 	simple_rng(time_seed)
 }
 
-
-fn find_enclosure_from(text: &str, pos: usize, open: char, close: char) -> Result<Option<(usize, usize)>, SyntaxError> {
-	let mut depth = 0;
-	let slice = &text[pos..];
-	let mut start_index = 0;
-	for (i, c) in slice.char_indices() {
-		if c == open {
-			if depth == 0 {
-				start_index = i + pos;
-			}
-			depth += 1;
-		} else if c == close {
-			depth -= 1;
-			if depth == 0 {
-				return Ok(Some((start_index, pos+i+1)))
+/// Splits a script into its statements, which are separated by semicolons (that are not in a tag),
+/// leaving out any empty statements. Returns where each statement starts in the script along
+/// with its text.
+fn split_statements(script: &str) -> Vec<(usize, &str)> {
+	let mut statements: Vec<(usize, &str)> = Vec::new();
+	let mut tag = false;
+	let mut start = 0;
+	// the text of a statement without the whitespace around it, and where it starts
+	let trimmed = |start: usize, end: usize| {
+		let text = &script[start..end];
+		(start + text.len() - text.trim_start().len(), text.trim())
+	};
+	for (i, c) in script.char_indices() {
+		match c {
+			_ if tag => tag = c != ']',
+			'[' => tag = true,
+			';' => {
+				statements.push(trimmed(start, i));
+				start = i + 1;
 			}
+			_ => {}
 		}
 	}
-	if depth > 0 {
-		return Err(SyntaxError::from("Found '(' without matching ')'"));
-	}
-	return Ok(None);
+	statements.push(trimmed(start, script.len()));
+	statements.retain(|(_, statement)| !statement.is_empty());
+	statements
 }
 
-fn find_operator_params(text: &str, op_pos: usize) -> Result<(usize, usize), SyntaxError> {
-	#[cfg(test)]
-	eprintln!("'{}' '{}' '{}'", &text[0..op_pos], &text[op_pos..op_pos+1], &text[op_pos+1..]);
-	let front_slice = &text[0..op_pos];
-	let back_slice = &text[op_pos+1..];
-	let mut end = text.len();
-	for (i, c) in back_slice.char_indices() {
-		if !(c.is_digit(10) || c == '.' || c == '-') {end = op_pos+1+i; break;}
-	}
-	let mut start = 0;
-	for (i, c) in front_slice.char_indices().rev() {
-		if !(c.is_digit(10) || c == '.' || c == '-') {start = i+1; break;}
-	}
-	if start == op_pos || end == op_pos+1 {
-		return Err(SyntaxError::from_string(format!("Missing numbers before or after operator {}", &text[op_pos..op_pos+1])));
+/// Returns the position of the '=' of a statement that assigns to a variable (eg "atk = 1d20+7"),
+/// or `None` if the statement is just a dice expression
+fn find_assignment(statement: &str, plugins: &Plugins) -> Option<usize> {
+	let eq = statement.find('=')?;
+	let name = statement[..eq].trim();
+	let is_assignment = !statement[eq+1..].starts_with('=') && expression::is_variable_name(name, plugins);
+	match is_assignment {
+		true => Some(eq),
+		false => None
 	}
-	Ok((start, end))
 }
 
-fn find_one_of(text: &str, chars: &[char]) -> Option<usize> {
-	let mut set = HashSet::with_capacity(chars.len());
-	for c in chars {set.insert(c);}
+/// Finds the {placeholders} in a dice expression template (eg "{prof}" in "1d20+{prof}"),
+/// returning where each one starts and ends in the text and its name. Braces around anything
+/// other than a single name are groups (eg "{1d20+{str}, 1d20+{str}}kh1"), not placeholders.
+fn placeholders(text: &str) -> Vec<(usize, usize, &str)> {
+	let mut found: Vec<(usize, usize, &str)> = Vec::new();
+	let mut tag = false;
+	let mut open: Option<usize> = None;
 	for (i, c) in text.char_indices() {
-		if set.contains(&c){
-			return Some(i);
+		match c {
+			_ if tag => tag = c != ']',
+			'[' => tag = true,
+			'{' => open = Some(i),
+			'}' => if let Some(open) = open.take() {
+				let name = text[open+1..i].trim();
+				if is_variable_name(name) {
+					found.push((open, i + 1, name));
+				}
+			},
+			_ => {}
 		}
 	}
-	return None;
+	found
 }
 
 #[cfg(test)]
 mod unit_tests {
-	use crate::DiceRoll;
-
 	#[test]
 	fn arithmatic_checks() {
 		use crate::{DiceBag, simple_rng};
@@ -509,7 +1050,7 @@ mod unit_tests {
 
 	#[test]
 	fn dice_checks() {
-		use crate::{DiceBag, simple_rng};
+		use crate::{BreakdownPart, DiceBag, simple_rng};
 		let mut dice = DiceBag::new(simple_rng(42));
 		assert!(dice.eval_total("1d20-30").unwrap() <= -10);
 		assert!(dice.eval_total("1d20-30").unwrap() > -30);
@@ -519,6 +1060,753 @@ mod unit_tests {
 		assert_eq!(roll.max, 18);
 		assert_eq!(roll.min, 3);
 		assert_eq!(roll.average, 3.5*3.);
+		assert_eq!(dice.eval_ave("20-2d6").unwrap(), 13.);
+		let detailed = dice.eval_detailed("2d6+1d4+(1d8)").unwrap();
+		assert_eq!(detailed.dice.len(), 3);
+		assert_eq!((detailed.dice[0].count, detailed.dice[0].sides), (2, 6));
+		assert_eq!((detailed.dice[2].count, detailed.dice[2].sides), (1, 8));
+		assert_eq!(detailed.dice[0].rolls.len(), 2);
+		let dice_total: u32 = detailed.dice.iter().flat_map(|d| d.rolls.iter()).sum();
+		assert_eq!(detailed.roll.total, dice_total as i64);
+		let detailed = dice.eval_detailed("3d6 + 2").unwrap();
+		assert_eq!(detailed.breakdown.len(), 2);
+		assert_eq!(detailed.breakdown[0], BreakdownPart::Dice(detailed.dice[0].clone()));
+		assert_eq!(detailed.breakdown[1], BreakdownPart::Text(" + 2".into()));
+		assert_eq!(detailed.roll.total, detailed.dice[0].total() + 2);
+	}
+
+	#[test]
+	fn conditional_checks() {
+		use crate::{BreakdownPart, DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		assert_eq!(dice.eval_total("1 ? 2 : 3").unwrap(), 2);
+		assert_eq!(dice.eval_total("0 ? 2 : 3").unwrap(), 3);
+		assert_eq!(dice.eval_total("0 ? 1 : 0 ? 2 : 3").unwrap(), 3);
+		assert_eq!(dice.eval_total("(1 ? 2 : 3) + 4").unwrap(), 6);
+		assert_eq!(dice.eval_total("5-5 ? 1d6 : 2*2").unwrap(), 4);
+		let detailed = dice.eval_detailed("0 ? 2d6 : 1d4").unwrap();
+		assert_eq!(detailed.dice.len(), 1);
+		assert_eq!(detailed.dice[0].sides, 4);
+		let detailed = dice.eval_detailed("(1 ? 2d6 : 1d4) + 1d8").unwrap();
+		assert_eq!(detailed.dice.iter().map(|d| d.sides).collect::<Vec<u32>>(), vec![8, 6]);
+		assert_eq!(detailed.breakdown, vec![
+			BreakdownPart::Text("(1 ? ".into()), BreakdownPart::Dice(detailed.dice[1].clone()),
+			BreakdownPart::Text(" : 1d4) + ".into()), BreakdownPart::Dice(detailed.dice[0].clone())
+		]);
+		let roll = dice.eval("1d2-1 ? 2d6 : 0").unwrap();
+		assert_eq!((roll.min, roll.max, roll.average), (0, 12, 3.5));
+		let roll = dice.eval("1d6 ? 1d4 : 10").unwrap();
+		assert_eq!((roll.min, roll.max, roll.average), (1, 4, 2.5));
+		let roll = dice.eval("1d2-1 ? 0 : 10").unwrap();
+		assert_eq!((roll.min, roll.max, roll.average), (0, 10, 5.));
+		let roll = dice.eval("1d20+5>=15 ? 2d6 : 0").unwrap();
+		assert_eq!((roll.min, roll.max, roll.average), (0, 12, 0.55 * 7.));
+		assert!((dice.eval_ave("1d6-1d4 ? 10 : 1d6-3").unwrap() - (10. * 5. / 6. + 0.5 / 6.)).abs() < 1e-9);
+		// too many possible results to work out the chance of the condition exactly, so the
+		// average is approximated instead
+		let roll = dice.eval("101d1000-1 ? 1d6 : 0").unwrap();
+		assert!(roll.total >= 1 && roll.total <= 6);
+		assert_eq!(roll.average, 3.5);
+		assert!(dice.eval_total("1 ? 2").is_err());
+		assert!(dice.eval_total("1 : 2").is_err());
+	}
+
+	#[test]
+	fn comparison_checks() {
+		use crate::{DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		assert_eq!(dice.eval_total("3>2").unwrap(), 1);
+		assert_eq!(dice.eval_total("2>2").unwrap(), 0);
+		assert_eq!(dice.eval_total("2>=2").unwrap(), 1);
+		assert_eq!(dice.eval_total("1<2").unwrap(), 1);
+		assert_eq!(dice.eval_total("3<=2").unwrap(), 0);
+		assert_eq!(dice.eval_total("2==1+1").unwrap(), 1);
+		assert_eq!(dice.eval_total("2!=1+1").unwrap(), 0);
+		assert_eq!(dice.eval_total("(1d6>=1)*5").unwrap(), 5);
+		assert_eq!(dice.eval_total("1+1 == 2 ? 10 : 20").unwrap(), 10);
+		let roll = dice.eval("1d20>=11").unwrap();
+		assert_eq!((roll.min, roll.max, roll.average), (0, 1, 0.5));
+		let roll = dice.eval("1d6==1d6").unwrap();
+		assert_eq!((roll.min, roll.max), (0, 1));
+		assert!((roll.average - 1. / 6.).abs() < 1e-12);
+		let roll = dice.eval("1d6>0").unwrap();
+		assert_eq!((roll.min, roll.max, roll.average), (1, 1, 1.));
+		// too many possible results to work out the chance exactly, so it is estimated instead
+		let roll = dice.eval("101d1000 >= 50000").unwrap();
+		assert_eq!((roll.min, roll.max), (0, 1));
+		assert!((roll.average - 0.575).abs() < 0.01);
+		let roll = dice.eval("101d1000 >= 50000 ? 1d6 : 0").unwrap();
+		assert_eq!((roll.min, roll.max), (0, 6));
+		assert!((roll.average - 0.575 * 3.5).abs() < 0.05);
+		assert!(dice.eval_total("1=1").is_err());
+		assert!(dice.eval_total("1>").is_err());
+	}
+
+	#[test]
+	fn keep_drop_checks() {
+		use crate::{BreakdownPart, DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		let roll = dice.eval("4d6kh3").unwrap();
+		assert_eq!((roll.min, roll.max), (3, 18));
+		assert!((roll.average - 15869. / 1296.).abs() < 1e-9);
+		let roll = dice.eval("2d20k").unwrap();
+		assert!((roll.average - 13.825).abs() < 1e-9);
+		let roll = dice.eval("2d20kl1").unwrap();
+		assert!((roll.average - 7.175).abs() < 1e-9);
+		let roll = dice.eval("4d6dl1").unwrap();
+		assert!((roll.average - 15869. / 1296.).abs() < 1e-9);
+		let roll = dice.eval("3d6dh5").unwrap();
+		assert_eq!((roll.min, roll.max, roll.average), (0, 0, 0.));
+		let roll = dice.eval("6d6kh(1d3)").unwrap();
+		assert_eq!((roll.min, roll.max), (1, 18));
+		let roll = dice.eval("(1d4)d6dl(1d2)").unwrap();
+		assert_eq!((roll.min, roll.max), (0, 12));
+		// too many combinations to work out exactly, but it still rolls
+		let roll = dice.eval("40d6kh3").unwrap();
+		assert!((roll.average - 18.).abs() < 0.1);
+		let roll = dice.eval("40d6kl3").unwrap();
+		assert!((roll.average - 3.).abs() < 0.1);
+		assert!(dice.eval_total("4d6kh(1-2)").is_err());
+		assert!(dice.eval_total("4d6dl(-1)").is_err());
+		for _ in 0..20 {
+			let detailed = dice.eval_detailed("4d6kh3+1").unwrap();
+			let rolled = &detailed.dice[0];
+			assert_eq!(rolled.dropped.len(), 1);
+			let lowest = rolled.rolls.iter().min().unwrap();
+			assert_eq!(rolled.rolls[rolled.dropped[0]], *lowest);
+			assert_eq!(detailed.roll.total, rolled.rolls.iter().sum::<u32>() as i64 - *lowest as i64 + 1);
+			assert_eq!(detailed.breakdown[0], BreakdownPart::Dice(rolled.clone()));
+			let detailed = dice.eval_detailed("6d6kh(1d3)").unwrap();
+			assert_eq!(detailed.dice.len(), 2);
+			assert_eq!(detailed.dice[1].kept().count() as i64, detailed.dice[0].total());
+		}
+	}
+
+	#[test]
+	fn group_checks() {
+		use crate::{DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		assert_eq!(dice.eval_total("{1, 2, 3}").unwrap(), 6);
+		assert_eq!(dice.eval_total("{1, 5, 3}kh1").unwrap(), 5);
+		assert_eq!(dice.eval_total("{1, 5, 3}kl2").unwrap(), 4);
+		assert_eq!(dice.eval_total("{1, 5, 3}dh1*2").unwrap(), 8);
+		assert_eq!(dice.eval_total("{-4, 2}dl1").unwrap(), 2);
+		assert_eq!(dice.eval_total("{1 > 0 ? 7 : 8, 2}k").unwrap(), 7);
+		let roll = dice.eval("{1d20, 1d20}kh1").unwrap();
+		assert_eq!((roll.min, roll.max), (1, 20));
+		assert!((roll.average - 13.825).abs() < 1e-9);
+		let roll = dice.eval("{4d6+2, 3d8, 1d20}kh1").unwrap();
+		assert_eq!((roll.min, roll.max), (6, 26));
+		let roll = dice.eval("{1d6, 1d4-5}kl1").unwrap();
+		assert_eq!((roll.min, roll.max), (-4, -1));
+		let roll = dice.eval("{1d4, 1d6}").unwrap();
+		assert_eq!((roll.min, roll.max, roll.average), (2, 10, 6.));
+		let roll = dice.eval("{10d10, 10d10}kh1").unwrap();
+		assert_eq!((roll.min, roll.max), (10, 100));
+		// too many combinations to work out exactly, but it still rolls
+		let roll = dice.eval("{100d100, 100d100, 100d100}kl2").unwrap();
+		assert!((roll.average - 10100.).abs() < 1e-9);
+		assert!((dice.eval_ave("{1d4, 2 ? 1d6 : 0}").unwrap() - 6.).abs() < 1e-9);
+		let detailed = dice.eval_detailed("{0 ? 1d6 : 1d8, 1d10}").unwrap();
+		assert_eq!(detailed.dice.iter().map(|d| d.sides).collect::<Vec<u32>>(), vec![10, 8]);
+		let detailed = dice.eval_detailed("{2d6, 1d8}kh1").unwrap();
+		assert_eq!(detailed.dice.len(), 2);
+		assert_eq!(detailed.roll.total, detailed.dice[0].total().max(detailed.dice[1].total()));
+		assert!(dice.eval_total("{1, 2}kh(0-1)").is_err());
+		assert!(dice.eval_total("{}").is_err());
+	}
+
+	#[test]
+	fn tag_checks() {
+		use crate::{DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		let detailed = dice.eval_detailed("2d6[fire] + 1d4[cold] + 3[fire] + 1").unwrap();
+		assert_eq!(detailed.tags.len(), 2);
+		assert_eq!(detailed.tags["fire"], detailed.dice[0].total() + 3);
+		assert_eq!(detailed.tags["cold"], detailed.dice[1].total());
+		assert_eq!(detailed.roll.total, detailed.tags.values().sum::<i64>() + 1);
+		let roll = dice.eval("2d6[fire]+1d4[cold]").unwrap();
+		assert_eq!((roll.min, roll.max, roll.average), (3, 16, 9.5));
+		let detailed = dice.eval_detailed("{5[fire], 2[cold]}kh1").unwrap();
+		assert_eq!(detailed.tags.get("fire"), Some(&5));
+		assert_eq!(detailed.tags.get("cold"), None);
+		let detailed = dice.eval_detailed("0 ? 1d6[fire] : 2[acid]").unwrap();
+		assert_eq!(detailed.tags.keys().collect::<Vec<_>>(), ["acid"]);
+		assert!(dice.eval_detailed("3d6").unwrap().tags.is_empty());
+		let detailed = dice.eval_detailed("-(1d4+2)[cold] + 2x3[d20 bonus?]").unwrap();
+		assert_eq!(detailed.tags["cold"], detailed.dice[0].total() + 2);
+		assert_eq!(detailed.tags["d20 bonus?"], 3);
+		assert_eq!(detailed.dice.len(), 1);
+		assert!(dice.eval_total("2d6[fire").is_err());
+		assert!(dice.eval_total("[fire]+1").is_err());
+		assert!(dice.eval_total("2@0").is_err());
+	}
+
+	#[test]
+	fn script_checks() {
+		use crate::{DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		for _ in 0..20 {
+			let script = dice.eval_script("atk = 1d20+7; dmg = 2d6+4; atk>=16 ? dmg : 0").unwrap();
+			let (atk, dmg) = (script.get("atk").unwrap(), script.get("dmg").unwrap());
+			assert!((8..=27).contains(&atk) && (6..=16).contains(&dmg));
+			assert_eq!(script.total, if atk >= 16 {dmg} else {0});
+			assert_eq!(script.bindings.len(), 2);
+		}
+		let script = dice.eval_script("a = 2; a = a * 3; y = {a, 1d4}kl1").unwrap();
+		assert_eq!(script.bindings[..2], [("a".to_string(), 2), ("a".to_string(), 6)]);
+		assert_eq!((script.get("a"), script.get("z")), (Some(6), None));
+		assert_eq!(script.total, script.get("y").unwrap());
+		let script = dice.eval_script("N = 4; X = 3; Nd6kh(X)").unwrap();
+		assert!((3..=18).contains(&script.total));
+		assert_eq!(dice.eval_script("1d6 + 2").unwrap().bindings, []);
+		let script = dice.eval_script("x = -3; atk = 1d20; 2x x - atk2d").unwrap_err();
+		assert_eq!(script.msg.as_deref(), Some("Unknown variable 'atk2d'"));
+		let script = dice.eval_script("x = -3; _N = 2; 1d1 x 2x x + _Nd1 ;;").unwrap();
+		assert_eq!(script.total, -4);
+		assert!(dice.eval_script("dmg + 2; dmg = 5").is_err());
+		assert!(dice.eval_script(" ; ").is_err());
+		assert!(dice.eval_script("a = ; 1").is_err());
+		assert!(dice.eval_script("1 = 2").is_err());
+		assert!(dice.eval_script("d = 2").is_err());
+		// errors point at where they are in the whole script
+		let error = dice.eval_script("a = 1d6;\nb = a + ;").unwrap_err();
+		assert_eq!((error.line, error.col), (Some(2), Some(8)));
+		let error = dice.eval_script("x = 1; 1d20 + y").unwrap_err();
+		assert_eq!((error.msg.as_deref(), error.line, error.col), (Some("Unknown variable 'y'"), Some(1), Some(15)));
+		assert!(dice.eval_total("1d20 + str").is_err());
+	}
+
+	#[test]
+	fn template_checks() {
+		use crate::{DiceBag, fill_template, simple_rng};
+		use std::collections::HashMap;
+		let stats: HashMap<&str, i64> = [("prof", 3), ("str_mod", -1)].into_iter().collect();
+		assert_eq!(fill_template("1d20+{prof}+{ str_mod }", &stats).unwrap(), "1d20+3+(-1)");
+		assert_eq!(fill_template("{1d20+{prof}, 1d20+{prof}}kh1", &stats).unwrap(), "{1d20+3, 1d20+3}kh1");
+		assert_eq!(fill_template("2d6", &stats).unwrap(), "2d6");
+		let err = fill_template("1d20+{dex}+{prof}+{wis}+{dex}", &stats).unwrap_err();
+		assert_eq!(err.msg.as_deref(), Some("No value for placeholders {dex}, {wis}"));
+		let err = fill_template("1d20+{dex}", &stats).unwrap_err();
+		assert_eq!(err.msg.as_deref(), Some("No value for placeholder {dex}"));
+		assert_eq!(crate::placeholders("1d20+{prof}+{ str_mod }"), [(5, 11, "prof"), (12, 23, "str_mod")]);
+		assert_eq!(crate::placeholders("{1d20+{str}, 1d20}kh1"), [(6, 11, "str")]);
+		assert_eq!(crate::placeholders("{x}*{2}[{fire}]"), [(0, 3, "x")]);
+		assert!(crate::placeholders("1d20+{prof").is_empty());
+		let mut dice = DiceBag::new(simple_rng(42));
+		let roll = dice.eval_template("1d20+{prof}+{str_mod}", &stats).unwrap();
+		assert_eq!((roll.min, roll.max), (3, 22));
+		let owned: HashMap<String, i64> = [("lvl".to_string(), 5)].into_iter().collect();
+		assert_eq!(dice.eval_template("{lvl}d1", &owned).unwrap().total, 5);
+		assert!(dice.eval_template("1d20+{lvl}", &stats).is_err());
+	}
+
+	#[test]
+	fn vs_checks() {
+		use crate::{DiceBag, DiceRoll, Outcome, VsRoll, simple_rng};
+		let roll = |total| DiceRoll{total, min: 6, max: 25, average: 15.5};
+		assert_eq!(VsRoll::new(roll(15), 15).outcome, Outcome::Success);
+		assert_eq!(VsRoll::new(roll(15), 15).margin, 0);
+		assert_eq!(VsRoll::new(roll(14), 15).outcome, Outcome::Failure);
+		assert_eq!(VsRoll::new(roll(14), 15).margin, -1);
+		assert_eq!(VsRoll::new(roll(25), 30).outcome, Outcome::CriticalSuccess);
+		assert_eq!(VsRoll::new(roll(6), 5).outcome, Outcome::CriticalFailure);
+		assert_eq!(VsRoll::new(DiceRoll{total: 3, min: 3, max: 3, average: 3.}, 3).outcome, Outcome::Success);
+		assert_eq!(format!("{}", VsRoll::new(roll(17), 15)), "17 (success)");
+		assert!(Outcome::CriticalSuccess.is_success() && Outcome::CriticalSuccess.is_critical());
+		assert!(!Outcome::Failure.is_success() && !Outcome::Failure.is_critical());
+		let mut dice = DiceBag::new(simple_rng(42));
+		for _ in 0..100 {
+			let vs = dice.eval_vs("1d20+5", 16).unwrap();
+			assert_eq!(vs.margin, vs.roll.total - 16);
+			match vs.roll.total {
+				25 => assert_eq!(vs.outcome, Outcome::CriticalSuccess),
+				6 => assert_eq!(vs.outcome, Outcome::CriticalFailure),
+				16.. => assert_eq!(vs.outcome, Outcome::Success),
+				_ => assert_eq!(vs.outcome, Outcome::Failure)
+			}
+		}
+		assert!(dice.eval_vs("1d20+", 10).is_err());
+	}
+
+	#[cfg(feature = "exact")]
+	#[test]
+	fn exact_checks() {
+		use crate::{DiceBag, FixedRolls, Rational64, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		let roll = dice.eval_exact("1d6/2").unwrap();
+		assert_eq!((roll.min, roll.max, roll.average), (0, 3, Rational64::new(3, 2)));
+		assert_eq!(roll.probability(1), Rational64::new(1, 3));
+		assert_eq!(roll.probability(4), Rational64::new(0, 1));
+		let roll = dice.eval_exact("3d6").unwrap();
+		assert_eq!((roll.average, roll.probability(10)), (Rational64::new(21, 2), Rational64::new(1, 8)));
+		assert_eq!(roll.probabilities.values().sum::<Rational64>(), Rational64::new(1, 1));
+		assert_eq!(dice.eval_exact("4d6kh3").unwrap().average, Rational64::new(15869, 1296));
+		assert_eq!(dice.eval_exact("1d20 >= 15 ? 2d6 : 0").unwrap().average, Rational64::new(21, 10));
+		assert_eq!(dice.eval_exact("{1d20, 1d20}kh1").unwrap().probability(20), Rational64::new(39, 400));
+		assert!(dice.eval_exact("100d100").is_err());
+		assert!(dice.eval_exact("30d6").is_err());
+		assert!(dice.eval_exact("1d6+").is_err());
+		let mut dice = DiceBag::new(FixedRolls::new([5]));
+		assert_eq!(dice.eval_exact("1d6+1").unwrap().total, 6);
+	}
+
+	#[cfg(feature = "fair")]
+	#[test]
+	fn fair_checks() {
+		use crate::{DiceRoll, FairRoller, commit_seed};
+		// same as `echo -n 1234 | sha256sum`
+		assert_eq!(commit_seed(1234), "03ac674216f3e15c761ee1a5e255f067953623c8b388b4459e13f978d7c846f4");
+		let roller = FairRoller::new(1234);
+		assert_eq!(roller.commitment(), commit_seed(1234));
+		let roll = roller.roll(42, "4d6kh3+2").unwrap();
+		assert!(roll.verify(roller.commitment()));
+		assert!(roll.verify(&roller.commitment().to_uppercase()));
+		assert!(!roll.verify(&commit_seed(1235)));
+		let mut cheat = roll.clone();
+		cheat.roll = DiceRoll{total: roll.roll.total + 1, ..roll.roll};
+		assert!(!cheat.verify(roller.commitment()));
+		let mut cheat = roll.clone();
+		cheat.expression = "4d6kh3+3".to_string();
+		assert!(!cheat.verify(roller.commitment()));
+		// the roll depends on the other player's seed
+		let rolls: Vec<i64> = (0..20).map(|player_seed| roller.roll(player_seed, "1d1000").unwrap().roll.total).collect();
+		assert!(rolls.iter().any(|total| *total != rolls[0]));
+		assert!(roller.roll(42, "1d6+").is_err());
+	}
+
+	#[test]
+	fn keep_average_checks() {
+		use crate::{DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+		let roll = dice.eval("4d6kh3").unwrap();
+		assert!(close(roll.average, 15869. / 1296.) && (roll.min, roll.max) == (3, 18));
+		assert!(close(dice.eval_ave("4d6dl1").unwrap(), 15869. / 1296.));
+		assert!(close(dice.eval_ave("4d6kl3").unwrap(), 21. - 15869. / 1296.));
+		assert!(close(dice.eval_ave("4d6dh1").unwrap(), 21. - 15869. / 1296.));
+		assert!(close(dice.eval_ave("2d20kh1").unwrap(), 13.825));
+		assert!(close(dice.eval_ave("2d20dh1").unwrap(), 7.175));
+		// too many dice to try every combination of them
+		let roll = dice.eval("8d6kh3").unwrap();
+		assert!((roll.min, roll.max) == (3, 18) && (roll.average - 15.388804941).abs() < 1e-6);
+		let distribution = dice.distribution("8d6kh3").unwrap();
+		assert!(close(distribution.mean(), roll.average));
+		// too many possible totals to work out their chances, but the average is still exact
+		let average = dice.eval_ave("100d100kh50").unwrap();
+		let sampled = dice.sample_distribution("100d100kh50", 2000).unwrap().mean();
+		assert!(average > 3700. && (average - sampled).abs() < 10.);
+		assert!(close(dice.eval_ave("100d100dl50").unwrap(), average));
+		assert!(close(dice.eval_ave("1000d6dl1").unwrap() + dice.eval_ave("1000d6kl1").unwrap(), 3500.));
+		assert!(close(dice.eval_ave("4d6kh0").unwrap(), 0.));
+	}
+
+	#[test]
+	fn parse_all_errors_checks() {
+		use crate::{DiceBag, simple_rng};
+		let dice = DiceBag::new(simple_rng(42));
+		let errors = |text: &str| dice.parse_all_errors(text).iter()
+			.map(|e| format!("{}..{} {}", e.start, e.end, e.error.msg.clone().unwrap())).collect::<Vec<String>>();
+		for text in ["1d20+5", "{1d20, 1d20}kh1 >= 15 ? 2d6[fire] : 0", "atk+1d6"] {
+			assert!(dice.parse_all_errors(text).is_empty());
+		}
+		assert_eq!(errors("1d20 + * 5 + (3"), ["7..8 Expected a number or '('", "15..15 Expected ')' to match '('"]);
+		assert_eq!(errors("2d6 $ + 1.5 + 3d[]"), ["4..5 Unexpected character '$'", "9..10 Found '.', but decimal numbers are not supported (integer math only)",
+			"16..18 Expected a number or '('"]);
+		assert_eq!(errors("2d6 + 99999999999999999999 + 1d20)"), ["6..26 Number 99999999999999999999 is too big", "33..34 Unexpected ')'"]);
+		assert_eq!(errors("1d6 + [fire"), ["6..11 Found '[' without matching ']'"]);
+		assert_eq!(errors("{1d6, }kh1 + 1d8 = 2"), ["6..7 Expected a number or '('", "17..18 Unexpected '=' (use == or != to compare numbers)"]);
+		// the first error is the same one that parse(...) returns
+		for text in ["1d20 + * 5 + (3", "2d6 $ + 1.5", "1d6 + [fire", "(1d6", ""] {
+			let first = &dice.parse_all_errors(text)[0];
+			let error = dice.parse(text).unwrap_err();
+			assert_eq!((&first.error.msg, first.error.line, first.error.col), (&error.msg, error.line, error.col));
+		}
+		let errors = dice.parse_script_all_errors("atk = 1d20+; dmg = 2d6 5; atk >= 15 ? dmg : 0\nbonus = 1d4)");
+		assert_eq!(errors.iter().map(|e| (e.start, e.error.line)).collect::<Vec<_>>(), [(11, Some(1)), (23, Some(1)), (46, Some(2))]);
+		assert!(dice.parse_script_all_errors("atk = 1d20+7; atk >= 16 ? 2d6 : 0").is_empty());
+		assert_eq!(dice.parse_script_all_errors(";;").len(), 1);
+	}
+
+	#[test]
+	fn dist_eq_checks() {
+		use crate::{DiceBag, simple_rng};
+		let dice = DiceBag::new(simple_rng(42));
+		let dist_eq = |a: &str, b: &str| dice.parse(a).unwrap().dist_eq(&dice.parse(b).unwrap());
+		assert!(dist_eq("2d6*2", "2*(2d6)").unwrap());
+		assert!(dist_eq("1d6+1d6+3", "2d6+3").unwrap());
+		assert!(dist_eq("4d6dl1", "4d6kh3").unwrap());
+		assert!(dist_eq("7-1d6", "1d6").unwrap());
+		assert!(dist_eq("{1d20, 1d20}kh1", "2d20kh1").unwrap());
+		assert!(dist_eq("1d20 >= 11 ? 1 : 0", "1d2-1").unwrap());
+		assert!(!dist_eq("2d3", "1d2+1d4").unwrap());
+		assert!(!dist_eq("2d6", "1d11+1").unwrap());
+		assert!(!dist_eq("1d6*2", "1d6+1d6").unwrap());
+		assert!(!dist_eq("3d6", "3d6+1").unwrap());
+		assert!(dist_eq("atk+1d6", "1d6").is_err());
+		assert!(dist_eq("1d6", "1000d1000").is_err());
+	}
+
+	#[test]
+	fn step_limit_checks() {
+		use crate::{DiceBag, SyntaxError, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		assert_eq!(dice.step_limit(), None);
+		assert!(dice.eval_total("100000d6").is_ok());
+		dice.set_step_limit(Some(13));
+		assert_eq!(dice.step_limit(), Some(13));
+		assert!(dice.eval("2d6+3").is_ok());
+		let error = dice.eval("3d6+3").unwrap_err();
+		assert!(error.is_step_limit());
+		assert_eq!(error.to_string(), "SyntaxError: Evaluation took more than 13 steps");
+		assert!(!dice.eval("2d6+").unwrap_err().is_step_limit());
+		// parsing is limited too, before going through any nested parentheses
+		let nested = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+		dice.set_step_limit(Some(1000));
+		assert!(dice.parse(&nested).unwrap_err().is_step_limit());
+		assert!(dice.eval_total("1000000000d6").unwrap_err().is_step_limit());
+		assert!(dice.eval_detailed("1d20 > 10 ? 1000d6 : 0").is_err_and(|error| error.is_step_limit()));
+		assert!(dice.sample_distribution("1d20", 100).is_ok());
+		assert!(dice.sample_distribution("1d20", 1000).unwrap_err().is_step_limit());
+		assert!(dice.eval_script("a = 500d6; b = 500d6; a + b").unwrap_err().is_step_limit());
+		let expr = dice.parse("997d6").unwrap();
+		assert!(dice.eval_expr(&expr).is_ok());
+		let expr = dice.parse("998d6").unwrap();
+		assert!(dice.eval_expr(&expr).unwrap_err().is_step_limit());
+		dice.set_step_limit(None);
+		assert!(dice.eval_expr(&expr).is_ok());
+		// only the step limit itself counts, not an error that happens to have the same message
+		dice.register_function("fail", |_| Err(SyntaxError::from("Evaluation took more than 5 steps"))).unwrap();
+		assert!(!dice.eval("fail(1)").unwrap_err().is_step_limit());
+	}
+
+	#[test]
+	fn shared_checks() {
+		use crate::{DiceBag, FixedRolls, SharedDiceBag, simple_rng};
+		fn assert_send_sync<T: Send + Sync>(_: &T) {}
+		let dice = SharedDiceBag::new(simple_rng(42));
+		assert_send_sync(&dice);
+		let threads: Vec<_> = (0..8).map(|_| {
+			let dice = dice.clone();
+			std::thread::spawn(move || (0..100).map(|_| dice.eval_total("3d6").unwrap()).collect::<Vec<i64>>())
+		}).collect();
+		for thread in threads {
+			assert!(thread.join().unwrap().iter().all(|total| (3..=18).contains(total)));
+		}
+		// every clone rolls with the same dice bag
+		let dice = SharedDiceBag::new(FixedRolls::new([4, 2, 6]));
+		let other = dice.clone();
+		assert_eq!(dice.eval_total("1d6").unwrap(), 4);
+		assert_eq!(other.eval("1d6+1").unwrap().total, 3);
+		assert_eq!(dice.with(|dice| dice.eval_total("1d6")).unwrap(), 6);
+		assert!(other.eval_total("1d6").is_err());
+		// custom functions are kept, and can be registered on the shared dice bag
+		let mut bag = DiceBag::new(simple_rng(42));
+		bag.register_function("double", |args| Ok(args.iter().sum::<i64>() * 2)).unwrap();
+		let dice = SharedDiceBag::from(bag);
+		dice.with(|dice| dice.register_function("triple", |args| Ok(args.iter().sum::<i64>() * 3))).unwrap();
+		assert_eq!(dice.eval_total("double(2) + triple(3)").unwrap(), 13);
+		let expr = dice.parse("1d20+5").unwrap();
+		assert_eq!(dice.eval_expr(&expr).unwrap().max, 25);
+		assert_eq!(dice.eval_detailed("2d6").unwrap().dice.len(), 1);
+		assert!(dice.eval("1d6+").is_err());
+	}
+
+	#[test]
+	fn introspection_checks() {
+		use crate::{DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		let expr = dice.parse("8d6+4d4+2").unwrap();
+		assert_eq!((expr.dice_count().unwrap(), expr.max_die_size().unwrap(), expr.has_dice()), (12, 6, true));
+		let expr = dice.parse("(1d4)d6 + {1d20, 1d12}kh1").unwrap();
+		assert_eq!((expr.dice_count().unwrap(), expr.max_die_size().unwrap()), (7, 20));
+		let expr = dice.parse("1d20 >= 15 ? 2d6[fire] : 4d4kh(1d2)").unwrap();
+		assert_eq!((expr.dice_count().unwrap(), expr.max_die_size().unwrap()), (8, 20));
+		let expr = dice.parse("3*(2+5)").unwrap();
+		assert_eq!((expr.dice_count().unwrap(), expr.max_die_size().unwrap(), expr.has_dice()), (0, 0, false));
+		assert!(expr.variables().is_empty());
+		assert_eq!(dice.eval_expr(&expr).unwrap().total, 21);
+		let expr = dice.parse("atk >= 16 ? dmg + atk : 0").unwrap();
+		assert_eq!(expr.variables(), ["atk", "dmg"]);
+		assert!(!expr.has_dice());
+		assert!(dice.eval_expr(&expr).is_err());
+		let expr = dice.parse("(n)d6").unwrap();
+		assert!(expr.has_dice() && expr.dice_count().is_err());
+		dice.register_modifier("s", |rolls, _, _| Ok(rolls.len() as i64)).unwrap();
+		let expr = dice.parse("6d10s8").unwrap();
+		assert_eq!((expr.dice_count().unwrap(), expr.max_die_size().unwrap()), (6, 10));
+		assert!(dice.parse("1d6+").is_err());
+		let expr = dice.parse("2d6+3").unwrap();
+		for _ in 0..100 {
+			assert!((5..=15).contains(&dice.eval_expr(&expr).unwrap().total));
+		}
+	}
+
+	#[test]
+	fn partial_eval_checks() {
+		use std::collections::HashMap;
+		use crate::{DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		let stats = HashMap::from([("prof", 4), ("str_mod", 5)]);
+		let expr = dice.parse("1d20 + prof + str_mod").unwrap().partial_eval(&stats).unwrap();
+		assert_eq!(expr.to_string(), "1d20+9");
+		assert!(expr.variables().is_empty());
+		assert_eq!(dice.eval_expr(&expr).unwrap().min, 10);
+		let expr = dice.parse("1d20+prof >= ac ? 2d6+str_mod : 0").unwrap().partial_eval(&stats).unwrap();
+		assert_eq!(expr.to_string(), "1d20+4 >= ac ? 2d6+5 : 0");
+		assert_eq!(expr.variables(), ["ac"]);
+		assert_eq!(dice.parse("2d6").unwrap().partial_eval(&HashMap::<String, i64>::new()).unwrap().to_string(), "2d6");
+		assert!(dice.parse("1d6+10/(prof-4)").unwrap().partial_eval(&stats).is_err());
+	}
+
+	#[test]
+	fn notation_checks() {
+		use crate::{DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		dice.register_function("max", |args| Ok(args.iter().copied().max().unwrap_or(0))).unwrap();
+		let render = |text: &str| {
+			let expr = dice.parse(text).unwrap();
+			(expr.to_latex(), expr.to_unicode())
+		};
+		assert_eq!(render("2d6/2+3"), ("\\frac{2\\mathrm{d}6}{2} + 3".into(), "2d6 \u{F7} 2 + 3".into()));
+		assert_eq!(render("3*(1d8-1)"), ("3 \\cdot \\left(1\\mathrm{d}8 - 1\\right)".into(), "3 \u{22C5} (1d8 \u{2212} 1)".into()));
+		assert_eq!(render("(2+1d4)/(3-1)").0, "\\frac{2 + 1\\mathrm{d}4}{3 - 1}");
+		assert_eq!(render("1d20+str_mod >= 15 ? 2d6[fire] : 0"), (
+			"1\\mathrm{d}20 + \\mathit{str\\_mod} \\geq 15 \\mathrel{?} 2\\mathrm{d}6\\,[\\text{fire}] : 0".into(),
+			"1d20 + str_mod \u{2265} 15 ? 2d6 [fire] : 0".into()
+		));
+		assert_eq!(render("{4d6kh3, -2} != 1"), (
+			"\\{4\\mathrm{d}6\\mathrm{kh}3, -2\\} \\neq 1".into(), "{4d6kh3, \u{2212}2} \u{2260} 1".into()
+		));
+		assert_eq!(render("max(1d20, 1d20) <= 5"), (
+			"\\operatorname{max}\\left(1\\mathrm{d}20, 1\\mathrm{d}20\\right) \\leq 5".into(), "max(1d20, 1d20) \u{2264} 5".into()
+		));
+		assert_eq!(render("2d6[100% fire]").0, "2\\mathrm{d}6\\,[\\text{100\\% fire}]");
+	}
+
+	#[test]
+	fn verify_checks() {
+		use crate::{DiceBag, DiceRoll, simple_rng, verify_roll};
+		for seed in [0, 7, 1234, u64::MAX] {
+			let roll = DiceBag::new(simple_rng(seed)).eval("4d6kh3+2").unwrap();
+			assert!(verify_roll(seed, "4d6kh3+2", &roll));
+			assert!(!verify_roll(seed, "4d6kh3+2", &DiceRoll{total: roll.total + 1, ..roll}));
+			assert!(!verify_roll(seed, "4d6kh3+3", &roll));
+		}
+		// only the first roll made with the seed can be verified
+		let mut dice = DiceBag::new(simple_rng(99));
+		let first = dice.eval("1d1000").unwrap();
+		let second = dice.eval("1d1000").unwrap();
+		assert!(verify_roll(99, "1d1000", &first));
+		assert!(first == second || !verify_roll(99, "1d1000", &second));
+		assert!(!verify_roll(99, "1d6+", &first));
+		// the dice are checked too, even when the total is right
+		let roll = DiceBag::new(simple_rng(42)).eval_detailed("2d20kh1").unwrap();
+		assert!(crate::verify_detailed(42, "2d20kh1", &roll));
+		let mut swapped = roll.clone();
+		swapped.dice[0].rolls.reverse();
+		assert_eq!(roll.dice[0].rolls[0] == roll.dice[0].rolls[1], crate::verify_detailed(42, "2d20kh1", &swapped));
+	}
+
+	#[test]
+	fn floor_average_checks() {
+		use crate::{DiceBag, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		assert_eq!(dice.eval("2d8+2").unwrap().floor_average(), 11);
+		assert_eq!(dice.eval("1d8").unwrap().floor_average(), 4);
+		assert_eq!(dice.eval("2d6").unwrap().floor_average(), 7);
+		assert_eq!(dice.eval("-1d4").unwrap().floor_average(), -3);
+		assert_eq!(dice.eval("6d6/3").unwrap().floor_average(), 7);
+	}
+
+	#[test]
+	fn opposed_checks() {
+		use crate::{DiceBag, DiceRoll, OpposedRoll, TieBreak, Winner, simple_rng};
+		let roll = |total| DiceRoll{total, min: 1, max: 20, average: 10.5};
+		let opposed = OpposedRoll::new(roll(12), roll(9), TieBreak::Tie);
+		assert_eq!((opposed.winner, opposed.margin), (Winner::A, 3));
+		let opposed = OpposedRoll::new(roll(2), roll(9), TieBreak::FavorA);
+		assert_eq!((opposed.winner, opposed.margin), (Winner::B, -7));
+		assert_eq!(OpposedRoll::new(roll(9), roll(9), TieBreak::Tie).winner, Winner::Tie);
+		assert_eq!(OpposedRoll::new(roll(9), roll(9), TieBreak::FavorA).winner, Winner::A);
+		assert_eq!(OpposedRoll::new(roll(9), roll(9), TieBreak::FavorB).winner, Winner::B);
+		let mut dice = DiceBag::new(simple_rng(42));
+		assert_eq!(dice.opposed("1d20+30", "1d20").unwrap().winner, Winner::A);
+		assert_eq!(dice.opposed("5", "5").unwrap().winner, Winner::Tie);
+		assert_eq!(dice.opposed_with("5", "5", TieBreak::Reroll).unwrap().winner, Winner::Tie);
+		for _ in 0..100 {
+			let opposed = dice.opposed_with("1d2", "1d2", TieBreak::Reroll).unwrap();
+			assert_ne!(opposed.winner, Winner::Tie);
+			assert_eq!(opposed.margin, opposed.a.total - opposed.b.total);
+		}
+		assert!(dice.opposed("1d20", "1d20+").is_err());
+	}
+
+	#[test]
+	fn distribution_checks() {
+		use crate::{DiceBag, Distribution, simple_rng};
+		let dist = Distribution::from_samples([1, 2, 2, 3]);
+		assert_eq!(dist.probability(2), 0.5);
+		assert_eq!(dist.probability(4), 0.);
+		assert_eq!((dist.min(), dist.max()), (Some(1), Some(3)));
+		assert_eq!(dist.mean(), 2.);
+		assert_eq!(dist.std_dev(), 0.5f64.sqrt());
+		assert_eq!((dist.median(), dist.mode()), (Some(2), Some(2)));
+		assert_eq!(dist.prob_at_least(2), 0.75);
+		assert_eq!(dist.prob_at_least(4), 0.);
+		let dist = Distribution::from_samples([1, 1, 2, 5, 5, 6]);
+		assert_eq!((dist.median(), dist.mode()), (Some(2), Some(1)));
+		assert_eq!(dist.modes(), [1, 5]);
+		assert_eq!(dist.to_points(), [(1, 1. / 3.), (2, 1. / 6.), (5, 1. / 3.), (6, 1. / 6.)]);
+		assert_eq!(Distribution::from_samples([3, 1, 1, 3]).to_csv(), "result,probability\n1,0.5\n3,0.5\n");
+		assert_eq!(Distribution::default().to_csv(), "result,probability\n");
+		assert!(Distribution::default().modes().is_empty());
+		let other = Distribution::from_samples([2, 4]);
+		assert!((dist.prob_greater_than(&other) - 0.5).abs() < 1e-9);
+		assert!((other.prob_greater_than(&dist) - 5. / 12.).abs() < 1e-9);
+		let mut dice = DiceBag::new(simple_rng(42));
+		let dist = dice.sample_distribution("2d6", 10000).unwrap();
+		assert_eq!((dist.min(), dist.max()), (Some(2), Some(12)));
+		assert!((dist.mean() - 7.).abs() < 0.2);
+		assert!((dist.iter().map(|(_, p)| p).sum::<f64>() - 1.).abs() < 1e-9);
+		let mut totals = [0i64; 1000];
+		dice.sample_totals("4d6kh3", &mut totals).unwrap();
+		assert!(totals.iter().all(|t| (3..=18).contains(t)));
+		assert!(totals.windows(2).any(|w| w[0] != w[1]));
+		assert!(dice.sample_totals("1d6+", &mut totals).is_err());
+		dice.sample_totals("1d6", &mut []).unwrap();
+		// exact distributions
+		let dist = dice.distribution("3d6").unwrap();
+		assert!((dist.probability(10) - 27. / 216.).abs() < 1e-9);
+		assert_eq!((dist.median(), dist.mode(), dist.modes()), (Some(10), Some(10), vec![10, 11]));
+		let dist = dice.distribution("4d6kh3").unwrap();
+		assert!((dist.mean() - 15869. / 1296.).abs() < 1e-9);
+		assert_eq!((dist.median(), dist.mode(), dist.modes()), (Some(12), Some(13), vec![13]));
+		assert_eq!(dice.distribution("2d20kl1").unwrap().modes(), [1]);
+		assert_eq!(dice.distribution("5").unwrap().modes(), [5]);
+		assert_eq!(dice.exact_distribution("3d6").unwrap(), Some(dice.distribution("3d6").unwrap()));
+		// too many combinations to work out exactly, so sampled
+		assert_eq!(dice.exact_distribution("200d1000").unwrap(), None);
+		let dist = dice.distribution("200d1000").unwrap();
+		assert!((dist.mean() - 100100.).abs() < 1000.);
+		assert!(dice.distribution("1d6+").is_err());
+	}
+
+	#[test]
+	fn plugin_checks() {
+		use crate::{DiceBag, FixedRolls, SyntaxError};
+		let mut dice = DiceBag::new(FixedRolls::new([9, 3, 10, 8, 1, 7, 15, 4]));
+		// count the dice that roll the target number or more, where a 10 counts twice
+		dice.register_modifier("s", |rolls, sides, target| {
+			let target = target.ok_or_else(|| SyntaxError::from("Expected a target number after 's'"))?;
+			Ok(rolls.iter().map(|r| if *r == sides {2} else {(*r as i64 >= target) as i64}).sum())
+		}).unwrap();
+		dice.register_function("max", |args| args.iter().copied().max()
+			.ok_or_else(|| SyntaxError::from("max() needs at least one number"))).unwrap();
+		let roll = dice.eval_detailed("5d10s8").unwrap();
+		assert_eq!(roll.roll.total, 4);
+		assert_eq!((roll.roll.min, roll.roll.max), (0, 10));
+		assert_eq!(roll.dice[0].rolls, [9, 3, 10, 8, 1]);
+		assert!(dice.eval("2d10s").is_err());
+		let roll = dice.eval("max(1d20, 1d20) + 1").unwrap();
+		assert_eq!((roll.total, roll.min, roll.max), (16, 2, 21));
+		assert!(dice.eval("max()").unwrap_err().to_string().contains("needs at least one number"));
+		assert!(dice.register_function("max", |_| Ok(0)).is_err());
+		assert!(dice.register_function("kh", |_| Ok(0)).is_err());
+		assert!(dice.register_function("2x", |_| Ok(0)).is_err());
+		assert!(dice.register_modifier("s2", |_, _, _| Ok(0)).is_err());
+		dice.register_function("max2", |_| Ok(0)).unwrap();
+		// registered plugins are part of the dice bag
+		assert!(dice.clone() == dice);
+		assert!(DiceBag::new(FixedRolls::new([1])).eval("max(1)").is_err());
+	}
+
+	#[test]
+	fn roller_checks() {
+		use crate::{DiceBag, FixedRolls, FnRoller, Outcome};
+		// a die that rolls its highest result, then 1, then 2, and so on
+		let mut next = 0;
+		let mut dice = DiceBag::new(FnRoller::new(|sides| {
+			next += 1;
+			if next == 1 {sides} else {next - 1}
+		}));
+		let roll = dice.eval_detailed("1d20+4d6kh3").unwrap();
+		assert_eq!(roll.dice[0].rolls, [20]);
+		assert_eq!(roll.dice[1].rolls, [1, 2, 3, 4]);
+		assert_eq!(roll.roll.total, 29);
+		assert_eq!(dice.roll(2, 6, 1), 12);
+		// results out of range are clamped
+		let mut dice = DiceBag::new(FnRoller::new(|_| 0));
+		assert_eq!(dice.eval_total("2d6").unwrap(), 2);
+		let mut dice = DiceBag::new(FnRoller::new(|sides| sides * 2));
+		assert_eq!(dice.eval_total("2d6").unwrap(), 12);
+		let mut dice = DiceBag::new(FixedRolls::new([17, 1, 6, 6, 9]));
+		assert_eq!(dice.eval_vs("1d20+2", 19).unwrap().outcome, Outcome::Success);
+		assert_eq!(dice.eval_total("3d6kl2").unwrap(), 7);
+		assert_eq!(dice.rng.remaining(), 1);
+		assert!(dice.eval_total("1d8").unwrap_err().to_string().contains("Fixed roll 9 is not possible on a d8"));
+		assert_eq!(dice.eval_total("1d10").unwrap(), 9);
+		assert!(dice.eval_total("1d10").unwrap_err().to_string().contains("Ran out of fixed rolls (all 5 were used)"));
+		let mut dice = DiceBag::new(FixedRolls::new([3, 4, 5]));
+		assert_eq!(dice.try_roll(2, 6, 1).unwrap(), 8);
+		assert!(dice.try_roll(2, 6, 0).unwrap_err().to_string().contains("Ran out of fixed rolls (all 3 were used)"));
+		assert!(DiceBag::new(FixedRolls::new([1])).try_roll(1, 6, i64::MAX).is_err());
+	}
+
+	#[test]
+	#[cfg(feature = "i18n")]
+	fn i18n_checks() {
+		use crate::{DiceBag, Locale, simple_rng};
+		let mut dice = DiceBag::new(simple_rng(42));
+		let err = dice.eval("1d6+\n2.5").unwrap_err();
+		assert_eq!(err.localized(Locale::English), err.to_string());
+		assert_eq!(err.localized(Locale::Spanish), "SyntaxError: Se encontró '.', pero no se admiten números decimales \
+			(solo números enteros); error en la línea 2, columna 2");
+		let err = dice.eval("0d0").unwrap_err();
+		assert_eq!(err.localized(Locale::German), "SyntaxError: Würfel mit 0 Seiten können nicht geworfen werden");
+		let err = dice.eval("1d20 = 5").unwrap_err();
+		assert!(err.localized(Locale::French).starts_with("SyntaxError: '=' inattendu (utilisez =="));
+		assert_eq!(Locale::Spanish.translate("Unexpected ')'"), "')' inesperado");
+		assert_eq!(Locale::French.translate("Something else"), "Something else");
+		assert_eq!(Locale::German.translate("Fixed roll 9 is not possible on a d8"), "Der festgelegte Wurf 9 ist auf einem W8 nicht möglich");
+		assert_eq!(Locale::German.translate("No value for placeholders {a}, {b}"), "Keine Werte für Platzhalter {a}, {b}");
+		assert_eq!((Locale::from_tag("fr-CA"), Locale::from_tag("de_DE.UTF-8")), (Some(Locale::French), Some(Locale::German)));
+		assert_eq!((Locale::from_tag("ES"), Locale::from_tag("ja")), (Some(Locale::Spanish), None));
+	}
+
+	#[test]
+	#[cfg(feature = "tracing")]
+	fn tracing_checks() {
+		use std::sync::{Arc, Mutex};
+		use tracing::{Event, Metadata, span, subscriber::Subscriber};
+		use crate::{DiceBag, simple_rng};
+		/// Collects the name of the span that each event was emitted in
+		struct Collector(Arc<Mutex<Vec<String>>>, Mutex<Vec<&'static str>>);
+		impl Subscriber for Collector {
+			fn enabled(&self, _: &Metadata<'_>) -> bool {true}
+			fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+				let mut names = self.1.lock().unwrap();
+				names.push(span.metadata().name());
+				span::Id::from_u64(names.len() as u64)
+			}
+			fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+			fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+			fn event(&self, event: &Event<'_>) {
+				let span = self.1.lock().unwrap().last().copied().unwrap_or("");
+				self.0.lock().unwrap().push(format!("{}: {}", span, event.metadata().level()));
+			}
+			fn enter(&self, _: &span::Id) {}
+			fn exit(&self, _: &span::Id) {}
+		}
+		let events = Arc::new(Mutex::new(Vec::new()));
+		tracing::subscriber::with_default(Collector(events.clone(), Mutex::new(Vec::new())), || {
+			DiceBag::new(simple_rng(42)).eval_total("3d6+2").unwrap();
+		});
+		let events = events.lock().unwrap();
+		// parsing and parsed, 3 dice, and 1 operator
+		assert_eq!(events.len(), 6);
+		assert!(events.iter().all(|event| event.starts_with("eval_total: ")));
+		assert_eq!(events.iter().filter(|event| event.ends_with("TRACE")).count(), 4);
 	}
 
 	#[test]
@@ -528,7 +1816,7 @@ mod unit_tests {
 		use crate::{DiceBag, DiceRoll, simple_rng};
 		let mut dice = DiceBag::new(simple_rng(42));
 		let roll = dice.eval("2d10+6").unwrap();
-		let roll2 = roll.clone();
+		let roll2 = roll;
 		let json_str = serde_json::to_string(&roll2).unwrap();
 		let serde_roll: DiceRoll = serde_json::from_str(json_str.as_str()).unwrap();
 		assert_eq!(roll, serde_roll);
@@ -0,0 +1,171 @@
+use std::borrow::Cow;
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+use crate::SyntaxError;
+
+/// A language that error messages can be shown in (see `SyntaxError.localized(...)`)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum Locale {
+	#[default]
+	English,
+	Spanish,
+	French,
+	German
+}
+
+/// Every error message in each language (in the same order as the `Locale` variants), where "{}"
+/// stands for the part of the message that changes (eg the number of dice). When one message
+/// starts like another, the longer one goes first.
+const MESSAGES: &[[&str; 4]] = &[
+	["Cannot drop {} dice", "No se pueden descartar {} dados", "Impossible d'écarter {} dés",
+		"Es können keine {} Würfel entfernt werden"],
+	["Cannot keep {} dice", "No se pueden conservar {} dados", "Impossible de garder {} dés",
+		"Es können keine {} Würfel behalten werden"],
+	["Cannot roll dice with {} sides", "No se pueden tirar dados de {} caras", "Impossible de lancer des dés à {} faces",
+		"Würfel mit {} Seiten können nicht geworfen werden"],
+	["Cannot roll {} dice", "No se pueden tirar {} dados", "Impossible de lancer {} dés",
+		"Es können keine {} Würfel geworfen werden"],
+	["Division by zero", "División por cero", "Division par zéro", "Division durch null"],
+	["Evaluation took more than {} steps", "La evaluación tomó más de {} pasos", "L'évaluation a pris plus de {} étapes",
+		"Die Auswertung hat mehr als {} Schritte gebraucht"],
+	["Expected a number or '('", "Se esperaba un número o '('", "Nombre ou '(' attendu", "Zahl oder '(' erwartet"],
+	["Expected ')' to match '('", "Se esperaba ')' para cerrar '('", "')' attendu pour fermer '('",
+		"')' passend zu '(' erwartet"],
+	["Expected ':' after the '?' of a conditional expression", "Se esperaba ':' después del '?' de una expresión condicional",
+		"':' attendu après le '?' d'une expression conditionnelle", "':' nach dem '?' eines bedingten Ausdrucks erwartet"],
+	["Expected '}' to match '{'", "Se esperaba '}' para cerrar '{'", "'}' attendu pour fermer '{'",
+		"'}' passend zu '{' erwartet"],
+	["Found '.', but decimal numbers are not supported (integer math only)",
+		"Se encontró '.', pero no se admiten números decimales (solo números enteros)",
+		"'.' trouvé, mais les nombres décimaux ne sont pas pris en charge (nombres entiers uniquement)",
+		"'.' gefunden, aber Dezimalzahlen werden nicht unterstützt (nur Ganzzahlen)"],
+	["Found '[' without matching ']'", "Se encontró '[' sin su ']' correspondiente", "'[' trouvé sans ']' correspondant",
+		"'[' ohne passende ']' gefunden"],
+	["Integer overflow (result is too big)", "Desbordamiento de enteros (el resultado es demasiado grande)",
+		"Dépassement d'entier (résultat trop grand)", "Ganzzahlüberlauf (Ergebnis ist zu groß)"],
+	["Number {} is too big", "El número {} es demasiado grande", "Le nombre {} est trop grand", "Die Zahl {} ist zu groß"],
+	["Script has no dice expressions", "El script no tiene expresiones de dados",
+		"Le script ne contient aucune expression de dés", "Das Skript enthält keine Würfelausdrücke"],
+	["Too many possible results to work them out exactly", "Hay demasiados resultados posibles para calcularlos con exactitud",
+		"Trop de résultats possibles pour les calculer exactement", "Zu viele mögliche Ergebnisse, um sie exakt zu berechnen"],
+	["Missing number of dice before 'd'", "Falta el número de dados antes de 'd'", "Nombre de dés manquant avant 'd'",
+		"Anzahl der Würfel vor 'd' fehlt"],
+	["Unexpected '{}' (use == or != to compare numbers)", "'{}' inesperado (usa == o != para comparar números)",
+		"'{}' inattendu (utilisez == ou != pour comparer des nombres)",
+		"Unerwartetes '{}' (verwende == oder != um Zahlen zu vergleichen)"],
+	["Unexpected character '{}'", "Carácter inesperado '{}'", "Caractère inattendu '{}'", "Unerwartetes Zeichen '{}'"],
+	["Unexpected end of dice expression", "Fin inesperado de la expresión de dados",
+		"Fin inattendue de l'expression de dés", "Unerwartetes Ende des Würfelausdrucks"],
+	["Unexpected '{}'", "'{}' inesperado", "'{}' inattendu", "Unerwartetes '{}'"],
+	["Unknown variable '{}'", "Variable desconocida '{}'", "Variable inconnue '{}'", "Unbekannte Variable '{}'"],
+	["No value for placeholders {}", "Faltan los valores de los marcadores {}",
+		"Aucune valeur pour les espaces réservés {}", "Keine Werte für Platzhalter {}"],
+	["No value for placeholder {}", "Falta el valor del marcador {}", "Aucune valeur pour l'espace réservé {}",
+		"Kein Wert für Platzhalter {}"],
+	["Failed to parse string as integer", "No se pudo leer el texto como número entero",
+		"Impossible de lire le texte comme nombre entier", "Text konnte nicht als Ganzzahl gelesen werden"],
+	["Failed to parse string as decimal number", "No se pudo leer el texto como número decimal",
+		"Impossible de lire le texte comme nombre décimal", "Text konnte nicht als Dezimalzahl gelesen werden"],
+	["Failed to parse string", "No se pudo leer el texto", "Impossible de lire le texte", "Text konnte nicht gelesen werden"],
+	["Ran out of fixed rolls (all {} were used)", "Se acabaron las tiradas fijas (se usaron las {})",
+		"Plus de jets fixés (les {} ont été utilisés)", "Keine festgelegten Würfe mehr (alle {} wurden verwendet)"],
+	["Fixed roll {} is not possible on a d{}", "La tirada fija {} no es posible en un d{}",
+		"Le jet fixé {} est impossible sur un d{}", "Der festgelegte Wurf {} ist auf einem W{} nicht möglich"],
+	["'{}' cannot be the name of a custom dice modifier", "'{}' no puede ser el nombre de un modificador de dados personalizado",
+		"'{}' ne peut pas être le nom d'un modificateur de dés personnalisé",
+		"'{}' kann nicht der Name eines eigenen Würfelmodifikators sein"],
+	["'{}' cannot be the name of a custom function", "'{}' no puede ser el nombre de una función personalizada",
+		"'{}' ne peut pas être le nom d'une fonction personnalisée", "'{}' kann nicht der Name einer eigenen Funktion sein"],
+	["'{}' is already registered", "'{}' ya está registrado", "'{}' est déjà enregistré", "'{}' ist bereits registriert"],
+	["Cannot use the '{}' modifier together with a keep or drop modifier",
+		"No se puede usar el modificador '{}' junto con un modificador para conservar o descartar dados",
+		"Impossible d'utiliser le modificateur '{}' avec un modificateur pour garder ou écarter des dés",
+		"Der Modifikator '{}' kann nicht zusammen mit einem Modifikator zum Behalten oder Entfernen von Würfeln verwendet werden"],
+	["Too many arguments to work out the range of a custom function",
+		"Demasiados argumentos para calcular el rango de una función personalizada",
+		"Trop d'arguments pour calculer l'étendue d'une fonction personnalisée",
+		"Zu viele Argumente, um den Wertebereich einer eigenen Funktion zu berechnen"],
+	["Expected '(' after the name of a function", "Se esperaba '(' después del nombre de una función",
+		"'(' attendu après le nom d'une fonction", "'(' nach dem Namen einer Funktion erwartet"],
+	["Expected ')' after the arguments of a function", "Se esperaba ')' después de los argumentos de una función",
+		"')' attendu après les arguments d'une fonction", "')' nach den Argumenten einer Funktion erwartet"],
+	["; error on line {}", "; error en la línea {}", "; erreur à la ligne {}", "; Fehler in Zeile {}"],
+	[", column {}", ", columna {}", ", colonne {}", ", Spalte {}"],
+	["Caused by: {}", "Causado por: {}", "Causé par : {}", "Verursacht durch: {}"]
+];
+
+impl Locale {
+	/// Picks the language for a language tag (eg "es", "fr-CA", or "de_DE"), or returns `None` if
+	/// error messages are not available in that language
+	/// # Parameters
+	/// * `tag`: an IETF language tag or POSIX locale name
+	pub fn from_tag(tag: &str) -> Option<Locale> {
+		let language = tag.split(['-', '_', '.']).next().unwrap_or("");
+		match language.to_ascii_lowercase().as_str() {
+			"en" => Some(Locale::English),
+			"es" => Some(Locale::Spanish),
+			"fr" => Some(Locale::French),
+			"de" => Some(Locale::German),
+			_ => None
+		}
+	}
+
+	/// Translates an error message from English, or returns it unchanged if it is not one of the
+	/// library's messages
+	/// # Parameters
+	/// * `msg`: the English error message
+	pub fn translate<'a>(&self, msg: &'a str) -> Cow<'a, str> {
+		let column = *self as usize;
+		for row in MESSAGES {
+			if let Some(values) = match_message(row[0], msg) {
+				let mut translated = row[column].to_string();
+				for value in values {
+					translated = translated.replacen("{}", value, 1);
+				}
+				return Cow::Owned(translated);
+			}
+		}
+		Cow::Borrowed(msg)
+	}
+}
+
+/// Matches a message against a message pattern from `MESSAGES`, returning the part of the message
+/// that stands in for each "{}" of the pattern, or `None` if the message does not match
+fn match_message<'a>(pattern: &str, msg: &'a str) -> Option<Vec<&'a str>> {
+	let mut parts = pattern.split("{}");
+	let mut rest = msg.strip_prefix(parts.next().unwrap_or(""))?;
+	let mut values = Vec::new();
+	let mut parts = parts.peekable();
+	while let Some(part) = parts.next() {
+		let end = match parts.peek() {
+			// the last part of the pattern must end the message
+			None => rest.strip_suffix(part).map(|value| value.len())?,
+			Some(_) => rest.find(part)?
+		};
+		values.push(&rest[..end]);
+		rest = &rest[end + part.len()..];
+	}
+	Some(values).filter(|_| rest.is_empty())
+}
+
+impl SyntaxError {
+	/// Returns the error message in the given language, in the same format as the English
+	/// message printed by `Display`
+	/// # Parameters
+	/// * `locale`: the language to show the message in
+	pub fn localized(&self, locale: Locale) -> String {
+		let mut text = format!("SyntaxError: {}", locale.translate(self.msg.as_deref().unwrap_or("Failed to parse string")));
+		if let Some(line) = self.line {
+			text.push_str(&locale.translate(&format!("; error on line {}", line)));
+			if let Some(col) = self.col {
+				text.push_str(&locale.translate(&format!(", column {}", col)));
+			}
+		}
+		if let Some(cause) = &self.cause {
+			text.push_str("\n\t");
+			text.push_str(&locale.translate(&format!("Caused by: {}", cause)));
+		}
+		text
+	}
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+use crate::SyntaxError;
+
+/// A custom function, which takes the results of its arguments and returns its result
+pub(crate) type FunctionFn = dyn Fn(&[i64]) -> Result<i64, SyntaxError> + Send + Sync;
+
+/// A custom dice modifier, which takes the dice rolled (in the order that they were rolled), the
+/// number of sides of the dice, and the modifier's argument (if any), and returns the result
+pub(crate) type ModifierFn = dyn Fn(&[u32], u32, Option<i64>) -> Result<i64, SyntaxError> + Send + Sync;
+
+/// A registered callback, which is only equal to clones of itself
+pub(crate) struct Callback<F: ?Sized>(pub Arc<F>);
+
+impl<F: ?Sized> Clone for Callback<F> {
+	fn clone(&self) -> Self {
+		Callback(self.0.clone())
+	}
+}
+
+impl<F: ?Sized> PartialEq for Callback<F> {
+	fn eq(&self, other: &Self) -> bool {
+		Arc::ptr_eq(&self.0, &other.0)
+	}
+}
+
+impl<F: ?Sized> core::fmt::Debug for Callback<F> {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "Callback")
+	}
+}
+
+/// The custom functions (eg "max(1d20, 1d20)") and dice modifiers (eg the "s8" of "6d10s8")
+/// registered with a `DiceBag`
+#[derive(Clone, PartialEq, Default, Debug)]
+pub(crate) struct Plugins {
+	pub functions: Vec<(String, Callback<FunctionFn>)>,
+	pub modifiers: Vec<(String, Callback<ModifierFn>)>
+}
+
+impl Plugins {
+	/// Returns the position of the named function, if there is one
+	pub fn function(&self, name: &str) -> Option<usize> {
+		self.functions.iter().position(|(function, _)| function == name)
+	}
+
+	/// Returns the position of the named dice modifier, if there is one
+	pub fn modifier(&self, name: &str) -> Option<usize> {
+		self.modifiers.iter().position(|(modifier, _)| modifier == name)
+	}
+
+	/// Checks that the name can be given to a new function (`letters` is false) or dice modifier
+	/// (`letters` is true, since a modifier is followed by its argument, eg "s8")
+	pub fn check_name(&self, name: &str, letters: bool) -> Result<(), SyntaxError> {
+		let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+			&& name.chars().all(|c| c.is_ascii_alphabetic() || c == '_' || (!letters && c.is_ascii_digit()))
+			&& !crate::expression::is_keyword(name);
+		if !valid {
+			return Err(SyntaxError::from_string(format!("'{}' cannot be the name of a custom {}", name,
+				if letters {"dice modifier"} else {"function"})));
+		}
+		if self.function(name).is_some() || self.modifier(name).is_some() {
+			return Err(SyntaxError::from_string(format!("'{}' is already registered", name)));
+		}
+		Ok(())
+	}
+}
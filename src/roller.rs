@@ -0,0 +1,102 @@
+use core::fmt::Formatter;
+use crate::SyntaxError;
+
+/// A source of die rolls for a `DiceBag`. Every random number generator from the
+/// [rand crate](https://crates.io/crates/rand) is a `DieRoller`, and `FnRoller` turns any closure
+/// into one (eg to use rolls from an external randomness service).
+pub trait DieRoller {
+	/// Rolls one die, returning a result from 1 to `sides` (inclusive), or an error if no die
+	/// can be rolled
+	/// # Parameters
+	/// * `sides`: the number of sides of the die (at least 1)
+	fn roll_die(&mut self, sides: u32) -> Result<u32, SyntaxError>;
+}
+
+impl<R> DieRoller for R where R: rand::Rng + ?Sized {
+	fn roll_die(&mut self, sides: u32) -> Result<u32, SyntaxError> {
+		Ok(self.gen_range(1..=sides))
+	}
+}
+
+/// Adapts a closure that takes the number of sides of a die and returns the result into a
+/// `DieRoller`, so that the rolls can come from anywhere (eg random.org or the authoritative rolls
+/// of a game server). Results outside of 1 to the number of sides are clamped into that range.
+///
+/// # Example
+/// ```
+/// use dicexp::{DiceBag, FnRoller};
+/// // a very unlucky die that always rolls 1
+/// let mut dice_bag = DiceBag::new(FnRoller::new(|_sides| 1));
+/// assert_eq!(dice_bag.eval_total("3d6+2").expect("Error"), 5);
+/// ```
+#[derive(Clone)]
+pub struct FnRoller<F> where F: FnMut(u32) -> u32 {
+	roll: F
+}
+
+impl<F> FnRoller<F> where F: FnMut(u32) -> u32 {
+	/// Constructs a new `FnRoller`
+	/// # Parameters
+	/// * `roll`: the closure to call for every die, which takes the number of sides of the die
+	///   and returns the result
+	pub fn new(roll: F) -> Self {
+		FnRoller{roll}
+	}
+}
+
+impl<F> DieRoller for FnRoller<F> where F: FnMut(u32) -> u32 {
+	fn roll_die(&mut self, sides: u32) -> Result<u32, SyntaxError> {
+		Ok((self.roll)(sides).clamp(1, sides))
+	}
+}
+
+impl<F> core::fmt::Debug for FnRoller<F> where F: FnMut(u32) -> u32 {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "FnRoller")
+	}
+}
+
+/// A `DieRoller` that rolls a predefined sequence of results, for testing game logic with known
+/// rolls (eg "the goblin rolls exactly 17"). Rolling fails once every result has been used, or if
+/// the next result is not possible on the die being rolled.
+///
+/// # Example
+/// ```
+/// use dicexp::{DiceBag, FixedRolls};
+/// let mut dice_bag = DiceBag::new(FixedRolls::new([17, 3, 5]));
+/// assert_eq!(dice_bag.eval_total("1d20").expect("Error"), 17);
+/// assert_eq!(dice_bag.eval_total("2d6+1").expect("Error"), 9);
+/// assert!(dice_bag.eval_total("1d4").is_err());
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct FixedRolls {
+	rolls: Vec<u32>,
+	next: usize
+}
+
+impl FixedRolls {
+	/// Constructs a new `FixedRolls`
+	/// # Parameters
+	/// * `rolls`: the result of each die to roll, in order
+	pub fn new<I>(rolls: I) -> Self where I: IntoIterator<Item=u32> {
+		FixedRolls{rolls: rolls.into_iter().collect(), next: 0}
+	}
+
+	/// Returns how many results have not been rolled yet
+	pub fn remaining(&self) -> usize {
+		self.rolls.len() - self.next
+	}
+}
+
+impl DieRoller for FixedRolls {
+	fn roll_die(&mut self, sides: u32) -> Result<u32, SyntaxError> {
+		let roll = *self.rolls.get(self.next).ok_or_else(|| SyntaxError::from_string(
+			format!("Ran out of fixed rolls (all {} were used)", self.rolls.len())
+		))?;
+		if roll == 0 || roll > sides {
+			return Err(SyntaxError::from_string(format!("Fixed roll {} is not possible on a d{}", roll, sides)));
+		}
+		self.next += 1;
+		Ok(roll)
+	}
+}
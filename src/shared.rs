@@ -0,0 +1,111 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use crate::{DetailedRoll, DiceBag, DiceExpr, DiceRoll, DieRoller, SyntaxError};
+
+/// A `DiceBag` that can be shared between threads (eg the request handlers of a web server or the
+/// event handlers of a chat bot). Cloning a `SharedDiceBag` is cheap and every clone rolls with
+/// the same `DiceBag`, which is locked for the duration of each roll, so there is no need to wrap
+/// it in `Arc<Mutex<...>>`. `SharedDiceBag` is `Send` and `Sync` if the RNG is `Send`.
+///
+/// # Example
+/// ```
+/// use dicexp::{SharedDiceBag, new_simple_rng};
+/// let dice_bag = SharedDiceBag::new(new_simple_rng());
+/// let handles: Vec<_> = (0..4).map(|_| {
+///     let dice_bag = dice_bag.clone();
+///     std::thread::spawn(move || dice_bag.eval_total("1d20+5").expect("Error"))
+/// }).collect();
+/// for handle in handles {
+///     assert!((6..=25).contains(&handle.join().unwrap()));
+/// }
+/// ```
+pub struct SharedDiceBag<R: DieRoller> {
+	dice_bag: Arc<Mutex<DiceBag<R>>>
+}
+
+impl<R> Clone for SharedDiceBag<R> where R: DieRoller {
+	/// Returns another handle to the same `DiceBag`
+	fn clone(&self) -> Self {
+		SharedDiceBag{dice_bag: self.dice_bag.clone()}
+	}
+}
+
+impl<R> core::fmt::Debug for SharedDiceBag<R> where R: DieRoller + core::fmt::Debug {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "SharedDiceBag{{{:?}}}", *self.lock())
+	}
+}
+
+impl<R> Default for SharedDiceBag<R> where R: DieRoller + Default {
+	fn default() -> Self {
+		SharedDiceBag::from(DiceBag::default())
+	}
+}
+
+impl<R> From<DiceBag<R>> for SharedDiceBag<R> where R: DieRoller {
+	/// Shares a `DiceBag`, keeping its custom functions and dice modifiers
+	fn from(dice_bag: DiceBag<R>) -> Self {
+		SharedDiceBag{dice_bag: Arc::new(Mutex::new(dice_bag))}
+	}
+}
+
+impl<R> SharedDiceBag<R> where R: DieRoller {
+	/// Constructs a new `SharedDiceBag` instance
+	/// # Parameters
+	/// * `rng`: A random number generator to use for rolling dice
+	pub fn new(rng: R) -> Self {
+		SharedDiceBag::from(DiceBag::new(rng))
+	}
+
+	/// Locks the `DiceBag`. A panic in another thread while it was locked cannot leave the
+	/// `DiceBag` in a bad state, so a poisoned lock is used anyway.
+	fn lock(&self) -> MutexGuard<'_, DiceBag<R>> {
+		self.dice_bag.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// Calls the given closure with the locked `DiceBag`, for anything that does not have a
+	/// method on `SharedDiceBag` (eg `DiceBag.eval_script(...)` or
+	/// `DiceBag.register_function(...)`). Other threads wait to roll until the closure returns.
+	/// # Parameters
+	/// * `f`: the closure to call
+	pub fn with<T, F>(&self, f: F) -> T where F: FnOnce(&mut DiceBag<R>) -> T {
+		f(&mut self.lock())
+	}
+
+	/// Evaluates the given RPG dice notation expression (see `DiceBag.eval(...)`)
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	pub fn eval(&self, dice_expression: &str) -> Result<DiceRoll,SyntaxError> {
+		self.lock().eval(dice_expression)
+	}
+
+	/// Evaluates the given RPG dice notation expression and returns the total dice roll (see
+	/// `DiceBag.eval_total(...)`)
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	pub fn eval_total(&self, dice_expression: &str) -> Result<i64,SyntaxError> {
+		self.lock().eval_total(dice_expression)
+	}
+
+	/// Evaluates the given RPG dice notation expression, returning the result along with all of
+	/// the individual dice that were rolled (see `DiceBag.eval_detailed(...)`)
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	pub fn eval_detailed(&self, dice_expression: &str) -> Result<DetailedRoll,SyntaxError> {
+		self.lock().eval_detailed(dice_expression)
+	}
+
+	/// Parses the given RPG dice notation expression without rolling it (see `DiceBag.parse(...)`)
+	/// # Parameters
+	/// * `dice_expression`: An RPG dice notation expressions (eg "2d6+3")
+	pub fn parse(&self, dice_expression: &str) -> Result<DiceExpr,SyntaxError> {
+		self.lock().parse(dice_expression)
+	}
+
+	/// Evaluates an expression that was parsed with `SharedDiceBag.parse(...)` or
+	/// `DiceBag.parse(...)`
+	/// # Parameters
+	/// * `dice_expression`: the parsed dice expression
+	pub fn eval_expr(&self, dice_expression: &DiceExpr) -> Result<DiceRoll,SyntaxError> {
+		self.lock().eval_expr(dice_expression)
+	}
+}